@@ -0,0 +1,39 @@
+//! Check calls with more arguments than a byte can count.
+//!
+//! Run with `cargo run --example call_args`.
+use ruscal::{run_source, Value};
+
+/// A function of `n` arguments, which reads the first, the middle and the last of them, called
+/// with the arguments `0..n`
+fn call_source(n: usize) -> String {
+    let params = (0..n)
+        .map(|i| format!("a{i}: i64"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let body = format!("a{} - a0 + a{}", n - 1, n / 2);
+    let args = (0..n)
+        .map(|i| format!("i64({i})"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("fn f({params}) -> i64 {{ {body} }} f({args})")
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // 256 arguments overflowed the 8 bit count of `Call`
+    for n in [255, 256, 300] {
+        let value = run_source(&call_source(n))?;
+        println!("Called with {n} arguments: {value}");
+        assert_eq!(value, Value::I64((n - 1 + n / 2) as i64));
+    }
+
+    // Beyond the 16 bit count, the call is a compile error instead of a wrong count
+    let args = vec!["1"; u16::MAX as usize + 1].join(", ");
+    let Err(e) = run_source(&format!("print({args})")) else {
+        return Err("A call with too many arguments should not compile".into());
+    };
+    println!("{e}");
+    assert!(e
+        .to_string()
+        .contains("Too many arguments in a call to print"));
+    Ok(())
+}
@@ -0,0 +1,44 @@
+//! Time a script dominated by function calls, and show that calls by name refer to the
+//! function by index instead of loading its name.
+//!
+//! Run with `cargo run --release --example call_dispatch`.
+use std::{io::Cursor, time::Instant};
+
+use ruscal::{
+    compiler::{compile, read_program},
+    run_source, Args, Value,
+};
+
+const SOURCE: &str = "examples/fibonacci.kscl";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args {
+        source: Some(SOURCE.to_string()),
+        ..Args::new()
+    };
+    let mut buf = vec![];
+    compile(&mut buf, &args, "<Memory>")?;
+    let bytecode = read_program(&mut Cursor::new(buf))?;
+    let mut disasm = vec![];
+    bytecode.disasm(&mut disasm)?;
+    let disasm = String::from_utf8(disasm)?;
+    assert!(disasm.contains("Call 1 (\"fibonacci\")"));
+    // The name is not a literal pushed before every call anymore
+    assert!(!disasm.contains("Str(\"fibonacci\")"));
+
+    let n = 20;
+    let script = format!(
+        "fn fib(n: i64) -> i64 {{ if n < 2 {{ i64(1) }} else {{ fib(n - 1) + fib(n - 2) }} }} fib({n})"
+    );
+    let start = Instant::now();
+    let value = run_source(&script)?;
+    let elapsed = start.elapsed();
+    assert_eq!(value, Value::I64(10946));
+    // fib(n) calls itself fib(n + 1) * 2 - 1 times in total
+    let calls = 2 * 10946 - 1;
+    println!(
+        "fib({n}) = {value} made {calls} calls in {elapsed:?} ({:.0} calls/s)",
+        calls as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Check that folding the arithmetic on literals leaves fewer `Add` and `Mul` instructions.
+//!
+//! Run with `cargo run --example const_fold`.
+use ruscal::{
+    compiler::{compile, read_program},
+    Args,
+};
+
+/// Number of each opcode in the disassembly of the source, compiled with or without folding
+fn count_ops(
+    source: &str,
+    optimize: bool,
+    ops: &[&str],
+) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+    let args = Args {
+        source: Some(source.to_string()),
+        optimize,
+        ..Args::new()
+    };
+    let mut buf = vec![];
+    compile(&mut buf, &args, "<Memory>")?;
+    let mut disasm = vec![];
+    read_program(&mut &buf[..])?.disasm(&mut disasm)?;
+    let disasm = String::from_utf8(disasm)?;
+    Ok(ops
+        .iter()
+        .map(|op| {
+            disasm
+                .lines()
+                .filter(|line| line.split_whitespace().nth(1) == Some(op))
+                .count()
+        })
+        .collect())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let source = "examples/const_fold.kscl";
+    let ops = ["Add", "Mul", "Sub", "Div"];
+    let plain = count_ops(source, false, &ops)?;
+    let folded = count_ops(source, true, &ops)?;
+    for ((op, plain), folded) in ops.iter().zip(&plain).zip(&folded) {
+        println!("{op}: {plain} -> {folded}");
+    }
+    // `2 * 3 + 1`, `"Hello, " + "world"` and `10 - 4` are folded, but `x * 6` reads a variable
+    assert_eq!(plain[..3], [2, 2, 1]);
+    assert_eq!(folded[..3], [0, 1, 0]);
+    // `1 / 0` is left for the runtime to report
+    assert_eq!(plain[3], folded[3]);
+    Ok(())
+}
@@ -0,0 +1,39 @@
+//! Stop a script at a breakpoint, and read and patch the local variables by name.
+//!
+//! Run with `cargo run --example debug_locals`.
+use ruscal::{compiler::YieldResult, Value};
+
+const SCRIPT: &str = r#"
+fn area(w: f64, h: i64) -> f64 {
+    var scale: i64 = 2;
+    var label: str = "box";
+    for i in 0 to 1 {
+        println(label, ": ", w * h * scale);
+    }
+    0
+}
+area(1.5, 3);
+"#;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut vm = ruscal::load_source(SCRIPT)?;
+    vm.set_breakpoint(6);
+    let YieldResult::Breakpoint(line) = vm.interpret()? else {
+        return Err("The script should stop at the breakpoint".into());
+    };
+    println!("Stopped at line {line}");
+
+    let locals = vm.locals();
+    for (slot, name, value) in &locals {
+        println!("[{slot}] {name} = {value:?}");
+    }
+    let names: Vec<_> = locals.iter().map(|(_, name, _)| name.as_str()).collect();
+    assert_eq!(names, ["w", "h", "scale", "label", "i"]);
+
+    // The number is converted to i64, the declared type of `scale`
+    let (slot, _, _) = locals.iter().find(|(_, name, _)| name == "scale").unwrap();
+    vm.set_local(*slot, Value::F64(10.))?;
+    vm.delete_breakpoint(6);
+    vm.interpret()?;
+    Ok(())
+}
@@ -0,0 +1,20 @@
+//! A deeply nested expression is a syntax error instead of overflowing the stack.
+//!
+//! Run with `cargo run --example deep_expr`.
+fn main() {
+    let deep = vec!["1"; 10_000].join(" + ");
+    match ruscal::run_source(&deep) {
+        Ok(value) => println!("Unexpectedly evaluated to {value}"),
+        Err(e) => println!("{}", e.to_string().lines().next().unwrap_or_default()),
+    }
+
+    let parens = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+    if let Err(e) = ruscal::run_source(&parens) {
+        println!("{}", e.to_string().lines().next().unwrap_or_default());
+    }
+
+    // A higher limit allows deeper expressions, as long as the stack can afford them
+    ruscal::parser::set_max_expr_depth(400);
+    let value = ruscal::run_source(&vec!["1"; 300].join(" + "));
+    println!("{value:?}");
+}
@@ -0,0 +1,56 @@
+//! Embed ruscal in a Rust program.
+//!
+//! Run with `cargo run --example embed`.
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+/// Writer which collects the output of the script into a shared buffer.
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn main() {
+    let value = ruscal::run_source(
+        r#"
+fn square(x: i64) -> i64 {
+    x * x
+}
+square(12)
+"#,
+    );
+    println!("Returned value: {:?}", value);
+
+    // A variable last read after a loop is the value, not what the loop left on the stack
+    let total = ruscal::run_source(
+        r#"
+var total: i64 = i64(0);
+for i in 0 to 4 {
+    total = total + i;
+}
+total
+"#,
+    );
+    assert!(matches!(total, Ok(ruscal::Value::I64(6))));
+    println!("Returned variable: {:?}", total);
+
+    let captured = Rc::new(RefCell::new(vec![]));
+    ruscal::output::set_writer(Some(Box::new(Capture(captured.clone()))));
+    ruscal::run_source(r#"println("Hello from the script!");"#).unwrap();
+    ruscal::output::set_writer(None);
+    println!(
+        "Captured output: {:?}",
+        String::from_utf8_lossy(&captured.borrow())
+    );
+
+    if let Err(e) = ruscal::run_source(r#"var a: i64 = "str";"#) {
+        println!("{e}");
+    }
+}
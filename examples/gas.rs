@@ -0,0 +1,28 @@
+//! Bound the execution of an untrusted script by the number of instructions.
+//!
+//! Run with `cargo run --example gas`.
+use ruscal::{compiler::YieldResult, RuntimeError};
+
+const GAS: u64 = 100_000;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut vm = ruscal::load_source(
+        "fn sum(n: i64) -> i64 { if n < 1 { 0 } else { n + sum(n - 1) } } sum(100)",
+    )?
+    .with_gas(GAS);
+    let YieldResult::Finished(value) = vm.interpret()? else {
+        return Err("The script should finish".into());
+    };
+    println!("Finished within the budget with {value}");
+
+    let mut vm = ruscal::load_source("while 1 {}")?.with_gas(GAS);
+    let Err(e) = vm.interpret() else {
+        return Err("The infinite loop should run out of gas".into());
+    };
+    assert_eq!(
+        e.error.downcast_ref::<RuntimeError>(),
+        Some(&RuntimeError::GasExhausted(GAS))
+    );
+    println!("Stopped the infinite loop: {e}");
+    Ok(())
+}
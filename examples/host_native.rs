@@ -0,0 +1,47 @@
+//! Register host functions which scripts can call like the standard natives.
+//!
+//! Run with `cargo run --example host_native`.
+use std::{cell::Cell, rc::Rc, time::SystemTime};
+
+use ruscal::{register_native, RuntimeError, TypeDecl, Value};
+
+fn main() {
+    register_native("now", vec![], TypeDecl::F64, |_| {
+        let elapsed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|e| RuntimeError::Native(e.to_string()))?;
+        Ok(Value::F64(elapsed.as_secs_f64()))
+    });
+
+    // The closure must be 'static, so the host shares its state through an `Rc`
+    let calls = Rc::new(Cell::new(0));
+    let counter = calls.clone();
+    register_native(
+        "log_event",
+        vec![("name", TypeDecl::Str)],
+        TypeDecl::I64,
+        move |args| {
+            let [Value::Str(name)] = args else {
+                return Err(RuntimeError::Native("log_event takes a name".to_string()));
+            };
+            counter.set(counter.get() + 1);
+            println!("Host received event {name:?}");
+            Ok(Value::I64(counter.get()))
+        },
+    );
+
+    let value = ruscal::run_source(
+        r#"
+log_event("start");
+var t: f64 = now();
+log_event("end");
+0 < t
+"#,
+    );
+    println!("Returned value: {value:?}");
+    println!("log_event was called {} times", calls.get());
+
+    if let Err(e) = ruscal::run_source("log_event(1, 2);") {
+        println!("{e}");
+    }
+}
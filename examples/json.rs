@@ -0,0 +1,36 @@
+//! Pass values between a script and the host program as JSON.
+//!
+//! Run with `cargo run --example json`.
+use ruscal::Value;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let value = ruscal::run_source(
+        r#"
+var names: Array<str> = ["plain", "backslash \\", "line\nbreak"];
+push(names, "added");
+names
+"#,
+    )?;
+    let json = value.to_json();
+    println!("{json}");
+    assert_eq!(Value::from_json(&json)?, value);
+
+    for src in [
+        "[1, -2, 3.0, 1e3, 9223372036854775807, 9223372036854775808]",
+        r#"["é😀", "tab\tquote\"", true, false]"#,
+        "[[], [[]]]",
+        "{}",
+        "[1, null]",
+        "[1, 2",
+    ] {
+        match Value::from_json(src) {
+            Ok(value) => println!("{src} => {value} => {}", value.to_json()),
+            Err(e) => println!("{src} => {e}"),
+        }
+    }
+
+    for value in [Value::F64(1.), Value::I64(i64::MIN), Value::F64(f64::NAN)] {
+        println!("{value:?} => {}", value.to_json());
+    }
+    Ok(())
+}
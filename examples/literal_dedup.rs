@@ -0,0 +1,32 @@
+//! Check that deduplicating the literals of a function keeps the value of every float literal.
+//!
+//! Run with `cargo run --example literal_dedup`.
+use ruscal::{run_source, Value};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Equal floats share a key, and every NaN does
+    let key = |value: f64| Value::F64(value).literal_key();
+    assert_eq!(key(3.0), key(3.0));
+    assert_ne!(key(3.0), key(3.5));
+    assert_eq!(key(f64::NAN), key(-f64::NAN));
+    assert_eq!(key(f64::NAN), key(f64::from_bits(f64::NAN.to_bits() | 1)));
+    // `0.0 == -0.0`, but they are different literals
+    assert_ne!(key(0.0), key(-0.0));
+
+    let source = r#"
+var a: f64 = 3.0;
+var b: f64 = 3.0;
+var zero: f64 = 0.0;
+var neg: f64 = -0.0;
+str(a + b) + " " + str(1 / zero) + " " + str(1 / neg) + " " + str(1 / 0.0) + " " + str(1 / -0.0)
+"#;
+    let value = run_source(source)?;
+    println!("{value}");
+    assert_eq!(value, Value::str("6 inf -inf inf -inf"));
+
+    // A NaN computed at runtime is a NaN whichever literals surround it
+    let value = run_source("var nan: f64 = 0.0 / 0.0; str(nan == nan) + \" \" + str(nan)")?;
+    println!("{value}");
+    assert_eq!(value, Value::str("0 NaN"));
+    Ok(())
+}
@@ -0,0 +1,41 @@
+//! A `match` ending a function has no value, like a loop.
+//!
+//! Run with `cargo run --example match_tail`.
+use ruscal::{compiler::YieldResult, Value};
+
+const SRC: &str = r#"
+export cofn steps(n: i64) -> i64 {
+    var last: i64 = n * 10;
+    match n {
+        1 => { yield last; }
+        _ => { puts("no yield "); }
+    }
+}
+"#;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The arm taken doesn't leave a local or the scrutinee behind as the value
+    let mut vm = ruscal::load_source(SRC)?;
+    vm.reset();
+    assert_eq!(vm.run_fn("steps", &[Value::I64(2)])?, Value::Unit);
+
+    let mut vm = ruscal::load_source(SRC)?;
+    vm.reset();
+    vm.init_fn("steps", &[Value::I64(1)])?;
+    assert!(matches!(
+        vm.interpret()?,
+        YieldResult::Suspend(Value::I64(10))
+    ));
+    assert!(matches!(
+        vm.interpret()?,
+        YieldResult::Finished(Value::Unit)
+    ));
+
+    // Same for the toplevel code
+    assert_eq!(
+        ruscal::run_source("var x: i64 = 10; match x { 10 => { puts(\"ten \"); } }")?,
+        Value::Unit
+    );
+    println!("done");
+    Ok(())
+}
@@ -0,0 +1,53 @@
+//! Bound the output of an untrusted script, separately for each `Vm`.
+//!
+//! Run with `cargo run --example output_limit`.
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use ruscal::{compiler::YieldResult, RuntimeError};
+
+const LIMIT: usize = 20;
+
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let capture = Capture::default();
+    ruscal::output::set_writer(Some(Box::new(capture.clone())));
+
+    // Each line is 5 bytes, so the fifth one would exceed the limit
+    let mut noisy = ruscal::load_source(r#"while 1 { puts("line\n"); }"#)?.with_output_limit(LIMIT);
+    // Another `Vm` on the same thread doesn't share the budget of `noisy`
+    let mut quiet = ruscal::load_source(r#"puts("twelve bytes"); 1"#)?.with_output_limit(LIMIT);
+
+    let Err(e) = noisy.interpret() else {
+        return Err("The infinite loop should exceed the output limit".into());
+    };
+    assert_eq!(
+        e.error.downcast_ref::<RuntimeError>(),
+        Some(&RuntimeError::OutputLimitExceeded(LIMIT))
+    );
+    // The output stopped before the line that didn't fit
+    assert_eq!(noisy.output_written(), LIMIT);
+    assert_eq!(*capture.0.borrow(), b"line\n".repeat(4));
+
+    let YieldResult::Finished(_) = quiet.interpret()? else {
+        return Err("The quiet script should finish".into());
+    };
+    assert_eq!(quiet.output_written(), 12);
+    assert_eq!(capture.0.borrow()[LIMIT..], *b"twelve bytes");
+
+    ruscal::output::set_writer(None);
+    println!("Stopped the noisy script: {e}");
+    Ok(())
+}
@@ -0,0 +1,24 @@
+//! Check that the random natives repeat the same sequence for the same seed.
+//!
+//! Run with `cargo run --example random`.
+use ruscal::{run_source, Value};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let source = "seed(i64(42)); var s: str = \"\"; for i in 0 to 5 { s = s + str(random_int(i64(1), i64(7))) + \" \"; } s + str(random())";
+    let first = run_source(source)?;
+    for _ in 0..10 {
+        assert_eq!(first, run_source(source)?);
+    }
+    let Value::Str(rolls) = &first else {
+        panic!("expected a string, got {first:?}");
+    };
+    println!("seed 42 rolled the same every time: {rolls}");
+
+    // A different seed gives a different sequence
+    assert_ne!(first, run_source(&source.replace("42", "43"))?);
+
+    // The generator stays within its range
+    let source = "seed(i64(1)); var bad: i64 = i64(0); for i in 0 to 1000 { var x: f64 = random(); var n: i64 = random_int(i64(0) - i64(3), i64(3)); if x < 0 { bad += i64(1); }; if x < 1 {} else { bad += i64(1); }; if n < i64(0) - i64(3) { bad += i64(1); }; if n < i64(3) {} else { bad += i64(1); }; } str(bad)";
+    assert_eq!(run_source(source)?, Value::str("0"));
+    Ok(())
+}
@@ -0,0 +1,41 @@
+//! The errors of `range`, which stop the script instead of building the array.
+//!
+//! Run with `cargo run --example range`.
+use ruscal::{RuntimeError, RuscalError};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(
+        ruscal::run_source("range(0, 100, 40)")?.to_string(),
+        "[0, 40, 80]"
+    );
+    // The distance overflows i64, but the number of the elements doesn't
+    let src = "var max: i64 = 9223372036854775807;
+var step: i64 = 4611686018427387904;
+range(i64(0) - max, max, step)";
+    assert_eq!(
+        ruscal::run_source(src)?.to_string(),
+        "[-9223372036854775807, -4611686018427387903, 1, 4611686018427387905]"
+    );
+
+    for (src, message) in [
+        ("range(0, 1, 0)", "range: step must not be zero"),
+        (
+            "range(i64(1000000000000))",
+            "range: 1000000000000 elements exceed the limit of 16777216",
+        ),
+        (
+            "range(0, 1, 2, 3)",
+            "range takes 1 to 3 arguments, but 4 were given",
+        ),
+    ] {
+        match ruscal::run_source(src) {
+            Err(RuscalError::Runtime {
+                error: RuntimeError::Native(e),
+                ..
+            }) => assert_eq!(e, message),
+            res => return Err(format!("{src} should fail, but got {res:?}").into()),
+        }
+        println!("{src}: {message}");
+    }
+    Ok(())
+}
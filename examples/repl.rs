@@ -0,0 +1,70 @@
+//! Evaluate inputs one by one like the REPL does, keeping the state across them.
+//!
+//! Run with `cargo run --example repl`.
+use ruscal::{compiler::Repl, Args, RuscalError, Value};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut repl = Repl::new(&Args::default());
+
+    assert!(repl.eval("var x: i64 = 1;")?.is_none());
+    assert_eq!(repl.eval("var y: i64 = x + 41; y")?, Some(Value::I64(42)));
+
+    // Type checks, but fails to compile, so neither `bad` nor `z` are left behind
+    let Err(e) = repl.eval("var z: i64 = 7; fn bad() -> i64 { x = 2; z }") else {
+        return Err("Assigning a toplevel variable in a function should fail".into());
+    };
+    assert!(matches!(e, RuscalError::Compile(_)));
+    println!("Rolled back: {e}");
+    assert!(!repl.has_fn("bad"));
+    assert_eq!(
+        repl.vars(),
+        [
+            ("x".to_string(), Value::I64(1)),
+            ("y".to_string(), Value::I64(42)),
+        ]
+    );
+
+    // The state after the rollback still compiles and runs
+    assert_eq!(repl.eval("var w: i64 = 5; x + w")?, Some(Value::I64(6)));
+    assert_eq!(
+        repl.vars(),
+        [
+            ("x".to_string(), Value::I64(1)),
+            ("y".to_string(), Value::I64(42)),
+            ("w".to_string(), Value::I64(5)),
+        ]
+    );
+
+    // A type error drops the variables defined before it in the same input
+    let Err(e) = repl.eval("var v: f64 = 1; var u: f64 = \"a\";") else {
+        return Err("Assigning a str to an f64 variable should fail".into());
+    };
+    assert!(matches!(e, RuscalError::TypeCheck(_)));
+    let Err(e) = repl.eval("v") else {
+        return Err("v should be undefined after the rollback".into());
+    };
+    assert!(matches!(e, RuscalError::TypeCheck(_)));
+
+    // An input doesn't have to outlive the call
+    let input = format!("x + {}", 2);
+    assert_eq!(repl.eval(&input)?, Some(Value::F64(3.)));
+
+    // Redefining a function reaches the functions compiled earlier which call it
+    repl.eval("fn base() -> i64 { 1 } fn twice_base() -> i64 { base() * 2 }")?;
+    assert_eq!(repl.eval("twice_base()")?, Some(Value::I64(2)));
+    repl.eval("fn base() -> i64 { 10 }")?;
+    assert_eq!(repl.eval("twice_base()")?, Some(Value::I64(20)));
+
+    // A failure inside a closure doesn't leave the closure behind for the next inputs
+    let Err(e) =
+        repl.eval("fn outer() -> fn { var k: i64 = 1; fn inner() -> i64 { x = 2; k } inner }")
+    else {
+        return Err("Assigning a toplevel variable in a closure should fail".into());
+    };
+    println!("Rolled back: {e}");
+    assert_eq!(
+        repl.eval("fn inner() -> i64 { 3 } inner()")?,
+        Some(Value::I64(3))
+    );
+    Ok(())
+}
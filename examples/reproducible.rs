@@ -0,0 +1,28 @@
+//! Compile the same source twice and check that the bytecode is identical.
+//!
+//! Run with `cargo run --example reproducible`.
+use ruscal::{compiler::compile, Args};
+
+fn compile_to_bytes(source: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let args = Args {
+        source: Some(source.to_string()),
+        ..Args::new()
+    };
+    let mut buf = vec![];
+    compile(&mut buf, &args, "<Memory>")?;
+    Ok(buf)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // A script with several functions, which the compiler keeps in a `HashMap`
+    let source = "examples/fn_def.kscl";
+    let first = compile_to_bytes(source)?;
+    for _ in 0..10 {
+        assert_eq!(first, compile_to_bytes(source)?);
+    }
+    println!(
+        "{source} compiled to the same {} bytes every time",
+        first.len()
+    );
+    Ok(())
+}
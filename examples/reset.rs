@@ -0,0 +1,42 @@
+//! Run the same program twice on one `Vm` with `Vm::reset` in between.
+//!
+//! Run with `cargo run --example reset`.
+use ruscal::compiler::{Vm, YieldResult};
+
+fn run(vm: &mut Vm) -> Result<String, Box<dyn std::error::Error>> {
+    vm.init_main(&[])?;
+    match vm.interpret()? {
+        YieldResult::Finished(value) => Ok(value.to_string()),
+        _ => Err("The script should finish".into()),
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The toplevel variable would keep the first run's value if it leaked into the second one
+    let source = r#"
+var total: i64 = i64(0);
+fn add(n: i64) -> i64 { total + n }
+for i in 0 to 10 {
+    total = add(i);
+}
+total
+"#;
+    // Instructions are only counted with a limit
+    let mut vm = ruscal::load_source(source)?.with_gas(u64::MAX);
+    let YieldResult::Finished(first) = vm.interpret()? else {
+        return Err("The script should finish".into());
+    };
+    let gas = vm.gas_used();
+
+    // Enough gas for one run but not two, so the second run fails if the count isn't reset
+    let mut vm = ruscal::load_source(source)?.with_gas(gas + gas / 2);
+    vm.interpret()?;
+    for _ in 0..3 {
+        vm.reset();
+        let again = run(&mut vm)?;
+        assert_eq!(first.to_string(), again);
+        assert_eq!(vm.gas_used(), gas);
+    }
+    println!("Every run returned {first} using {gas} instructions");
+    Ok(())
+}
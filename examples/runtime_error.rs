@@ -0,0 +1,68 @@
+//! Tell the runtime errors of a script apart by their kind.
+//!
+//! Run with `cargo run --example runtime_error`.
+use ruscal::{compiler::VmError, RuntimeError, RuscalError, Value};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let src = "var n: i64 = 6;
+var zero: i64 = 0;
+n / zero";
+    match ruscal::run_source(src) {
+        Err(RuscalError::Runtime {
+            error: RuntimeError::DivByZero,
+            line,
+        }) => {
+            assert_eq!(line, Some(3));
+            println!("Division by zero at line {line:?}");
+        }
+        res => return Err(format!("Expected a division by zero, but got {res:?}").into()),
+    }
+
+    // A value which went through the untyped `pop` can't be called unless it is a function,
+    // even if it is a string naming one
+    for (td, values) in [("f64", "1, 2"), ("str", "\"mk\", \"pop\"")] {
+        let src = format!(
+            "fn mk(a: Array<{td}>) -> fn {{ pop(a) }}
+var a: Array<{td}> = [{values}];
+var f: fn = mk(a);
+f(1)"
+        );
+        match ruscal::run_source(&src) {
+            Err(RuscalError::Runtime {
+                error:
+                    RuntimeError::TypeError {
+                        expected: "fn",
+                        got,
+                    },
+                line,
+            }) => {
+                assert_eq!(got, td);
+                assert_eq!(line, Some(4));
+                println!("Value of type {got} is not callable");
+            }
+            res => return Err(format!("Expected a type error, but got {res:?}").into()),
+        }
+    }
+
+    // An exported function can be called without running the toplevel code, but then the
+    // toplevel variables it reads are not defined yet
+    let src = "var scale: i64 = 3;
+export fn scaled(x: i64) -> i64 { x * scale }";
+    let mut vm = ruscal::load_source(src)?;
+    vm.interpret()?;
+    assert_eq!(vm.run_fn("scaled", &[Value::I64(2)])?, Value::I64(6));
+    vm.reset();
+    let e = vm
+        .run_fn("scaled", &[Value::I64(2)])
+        .expect_err("scale should be undefined after the reset");
+    let e = e.downcast::<VmError>().map_err(|e| e.to_string())?;
+    assert!(matches!(
+        RuscalError::from(*e),
+        RuscalError::Runtime {
+            error: RuntimeError::UndefinedGlobal,
+            line: Some(2)
+        }
+    ));
+    println!("Undefined global variable");
+    Ok(())
+}
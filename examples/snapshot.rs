@@ -0,0 +1,47 @@
+//! Save the state of a suspended script and resume it in another `Vm`.
+//!
+//! Run with `cargo run --example snapshot`.
+use ruscal::compiler::YieldResult;
+
+const SCRIPT: &str = r#"
+var count: i64 = 0;
+var names: Array<str> = ["a", "b"];
+count += 10;
+yield count;
+count += 1;
+count
+"#;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut vm = ruscal::load_source(SCRIPT)?;
+    let YieldResult::Suspend(value) = vm.interpret()? else {
+        return Err("The script should yield".into());
+    };
+    println!("Suspended with {value}");
+
+    let mut snapshot = vec![];
+    vm.write_snapshot(&mut snapshot)?;
+    println!("Snapshot of {} bytes", snapshot.len());
+
+    let mut resumed = ruscal::load_source(SCRIPT)?;
+    resumed.read_snapshot(&mut snapshot.as_slice())?;
+    let YieldResult::Finished(value) = resumed.interpret()? else {
+        return Err("The script should finish".into());
+    };
+    println!("Resumed and finished with {value}");
+
+    let mut vm = ruscal::load_source(
+        r#"
+cofn gen() -> i64 {
+    yield 1;
+}
+var co: cofn = gen();
+yield 0;
+"#,
+    )?;
+    vm.interpret()?;
+    if let Err(e) = vm.write_snapshot(&mut vec![]) {
+        println!("{e}");
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Check that stepping a script one instruction at a time gives the same result and output as
+//! running it by `Vm::interpret`, for every example script.
+//!
+//! Run with `cargo run --example step`.
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use ruscal::compiler::{StepResult, Vm, YieldResult};
+
+/// Writer which collects the output of the script into a shared buffer.
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The value of the script or its error, and its output
+fn capture(run: impl FnOnce() -> Result<String, String>) -> (Result<String, String>, Vec<u8>) {
+    let captured = Rc::new(RefCell::new(vec![]));
+    ruscal::output::set_writer(Some(Box::new(Capture(captured.clone()))));
+    let res = run();
+    ruscal::output::set_writer(None);
+    let output = captured.borrow().clone();
+    (res, output)
+}
+
+/// Run to the end, passing through the `yield`s of the toplevel code
+fn interpret(mut vm: Vm) -> Result<String, String> {
+    loop {
+        match vm.interpret() {
+            Ok(YieldResult::Finished(value)) => return Ok(value.to_string()),
+            Ok(_) => {}
+            Err(e) => return Err(e.error.to_string()),
+        }
+    }
+}
+
+fn step(mut vm: Vm) -> Result<String, String> {
+    loop {
+        match vm.step() {
+            Ok(StepResult::Finished(value)) => return Ok(value.to_string()),
+            Ok(_) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut paths = std::fs::read_dir("examples")?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    let mut checked = 0;
+    for path in paths {
+        if path.extension().is_none_or(|ext| ext != "kscl") {
+            continue;
+        }
+        let source = std::fs::read_to_string(&path)?;
+        // A script waiting for input can't run unattended
+        if source.contains("read_line") {
+            continue;
+        }
+        let Ok(vm) = ruscal::load_source(&source) else {
+            continue;
+        };
+        let interpreted = capture(|| interpret(vm));
+        let stepped = capture(|| step(ruscal::load_source(&source).unwrap()));
+        assert_eq!(interpreted, stepped, "{}", path.display());
+        checked += 1;
+    }
+    println!("Stepping matched interpret for {checked} scripts");
+    Ok(())
+}
@@ -0,0 +1,46 @@
+//! Check that copying a string value shares it instead of copying its contents.
+//!
+//! Run with `cargo run --example str_sharing`.
+use std::rc::Rc;
+
+use ruscal::{register_native, run_source, RuntimeError, TypeDecl, Value};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Reports how many values refer to the same string buffer as the argument
+    register_native(
+        "share_count",
+        vec![("s", TypeDecl::Str)],
+        TypeDecl::I64,
+        |args| {
+            let [Value::Str(s)] = args else {
+                return Err(RuntimeError::Native("share_count takes a str".to_string()));
+            };
+            Ok(Value::I64(Rc::strong_count(s) as i64))
+        },
+    );
+
+    // The variable, its copy and the argument all point to the same buffer
+    let source = r#"
+var s: str = "ruscal" * i64(100000);
+var t: str = s;
+share_count(t)
+"#;
+    let shared = run_source(source)?;
+    println!("share_count after copying a 600 KB string: {shared}");
+    assert!(matches!(shared, Value::I64(n) if n >= 3));
+
+    // Reading the variable in a loop doesn't leave copies of the buffer behind
+    let source = r#"
+var s: str = "ruscal" * i64(100000);
+var n: i64 = i64(0);
+for i in 0 to 1000 {
+    var t: str = s;
+    n = n + len(t);
+}
+share_count(s) + n - n
+"#;
+    let after_loop = run_source(source)?;
+    println!("share_count after reading it in a loop: {after_loop}");
+    assert!(matches!(after_loop, Value::I64(n) if n < 10));
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! Compile and run machine generated programs as large as a transpiler might emit, and check
+//! the limits which can't be lifted are reported with the function exceeding them.
+//!
+//! Run with `cargo run --example stress`.
+use ruscal::{run_source, Value};
+
+const FUNCS: usize = 500;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // A chain of functions, each adding its index before calling the previous one
+    let mut source = String::from("fn f0(x: i64) -> i64 { x }\n");
+    for i in 1..FUNCS {
+        source += &format!(
+            "fn f{i}(x: i64) -> i64 {{ var y: i64 = x + i64({i}); if y < i64(0) {{ i64(-1) }} else {{ f{}(y) }} }}\n",
+            i - 1
+        );
+    }
+    source += &format!("f{}(i64(0))", FUNCS - 1);
+    let value = run_source(&source)?;
+    println!("{FUNCS} functions returned {value}");
+    assert_eq!(value, Value::I64((FUNCS * (FUNCS - 1) / 2) as i64));
+
+    // A function can't have more literals than an operand can index
+    let literals = (0..70_000)
+        .map(|i| format!("\"s{i}\";"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let Err(e) = run_source(&format!("fn big() -> str {{ {literals} }} big()")) else {
+        return Err("A function with too many literals should not compile".into());
+    };
+    println!("{e}");
+    assert!(e
+        .to_string()
+        .contains("Function \"big\" has too many literals"));
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! Render the scripts in `examples` back to source and check that they parse back to the same
+//! tree, apart from the source locations.
+//!
+//! Run with `cargo run --example unparse`, or `cargo run --example unparse <file>` to print the
+//! canonical source of a script.
+use ruscal::{parser::statements_finish, unparser::unparse};
+
+/// Debug form of a tree without the source locations. The location of a node is dropped, and a
+/// name keeps only its text. The parser puts the location of `dbg` into its label, so the
+/// location is dropped from the label too.
+fn strip_spans(tree: &str) -> String {
+    const START: &str = "LocatedSpan { offset: ";
+    const FRAGMENT: &str = "fragment: ";
+    const END: &str = ", extra: () }";
+    let mut out = String::new();
+    let mut rest = tree;
+    while let Some(pos) = rest.find(START) {
+        let (before, span) = rest.split_at(pos);
+        let location = before.strip_suffix("span: ");
+        out.push_str(location.unwrap_or(before));
+        let fragment =
+            &span[span.find(FRAGMENT).expect("a span has a fragment") + FRAGMENT.len()..];
+        let len = string_literal_len(fragment);
+        if location.is_none() {
+            out.push_str(&fragment[..len]);
+        }
+        rest = &fragment[len + END.len()..];
+    }
+    out.push_str(rest);
+    strip_dbg_locations(&out)
+}
+
+fn strip_dbg_locations(tree: &str) -> String {
+    const LABEL: &str = "FnInvoke(\"dbg\", [Expression { expr: StrLiteral(\"[";
+    let mut out = String::new();
+    let mut rest = tree;
+    while let Some(pos) = rest.find(LABEL) {
+        let (before, label) = rest.split_at(pos + LABEL.len() - 1);
+        out.push_str(before);
+        rest = label.split_once("] ").map_or(label, |(_, rest)| rest);
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Length in bytes of the Debug form of a string at the start of `s`, with the quotes
+fn string_literal_len(s: &str) -> usize {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return i + 1,
+            _ => (),
+        }
+    }
+    panic!("unterminated string in {s}")
+}
+
+fn round_trip(src: &str) -> Result<String, String> {
+    let stmts = statements_finish(src.into()).map_err(|e| format!("{e:?}"))?;
+    let canonical = unparse(&stmts);
+    let reparsed = statements_finish(canonical.as_str().into())
+        .map_err(|e| format!("canonical source doesn't parse: {e:?}\n{canonical}"))?;
+    let (tree, retree) = (
+        strip_spans(&format!("{stmts:?}")),
+        strip_spans(&format!("{reparsed:?}")),
+    );
+    if tree != retree {
+        return Err(format!(
+            "round trip changed the tree:\n{canonical}\n---\n{tree}\n---\n{retree}"
+        ));
+    }
+    Ok(canonical)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(file) = std::env::args().nth(1) {
+        let src = std::fs::read_to_string(file)?;
+        print!("{}", round_trip(&src)?);
+        return Ok(());
+    }
+
+    let mut files = std::fs::read_dir("examples")?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "kscl"))
+        .collect::<Vec<_>>();
+    files.sort();
+    let mut failed = 0;
+    for file in &files {
+        let src = std::fs::read_to_string(file)?;
+        // Scripts that are meant to fail parsing are skipped
+        if statements_finish(src.as_str().into()).is_err() {
+            continue;
+        }
+        if let Err(e) = round_trip(&src) {
+            println!("{}: {e}", file.display());
+            failed += 1;
+        }
+    }
+    println!("{} scripts, {failed} failed", files.len());
+    if failed > 0 {
+        return Err(format!("{failed} scripts didn't round trip").into());
+    }
+
+    // Parentheses needed by the precedence or the associativity are kept
+    for src in ["a - (b - c);", "a / (b * c);", "(a + b) * c;", "!(a < b);"] {
+        let canonical = round_trip(src)?;
+        println!("{src} -> {}", canonical.trim_end());
+    }
+    Ok(())
+}
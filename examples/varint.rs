@@ -0,0 +1,40 @@
+//! Integers are serialized as varints, so small ones take a few bytes.
+//!
+//! Run with `cargo run --example varint`.
+use ruscal::Value;
+
+fn main() -> std::io::Result<()> {
+    let mut values: Vec<i64> = (-300..=300).collect();
+    for shift in 0..63 {
+        values.extend([
+            1 << shift,
+            -(1 << shift),
+            (1 << shift) - 1,
+            -(1 << shift) + 1,
+        ]);
+    }
+    values.extend([i64::MIN, i64::MAX, i64::MIN + 1, i64::MAX - 1]);
+
+    for value in values {
+        let mut buf = vec![];
+        Value::I64(value).serialize(&mut buf)?;
+        let read = Value::deserialize(&mut buf.as_slice())?;
+        assert_eq!(
+            read,
+            Value::I64(value),
+            "{value} didn't survive a round trip"
+        );
+    }
+
+    // A tag byte is followed by the varint
+    for value in [0, -1, 63, -64, 64, 8191, 8192, i64::MIN, i64::MAX] {
+        let mut buf = vec![];
+        Value::I64(value).serialize(&mut buf)?;
+        println!("{value}: {} bytes", buf.len());
+    }
+
+    let mut buf = vec![];
+    Value::array(vec![Value::I64(1), Value::I64(-2), Value::F64(3.)]).serialize(&mut buf)?;
+    println!("{}", Value::deserialize(&mut buf.as_slice())?);
+    Ok(())
+}
@@ -1,19 +1,27 @@
+use crate::explain::{source_snippet, ErrorCode};
+use crate::optimizer::{fold_constants, normalize, reassociate};
+use crate::output::{self, OutputBudget};
 use crate::parser::{
-    standard_functions, statements_finish, type_check, ExprEnum, Expression, FnDecl, NativeFn,
-    Span, Statement, Statements, TypeCheckContext, TypeDecl,
+    max_expr_depth, next_arg, standard_constants, standard_functions, statements_finish,
+    type_check, ExprEnum, Expression, FnDecl, NativeFn, Span, Statement, Statements,
+    TypeCheckContext, TypeDecl, DUMP_STACK, FILTER, MAP, REDUCE, SORT,
 };
-use crate::value::{deserialize_size, deserialize_str, serialize_size, serialize_str, Value};
-use ruscal::{dprintln, Args, RunMode};
+use crate::value::{
+    deserialize_size, deserialize_str, merge_sort_by, serialize_size, serialize_str, Closure,
+    LiteralKey, Value, ValueKind,
+};
+use ruscal::{dprintln, Args, RunMode, RuscalError};
 use std::{
-    cell::RefCell,
-    collections::HashMap,
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap, HashSet},
     error::Error,
     fmt::Display,
     io::{Read, Write},
     rc::Rc,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum OpCode {
     LoadLiteral,
@@ -34,8 +42,8 @@ pub enum OpCode {
     Jmp,
     /// Jump if false
     Jf,
-    /// Exit if false
-    Exitf,
+    /// Pop an index and exit with an error unless it is within `0..arg0`
+    BoundsCheck,
     Not,
     Lt,
     Eq,
@@ -47,6 +55,22 @@ pub enum OpCode {
     Yield,
     /// Await a coroutine in progress until the next yield
     Await,
+    /// Pop the first value which is the index, pop the second value which is a dynamic array,
+    /// and push the element
+    Index,
+    /// Pop n values where n is given by arg0, and push a dynamic array of them
+    MakeArray,
+    /// Convert the number at the stack distance arg0 in place to the `ValueKind` given by arg1
+    Cast,
+    /// Restart the current function with the top n values as arguments where n is given by arg0
+    TailCall,
+    /// Push the value of the global variable arg0
+    LoadGlobal,
+    /// Pop a value and store it to the global variable arg0
+    StoreGlobal,
+    /// Pop n captured values where n is given by arg0 and the function name below them,
+    /// and push a closure
+    MakeClosure,
 }
 
 macro_rules! impl_op_from {
@@ -81,14 +105,21 @@ impl_op_from!(
     Call,
     Jmp,
     Jf,
-    Exitf,
+    BoundsCheck,
     Not,
     Lt,
     Eq,
     Pop,
     Ret,
     Yield,
-    Await
+    Await,
+    Index,
+    MakeArray,
+    Cast,
+    TailCall,
+    LoadGlobal,
+    StoreGlobal,
+    MakeClosure
 );
 
 #[derive(Debug, Clone, Copy)]
@@ -129,6 +160,15 @@ impl Instruction {
     }
 }
 
+/// The argument count of `Call` is stored in `arg0`, so it can't exceed the operand width.
+const MAX_CALL_ARGS: usize = u16::MAX as usize;
+/// Literal indices are stored in `arg0` of `LoadLiteral`.
+const MAX_LITERALS: usize = u16::MAX as usize + 1;
+/// Jump targets are stored in `arg0`, and a jump can target the end of the function.
+const MAX_INSTRUCTIONS: usize = u16::MAX as usize;
+/// A longer string repetition fails with an error instead of aborting on the allocation.
+const MAX_STR_REPEAT_LEN: usize = 1 << 28;
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 /// Absolute Stack Index
 struct StkIdx(usize);
@@ -146,6 +186,15 @@ enum Target {
     Local(String, Option<TypeDecl>),
 }
 
+/// Toplevel variable, which functions can read through its global slot
+#[derive(Clone)]
+struct Global {
+    slot: u16,
+    /// Stack index of the variable in the toplevel function
+    stk_idx: StkIdx,
+    td: TypeDecl,
+}
+
 struct LoopFrame {
     start: StkIdx,
     break_ips: Vec<InstPtr>,
@@ -167,17 +216,210 @@ struct LoopStackUnderflowError;
 
 impl Display for LoopStackUnderflowError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "A break statement outside loop")
+        write!(f, "[{}] A break statement outside loop", ErrorCode::E006)
     }
 }
 
 impl Error for LoopStackUnderflowError {}
 
+/// Errors raised by the `Vm` while running a program.
+///
+/// The `Vm` returns them boxed, so hosts can match on the kind with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    UndefinedFunction(String),
+    /// Integer division by zero
+    DivByZero,
+    TypeError {
+        expected: &'static str,
+        got: &'static str,
+    },
+    /// A binary operator was applied to operands of types it doesn't support
+    UnsupportedOperation {
+        op: OpCode,
+        lhs: &'static str,
+        rhs: &'static str,
+    },
+    IndexOutOfBounds {
+        index: i64,
+        len: usize,
+    },
+    ArityMismatch {
+        fn_name: String,
+        expected: usize,
+        got: usize,
+    },
+    /// The print natives wrote more bytes than the limit
+    OutputLimitExceeded(usize),
+    /// Calls were nested deeper than the limit
+    StackOverflow(usize),
+    /// `assert` or `assert_eq` failed, with the message to show
+    AssertionFailed(String),
+    /// A native function failed on its arguments, with the message to show
+    Native(String),
+    /// The `Vm` executed as many instructions as `Vm::with_gas` allows
+    GasExhausted(u64),
+    /// The result of an operator on `i64` operands doesn't fit in `i64`
+    IntegerOverflow(OpCode),
+    /// `<` or `>` was applied to values which are not both numbers, or `sort` to values which
+    /// are neither both numbers nor both strings
+    Incomparable {
+        lhs: &'static str,
+        rhs: &'static str,
+    },
+    /// A function read a toplevel variable before the toplevel code defined it
+    UndefinedGlobal,
+    /// A coroutine was called from a native like `map` and yielded, with the name of the function
+    YieldFromNative(String),
+    /// A function capturing variables was called by its name instead of through its closure
+    ClosureCalledByName(String),
+    /// `*` would build a string longer than the limit in bytes
+    StringTooLong(usize),
+    /// The bytecode broke an invariant of the `Vm`, e.g. popped from an empty stack
+    Internal(String),
+}
+
+impl RuntimeError {
+    /// Code which can be looked up with `--explain`, if any
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Self::UndefinedFunction(_) => Some(ErrorCode::E003),
+            Self::TypeError { .. }
+            | Self::UnsupportedOperation { .. }
+            | Self::Incomparable { .. } => Some(ErrorCode::E002),
+            Self::IndexOutOfBounds { .. } => Some(ErrorCode::E005),
+            Self::OutputLimitExceeded(_) => Some(ErrorCode::E007),
+            Self::DivByZero
+            | Self::ArityMismatch { .. }
+            | Self::StackOverflow(_)
+            | Self::AssertionFailed(_)
+            | Self::Native(_)
+            | Self::IntegerOverflow(_)
+            | Self::GasExhausted(_)
+            | Self::UndefinedGlobal
+            | Self::YieldFromNative(_)
+            | Self::ClosureCalledByName(_)
+            | Self::StringTooLong(_)
+            | Self::Internal(_) => None,
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(code) = self.code() {
+            write!(f, "[{code}] ")?;
+        }
+        match self {
+            Self::UndefinedFunction(name) => write!(f, "Function {name:?} was not found"),
+            Self::TypeError { expected, got } => write!(f, "Expected {expected}, but got {got}"),
+            Self::DivByZero => write!(f, "Division by zero"),
+            Self::UnsupportedOperation { op, lhs, rhs } => {
+                write!(f, "{op:?} is not supported between {lhs} and {rhs}")
+            }
+            Self::IndexOutOfBounds { index, len } => {
+                write!(f, "Index {index} out of bounds for length {len}")
+            }
+            Self::ArityMismatch {
+                fn_name,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Function {fn_name:?} takes {expected} arguments, but {got} were given"
+            ),
+            Self::OutputLimitExceeded(limit) => {
+                write!(f, "Output exceeded the limit of {limit} bytes")
+            }
+            Self::StackOverflow(limit) => {
+                write!(f, "Stack overflow: calls nested deeper than {limit}")
+            }
+            Self::AssertionFailed(message) => write!(f, "Assertion failed: {message}"),
+            Self::Native(message) => write!(f, "{message}"),
+            Self::GasExhausted(limit) => {
+                write!(f, "Gas exhausted after {limit} instructions")
+            }
+            Self::IntegerOverflow(op) => write!(f, "Integer overflow in {op:?}"),
+            Self::Incomparable { lhs, rhs } => write!(f, "Cannot compare {lhs} and {rhs}"),
+            Self::UndefinedGlobal => write!(f, "Global variable is read before its definition"),
+            Self::YieldFromNative(name) => {
+                write!(f, "Function {name:?} can't yield from a native")
+            }
+            Self::ClosureCalledByName(name) => write!(
+                f,
+                "Function {name:?} captures variables, so it can only be called through its closure"
+            ),
+            Self::StringTooLong(limit) => {
+                write!(f, "String would be longer than the limit of {limit} bytes")
+            }
+            Self::Internal(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// Error returned by `Vm::interpret`, with the source line of the failing instruction.
+///
+/// The line is `None` if the bytecode has no line table for the instruction.
+#[derive(Debug)]
+pub struct VmError {
+    pub line: Option<u32>,
+    /// Usually a `RuntimeError`
+    pub error: Box<dyn Error>,
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(line) = self.line {
+            write!(f, " at line {line}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for VmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+impl From<VmError> for RuscalError {
+    fn from(e: VmError) -> Self {
+        let error = match e.error.downcast::<RuntimeError>() {
+            Ok(error) => *error,
+            Err(error) => RuntimeError::Internal(error.to_string()),
+        };
+        RuscalError::Runtime {
+            error,
+            line: e.line,
+        }
+    }
+}
+
 struct FnByteCode {
     args: Vec<String>,
+    /// Variables of the enclosing function captured by a closure, which come before the arguments
+    captures: Vec<String>,
     literals: Vec<Value>,
     instructions: Vec<Instruction>,
+    /// Source line of each instruction, aligned with `instructions`
+    lines: Vec<u32>,
+    /// Names of the local variables other than the captures and arguments, for the debugger
+    locals: Vec<LocalName>,
     cofn: bool,
+    /// Only exported functions can be used as entry points by the host
+    export: bool,
+}
+
+/// Name of the local variable at a stack slot, from the instruction at `ip` until another
+/// variable takes the slot
+#[derive(Clone)]
+struct LocalName {
+    ip: usize,
+    slot: usize,
+    name: String,
 }
 
 impl FnByteCode {
@@ -205,11 +447,33 @@ impl FnByteCode {
         Ok(())
     }
 
+    fn write_lines(lines: &[u32], writer: &mut impl Write) -> std::io::Result<()> {
+        serialize_size(lines.len(), writer)?;
+        for line in lines {
+            writer.write_all(&line.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn write_locals(locals: &[LocalName], writer: &mut impl Write) -> std::io::Result<()> {
+        serialize_size(locals.len(), writer)?;
+        for local in locals {
+            serialize_size(local.ip, writer)?;
+            serialize_size(local.slot, writer)?;
+            serialize_str(&local.name, writer)?;
+        }
+        Ok(())
+    }
+
     fn serialize(&self, writer: &mut impl Write) -> std::io::Result<()> {
         Self::write_args(&self.args, writer)?;
+        Self::write_args(&self.captures, writer)?;
         Self::write_literals(&self.literals, writer)?;
         Self::write_insts(&self.instructions, writer)?;
+        Self::write_lines(&self.lines, writer)?;
+        Self::write_locals(&self.locals, writer)?;
         writer.write_all(&[self.cofn as u8])?;
+        writer.write_all(&[self.export as u8])?;
         Ok(())
     }
 
@@ -241,28 +505,82 @@ impl FnByteCode {
         Ok(instructions)
     }
 
+    fn read_lines(reader: &mut impl Read) -> std::io::Result<Vec<u32>> {
+        let num_lines = deserialize_size(reader)?;
+        let mut lines = Vec::with_capacity(num_lines);
+        for _ in 0..num_lines {
+            let mut buf = [0u8; std::mem::size_of::<u32>()];
+            reader.read_exact(&mut buf)?;
+            lines.push(u32::from_le_bytes(buf));
+        }
+        Ok(lines)
+    }
+
+    fn read_locals(reader: &mut impl Read) -> std::io::Result<Vec<LocalName>> {
+        let num_locals = deserialize_size(reader)?;
+        let mut locals = Vec::with_capacity(num_locals);
+        for _ in 0..num_locals {
+            locals.push(LocalName {
+                ip: deserialize_size(reader)?,
+                slot: deserialize_size(reader)?,
+                name: deserialize_str(reader)?,
+            });
+        }
+        Ok(locals)
+    }
+
     fn deserialize(reader: &mut impl Read) -> std::io::Result<Self> {
         let args = Self::read_args(reader)?;
+        let captures = Self::read_args(reader)?;
         let literals = Self::read_literals(reader)?;
         let instructions = Self::read_instructions(reader)?;
+        let lines = Self::read_lines(reader)?;
+        let locals = Self::read_locals(reader)?;
         let mut cofn = [0u8];
         reader.read_exact(&mut cofn)?;
+        let mut export = [0u8];
+        reader.read_exact(&mut export)?;
         Ok(Self {
             args,
+            captures,
             literals,
             instructions,
+            lines,
+            locals,
             cofn: cofn[0] != 0,
+            export: export[0] != 0,
         })
     }
 
-    fn disasm(&self, writer: &mut impl Write) -> std::io::Result<()> {
-        disasm_common(&self.literals, &self.instructions, writer)
+    fn disasm(&self, fn_names: &[String], writer: &mut impl Write) -> std::io::Result<()> {
+        disasm_common(&self.literals, &self.instructions, fn_names, None, writer)
+    }
+
+    /// Name of the variable at the stack slot when the instruction at `ip` is executed next,
+    /// or `None` for a temporary value
+    fn local_name(&self, slot: usize, ip: usize) -> Option<&str> {
+        let (captures, args) = (self.captures.len(), self.args.len());
+        if slot < captures {
+            return Some(&self.captures[slot]);
+        }
+        if slot < captures + args {
+            return Some(&self.args[slot - captures]);
+        }
+        self.locals
+            .iter()
+            .rev()
+            .find(|local| local.slot == slot && local.ip <= ip)
+            .map(|local| local.name.as_str())
     }
 }
 
+/// Write the literals and instructions, marking the instruction at `ip` with `=>` if any.
+/// `fn_names` are the names of the functions which `Call` refers to by index.
 fn disasm_common(
     literals: &[Value],
     instructions: &[Instruction],
+    fn_names: &[String],
+    ip: Option<usize>,
     writer: &mut impl Write,
 ) -> std::io::Result<()> {
     use OpCode::*;
@@ -271,47 +589,245 @@ fn disasm_common(
         writeln!(writer, "   [{i}] {}", *con)?;
     }
 
+    // Label the jump targets in the order of their addresses
+    let mut targets: Vec<usize> = instructions
+        .iter()
+        .filter(|inst| matches!(inst.op, Jmp | Jf))
+        .map(|inst| inst.arg0 as usize)
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+    let label = |addr: u16| match targets.binary_search(&(addr as usize)) {
+        Ok(n) => format!("L{n}"),
+        Err(_) => addr.to_string(),
+    };
+
     writeln!(writer, "  Instructions [{}]", instructions.len())?;
     for (i, inst) in instructions.iter().enumerate() {
+        if let Ok(n) = targets.binary_search(&i) {
+            writeln!(writer, "  L{n}:")?;
+        }
+        let mark = if ip == Some(i) { " =>" } else { "   " };
         match inst.op {
             LoadLiteral => writeln!(
                 writer,
-                "   [{i}] {:?} {} ({:?})",
+                "{mark}[{i}] {:?} {} ({:?})",
                 inst.op, inst.arg0, literals[inst.arg0 as usize]
             )?,
-            Copy | IndexCopy | Dup | Call | Jmp | Pop | Store | IndexStore | Ret => {
-                writeln!(writer, "   [{i}] {:?} {}", inst.op, inst.arg0)?
+            Jmp => writeln!(writer, "{mark}[{i}] {:?} {}", inst.op, label(inst.arg0))?,
+            Jf => writeln!(
+                writer,
+                "{mark}[{i}] {:?} {} {}",
+                inst.op,
+                label(inst.arg0),
+                inst.arg1
+            )?,
+            Call if inst.arg1 != 0 => writeln!(
+                writer,
+                "{mark}[{i}] {:?} {} ({:?})",
+                inst.op,
+                inst.arg0,
+                fn_names
+                    .get(inst.arg1 as usize - 1)
+                    .map_or("?", String::as_str)
+            )?,
+            Copy | IndexCopy | Dup | Call | Pop | Store | IndexStore | Ret | MakeArray
+            | BoundsCheck | TailCall | LoadGlobal | StoreGlobal | MakeClosure => {
+                writeln!(writer, "{mark}[{i}] {:?} {}", inst.op, inst.arg0)?
             }
-            Jf => writeln!(writer, "   [{i}] {:?} {} {}", inst.op, inst.arg0, inst.arg1)?,
-            _ => writeln!(writer, "   [{i}] {:?}", inst.op)?,
+            Cast => writeln!(
+                writer,
+                "{mark}[{i}] {:?} {} {}",
+                inst.op, inst.arg0, inst.arg1
+            )?,
+            _ => writeln!(writer, "{mark}[{i}] {:?}", inst.op)?,
         }
     }
+    // A jump can target the end of the function
+    if let Ok(n) = targets.binary_search(&instructions.len()) {
+        writeln!(writer, "  L{n}:")?;
+    }
     Ok(())
 }
 
 struct Compiler {
     literals: Vec<Value>,
+    /// Index of `literals` for dedup lookup
+    literal_index: HashMap<LiteralKey, u16>,
     instructions: Vec<Instruction>,
+    /// Source lines of `instructions`, which may lag behind until `sync_lines` is called
+    lines: Vec<u32>,
+    /// Local variables defined so far in the function being compiled
+    locals: Vec<LocalName>,
+    /// Source line of the statement or expression being compiled
+    line: u32,
     target_stack: Vec<Target>,
-    funcs: HashMap<String, FnByteCode>,
+    funcs: HashMap<String, Rc<FnByteCode>>,
+    /// Names of the functions called by name, in the order of the indices `Call` refers to
+    fn_names: Vec<String>,
+    /// Index of `fn_names` by name
+    fn_ids: HashMap<String, u16>,
     loop_stack: Vec<LoopFrame>,
+    max_functions: Option<usize>,
+    /// Declared return type of the function being compiled
+    ret_type: Option<TypeDecl>,
+    /// First operand which didn't fit in an instruction
+    operand_overflow: Option<usize>,
+    /// Functions being compiled, which are not in `funcs` until their bodies are done
+    fn_stack: Vec<String>,
+    /// Name and arity of the function whose frame a self-recursive `return` can reuse
+    tail_fn: Option<(String, usize)>,
+    /// Toplevel variables defined so far, by name
+    globals: HashMap<String, Global>,
+    /// Number of nested blocks being compiled, which is 1 for the outermost statements
+    block_depth: usize,
+    /// Name and number of captures of the closure being compiled, which refers to itself by
+    /// a closure made from its own captures
+    closure: Option<(String, usize)>,
+    /// Number of lambdas compiled so far, to name them uniquely
+    lambdas: usize,
+    /// Maximum depth of nested expressions, which the parser limits as well
+    max_expr_depth: usize,
+    /// Depth of the expression being compiled
+    expr_depth: usize,
 }
 
 impl Compiler {
     fn new() -> Self {
         Self {
             literals: vec![],
+            literal_index: HashMap::new(),
             instructions: vec![],
+            lines: vec![],
+            locals: vec![],
+            line: 0,
             target_stack: vec![],
             funcs: HashMap::new(),
+            fn_names: vec![],
+            fn_ids: HashMap::new(),
             loop_stack: vec![],
+            max_functions: None,
+            ret_type: None,
+            operand_overflow: None,
+            fn_stack: vec![],
+            tail_fn: None,
+            globals: HashMap::new(),
+            block_depth: 0,
+            closure: None,
+            lambdas: 0,
+            max_expr_depth: max_expr_depth(),
+            expr_depth: 0,
+        }
+    }
+
+    /// Whether the name refers to a function compiled so far, being compiled or native
+    fn is_function(&self, name: &str) -> bool {
+        self.funcs.contains_key(name)
+            || self.fn_stack.iter().any(|func| func == name)
+            || standard_functions().contains_key(name)
+    }
+
+    /// The global variable of the name, unless a local variable shadows it
+    fn global(&self, name: &str) -> Option<Global> {
+        let shadowed = self
+            .target_stack
+            .iter()
+            .any(|target| matches!(target, Target::Local(id, _) if id == name));
+        if shadowed {
+            return None;
+        }
+        self.globals.get(name).cloned()
+    }
+
+    /// Copy a toplevel variable to its global slot, so functions see its new value
+    fn add_store_global_inst(&mut self, name: &str, stk_idx: StkIdx, td: &TypeDecl) {
+        let slot = match self.globals.get(name) {
+            Some(global) => global.slot,
+            None => self.operand(self.globals.len()),
+        };
+        self.globals.insert(
+            name.to_string(),
+            Global {
+                slot,
+                stk_idx,
+                td: td.clone(),
+            },
+        );
+        self.add_copy_inst(stk_idx);
+        self.add_inst(OpCode::StoreGlobal, slot);
+        self.target_stack.pop();
+    }
+
+    fn add_make_closure_inst(
+        &mut self,
+        name: &str,
+        captures: impl ExactSizeIterator<Item = StkIdx>,
+    ) -> StkIdx {
+        let num_captures = captures.len();
+        let fn_name = self.add_literal(Value::str(name));
+        self.add_load_literal_inst(fn_name);
+        for stk_idx in captures {
+            self.add_copy_inst(stk_idx);
         }
+        self.add_inst(OpCode::MakeClosure, num_captures as u16);
+        self.target_stack
+            .truncate(self.target_stack.len() - num_captures);
+        self.stack_top()
+    }
+
+    /// The number of captures of the closure being compiled, if the name refers to it
+    fn self_closure(&self, name: &str) -> Option<usize> {
+        let (closure, num_captures) = self.closure.as_ref()?;
+        let shadowed = self
+            .target_stack
+            .iter()
+            .any(|target| matches!(target, Target::Local(id, _) if id == name));
+        (closure == name && !shadowed).then_some(*num_captures)
+    }
+
+    fn add_zero_inst(&mut self) -> StkIdx {
+        let zero = self.add_literal(Value::F64(0.));
+        self.add_load_literal_inst(zero);
+        self.stack_top()
+    }
+
+    fn add_unit_inst(&mut self) -> StkIdx {
+        let unit = self.add_literal(Value::Unit);
+        self.add_load_literal_inst(unit);
+        self.stack_top()
+    }
+
+    fn add_load_global_inst(&mut self, global: &Global) -> StkIdx {
+        self.add_inst(OpCode::LoadGlobal, global.slot);
+        self.target_stack.push(Target::Temp);
+        self.stack_top()
     }
 
     fn stack_top(&self) -> StkIdx {
         StkIdx(self.target_stack.len() - 1)
     }
 
+    /// Make the stack slot a local variable of the name.
+    fn define_local(&mut self, stk_idx: StkIdx, name: &str, td: TypeDecl) {
+        self.target_stack[stk_idx.0] = Target::Local(name.to_string(), Some(td));
+        self.locals.push(LocalName {
+            ip: self.instructions.len(),
+            slot: stk_idx.0,
+            name: name.to_string(),
+        });
+    }
+
+    /// Attribute the instructions added so far to the current line.
+    fn sync_lines(&mut self) {
+        self.lines.resize(self.instructions.len(), self.line);
+    }
+
+    /// Attribute the instructions added from now on to `line`, and return the previous line.
+    fn set_line(&mut self, line: u32) -> u32 {
+        self.sync_lines();
+        std::mem::replace(&mut self.line, line)
+    }
+
     fn fixup_breaks(&mut self) -> Result<(), Box<dyn Error>> {
         let loop_frame = self.loop_stack.pop().ok_or(LoopStackUnderflowError)?;
         let break_jmp_addr = self.instructions.len();
@@ -324,26 +840,52 @@ impl Compiler {
     fn fixup_continues(&mut self) -> Result<(), Box<dyn Error>> {
         let loop_frame = self.loop_stack.last().ok_or(LoopStackUnderflowError)?;
         let continue_jmp_addr = self.instructions.len();
-        for (ip, stk) in &loop_frame.continue_ips {
-            self.instructions[ip.0].arg0 = (self.target_stack.len() - stk) as u16;
+        for (ip, stk) in loop_frame.continue_ips.clone() {
+            self.instructions[ip.0].arg0 = self.operand(self.target_stack.len() - stk);
             self.instructions[ip.0 + 1].arg0 = continue_jmp_addr as u16
         }
         Ok(())
     }
 
+    /// Index of the function to call by name, which the `ByteCode` resolves when it is loaded.
+    /// The function may be defined later, or be a native.
+    fn fn_id(&mut self, name: &str) -> u16 {
+        if let Some(id) = self.fn_ids.get(name) {
+            return *id;
+        }
+        let id = self.operand(self.fn_names.len());
+        self.fn_names.push(name.to_string());
+        self.fn_ids.insert(name.to_string(), id);
+        id
+    }
+
     fn add_literal(&mut self, value: Value) -> u16 {
-        let existing = self
-            .literals
-            .iter()
-            .enumerate()
-            .find(|(_, val)| **val == value);
-        if let Some((i, _)) = existing {
-            i as u16
-        } else {
-            let ret = self.literals.len();
-            self.literals.push(value);
-            ret as u16
+        let key = value.literal_key();
+        if let Some(i) = key.as_ref().and_then(|key| self.literal_index.get(key)) {
+            return *i;
         }
+        let ret = self.literals.len() as u16;
+        self.literals.push(value);
+        if let Some(key) = key {
+            self.literal_index.insert(key, ret);
+        }
+        ret
+    }
+
+    /// Convert a value to an instruction operand.
+    ///
+    /// The helpers adding instructions are infallible, so instead of silently truncating,
+    /// an overflow is recorded here and reported as an error by `add_fn`.
+    fn operand(&mut self, value: usize) -> u16 {
+        u16::try_from(value).unwrap_or_else(|_| {
+            self.operand_overflow.get_or_insert(value);
+            0
+        })
+    }
+
+    /// Distance from the stack top to the stack index, as an instruction operand
+    fn stack_distance(&mut self, stack_idx: StkIdx) -> u16 {
+        self.operand(self.target_stack.len() - stack_idx.0 - 1)
     }
 
     // return the absolute position of inserted value
@@ -353,11 +895,17 @@ impl Compiler {
         InstPtr(inst)
     }
 
+    /// Check that the index at `stack_idx` is within the length of a fixed size array
+    fn add_bounds_check_inst(&mut self, stack_idx: StkIdx, len: usize) {
+        self.add_copy_inst(stack_idx);
+        let len = self.operand(len);
+        self.add_inst(OpCode::BoundsCheck, len);
+        self.target_stack.pop();
+    }
+
     fn add_copy_inst(&mut self, stack_idx: StkIdx) -> InstPtr {
-        let inst = self.add_inst(
-            OpCode::Copy,
-            (self.target_stack.len() - stack_idx.0 - 1) as u16,
-        );
+        let distance = self.stack_distance(stack_idx);
+        let inst = self.add_inst(OpCode::Copy, distance);
         self.target_stack.push(Target::Temp);
         inst
     }
@@ -365,13 +913,18 @@ impl Compiler {
     fn add_index_copy_inst(&mut self, stack_idx: StkIdx) -> InstPtr {
         if self.target_stack.len() < stack_idx.0 + 1 {
             eprintln!("Compiled bytecode so far:");
-            disasm_common(&self.literals, &self.instructions, &mut std::io::stderr()).unwrap();
+            disasm_common(
+                &self.literals,
+                &self.instructions,
+                &self.fn_names,
+                None,
+                &mut std::io::stderr(),
+            )
+            .unwrap();
             panic!("Target stack underflow during compilation!");
         }
-        let inst = self.add_inst(
-            OpCode::IndexCopy,
-            (self.target_stack.len() - stack_idx.0 - 1) as u16,
-        );
+        let distance = self.stack_distance(stack_idx);
+        let inst = self.add_inst(OpCode::IndexCopy, distance);
         self.target_stack.pop(); // pop target array index
         self.target_stack.push(Target::Temp); // push value to copy
         inst
@@ -392,13 +945,18 @@ impl Compiler {
     fn add_store_inst(&mut self, stack_idx: StkIdx) -> InstPtr {
         if self.target_stack.len() < stack_idx.0 + 1 {
             eprintln!("Compiled bytecode so far:");
-            disasm_common(&self.literals, &self.instructions, &mut std::io::stderr()).unwrap();
+            disasm_common(
+                &self.literals,
+                &self.instructions,
+                &self.fn_names,
+                None,
+                &mut std::io::stderr(),
+            )
+            .unwrap();
             panic!("Target stack underflow during compilation!");
         }
-        let inst = self.add_inst(
-            OpCode::Store,
-            (self.target_stack.len() - stack_idx.0 - 1) as u16,
-        );
+        let distance = self.stack_distance(stack_idx);
+        let inst = self.add_inst(OpCode::Store, distance);
         self.target_stack.pop();
         inst
     }
@@ -406,18 +964,38 @@ impl Compiler {
     fn add_index_store_inst(&mut self, stack_idx: StkIdx) -> InstPtr {
         if self.target_stack.len() < stack_idx.0 + 1 {
             eprintln!("Compiled bytecode so far:");
-            disasm_common(&self.literals, &self.instructions, &mut std::io::stderr()).unwrap();
+            disasm_common(
+                &self.literals,
+                &self.instructions,
+                &self.fn_names,
+                None,
+                &mut std::io::stderr(),
+            )
+            .unwrap();
             panic!("Target stack underflow during compilation!");
         }
-        let inst = self.add_inst(
-            OpCode::IndexStore,
-            (self.target_stack.len() - stack_idx.0 - 1) as u16,
-        );
+        let distance = self.stack_distance(stack_idx);
+        let inst = self.add_inst(OpCode::IndexStore, distance);
         self.target_stack.pop(); // pop array index
         self.target_stack.pop(); // pop value to store
         inst
     }
 
+    /// Convert the number at the stack index in place to the declared type, if it's numeric.
+    ///
+    /// Number literals are always `F64`, so this keeps the runtime kind consistent with the
+    /// type checker, which treats a variable as its declared type.
+    fn add_cast_inst(&mut self, stack_idx: StkIdx, td: &TypeDecl) {
+        let kind = match td {
+            TypeDecl::F64 => ValueKind::F64,
+            TypeDecl::I64 => ValueKind::I64,
+            _ => return,
+        };
+        let distance = self.stack_distance(stack_idx);
+        self.instructions
+            .push(Instruction::new(OpCode::Cast, distance, kind as u16));
+    }
+
     fn add_jf_inst(&mut self, coerce_size: Option<u16>) -> InstPtr {
         // push with jump address 0, because it will be set later
         let inst = self.instructions.len();
@@ -428,10 +1006,11 @@ impl Compiler {
                 arg1: size,
             });
         } else {
+            let size = self.operand(self.target_stack.len() - 1);
             self.instructions.push(Instruction {
                 op: OpCode::Jf,
                 arg0: 0,
-                arg1: (self.target_stack.len() - 1) as u16,
+                arg1: size,
             });
         }
         let inst = InstPtr(inst);
@@ -448,44 +1027,113 @@ impl Compiler {
         if self.target_stack.len() <= stack_idx.0 {
             return None;
         }
-        let inst = self.add_inst(
-            OpCode::Pop,
-            (self.target_stack.len() - stack_idx.0 - 1) as u16,
-        );
+        let distance = self.stack_distance(stack_idx);
+        let inst = self.add_inst(OpCode::Pop, distance);
         self.target_stack.resize(stack_idx.0 + 1, Target::Temp);
         Some(inst)
     }
 
-    fn add_fn(&mut self, name: String, args: &[(Span, TypeDecl)], cofn: bool) {
+    fn add_fn(
+        &mut self,
+        name: String,
+        args: &[(Span, TypeDecl)],
+        captures: Vec<String>,
+        cofn: bool,
+        export: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if MAX_LITERALS < self.literals.len() {
+            return Err(format!(
+                "Function {name:?} has too many literals: {} (max {MAX_LITERALS})",
+                self.literals.len()
+            )
+            .into());
+        }
+        if let Some(value) = self.operand_overflow.take() {
+            return Err(format!(
+                "Function {name:?} has too many values on the stack: an operand {value} exceeds {}",
+                u16::MAX
+            )
+            .into());
+        }
+        if MAX_INSTRUCTIONS < self.instructions.len() {
+            return Err(format!(
+                "Function {name:?} has too many instructions: {} (max {MAX_INSTRUCTIONS})",
+                self.instructions.len()
+            )
+            .into());
+        }
+        if let Some(max_functions) = self.max_functions {
+            if !self.funcs.contains_key(&name) && max_functions <= self.funcs.len() {
+                return Err(format!(
+                    "Too many functions: {name:?} exceeds the limit of {max_functions}"
+                )
+                .into());
+            }
+        }
+        self.literal_index.clear();
+        self.sync_lines();
         self.funcs.insert(
             name,
-            FnByteCode {
+            Rc::new(FnByteCode {
                 args: args.iter().map(|(arg, _)| arg.to_string()).collect(),
+                captures,
                 literals: std::mem::take(&mut self.literals),
                 instructions: std::mem::take(&mut self.instructions),
+                lines: std::mem::take(&mut self.lines),
+                locals: std::mem::take(&mut self.locals),
                 cofn,
-            },
+                export,
+            }),
         );
+        Ok(())
     }
 
     fn write_funcs(&self, writer: &mut impl Write) -> std::io::Result<()> {
         serialize_size(self.funcs.len(), writer)?;
-        for (name, func) in &self.funcs {
+        // Sorted, so that the same source always compiles to the same bytes
+        let mut funcs: Vec<_> = self.funcs.iter().collect();
+        funcs.sort_unstable_by_key(|(name, _)| *name);
+        for (name, func) in funcs {
             serialize_str(name, writer)?;
             func.serialize(writer)?;
         }
+        serialize_size(self.fn_names.len(), writer)?;
+        for name in &self.fn_names {
+            serialize_str(name, writer)?;
+        }
         Ok(())
     }
 
     fn compile_expr(&mut self, ex: &Expression) -> Result<StkIdx, Box<dyn Error>> {
+        if self.max_expr_depth <= self.expr_depth {
+            return Err(format!(
+                "Expression is nested deeper than the limit of {}",
+                self.max_expr_depth
+            )
+            .into());
+        }
+        self.expr_depth += 1;
+        let outer_line = self.set_line(ex.span.location_line());
+        let res = self.compile_expr_kind(ex);
+        self.set_line(outer_line);
+        self.expr_depth -= 1;
+        res
+    }
+
+    fn compile_expr_kind(&mut self, ex: &Expression) -> Result<StkIdx, Box<dyn Error>> {
         Ok(match &ex.expr {
             ExprEnum::NumLiteral(num) => {
                 let id = self.add_literal(Value::F64(*num));
                 self.add_load_literal_inst(id);
                 self.stack_top()
             }
+            ExprEnum::BoolLiteral(value) => {
+                let id = self.add_literal(Value::I64(*value as i64));
+                self.add_load_literal_inst(id);
+                self.stack_top()
+            }
             ExprEnum::StrLiteral(str) => {
-                let id = self.add_literal(Value::Str(str.clone()));
+                let id = self.add_literal(Value::str(str));
                 self.add_load_literal_inst(id);
                 self.stack_top()
             }
@@ -523,6 +1171,22 @@ impl Compiler {
                     });
                 if let Some(var) = var {
                     return Ok(StkIdx(var.0));
+                } else if let Some(global) = self.global(ident) {
+                    self.add_load_global_inst(&global)
+                } else if let Some(num_captures) = self.self_closure(ident) {
+                    // The captures are at the bottom of the frame of a closure
+                    self.add_make_closure_inst(ident, (0..num_captures).map(StkIdx))
+                } else if let Some((_, value)) = standard_constants()
+                    .into_iter()
+                    .find(|(name, _)| name == ident.fragment())
+                {
+                    let id = self.add_literal(value);
+                    self.add_load_literal_inst(id);
+                    self.stack_top()
+                } else if self.is_function(ident) {
+                    let id = self.add_literal(Value::Func(ident.to_string()));
+                    self.add_load_literal_inst(id);
+                    self.stack_top()
                 } else {
                     return Err(format!("Variable not found: {ident:?}").into());
                 }
@@ -545,6 +1209,9 @@ impl Compiler {
                             return Err(format!("Variable not found: {ident:?}").into());
                         }
                     };
+                    if let TypeDecl::DynArray(_) = ty {
+                        return self.compile_dyn_index(StkIdx(idx), indices);
+                    }
                     let mut i = 0;
                     let mut stk_idxs: Vec<StkIdx> = vec![]; // stack_index
                     let mut array_lengths: Vec<usize> = vec![]; // len
@@ -576,14 +1243,7 @@ impl Compiler {
                     let index_idx = self.add_literal(Value::F64(0.)); // temp sum
                     self.add_load_literal_inst(index_idx);
                     for (i, stk_idx) in stk_idxs.iter().enumerate() {
-                        // check index out of range
-                        self.add_copy_inst(*stk_idx);
-                        let index_inst_idx = self.add_literal(Value::F64(array_lengths[i] as f64));
-                        self.add_load_literal_inst(index_inst_idx); // max index
-                        self.add_inst(OpCode::Lt, 0);
-                        self.add_inst(OpCode::Exitf, 0);
-                        self.target_stack.pop();
-                        self.target_stack.pop();
+                        self.add_bounds_check_inst(*stk_idx, array_lengths[i]);
 
                         self.add_copy_inst(*stk_idx);
                         let idx = self.add_literal(Value::F64(cumulative_lengths[i] as f64));
@@ -597,6 +1257,15 @@ impl Compiler {
                     }
                     self.add_index_copy_inst(StkIdx(idx));
                     self.stack_top()
+                } else if let Some(
+                    global @ Global {
+                        td: TypeDecl::DynArray(_),
+                        ..
+                    },
+                ) = self.global(ident)
+                {
+                    let stk_idx = self.add_load_global_inst(&global);
+                    return self.compile_dyn_index(stk_idx, indices);
                 } else {
                     return Err(format!("Variable not found: {ident:?}").into());
                 }
@@ -605,8 +1274,8 @@ impl Compiler {
             ExprEnum::Sub(lhs, rhs) => self.bin_op(OpCode::Sub, lhs, rhs)?,
             ExprEnum::Mul(lhs, rhs) => self.bin_op(OpCode::Mul, lhs, rhs)?,
             ExprEnum::Div(lhs, rhs) => self.bin_op(OpCode::Div, lhs, rhs)?,
-            ExprEnum::And(lhs, rhs) => self.bin_op(OpCode::And, lhs, rhs)?,
-            ExprEnum::Or(lhs, rhs) => self.bin_op(OpCode::Or, lhs, rhs)?,
+            ExprEnum::And(lhs, rhs) => self.short_circuit_op(OpCode::And, lhs, rhs)?,
+            ExprEnum::Or(lhs, rhs) => self.short_circuit_op(OpCode::Or, lhs, rhs)?,
             ExprEnum::Not(ex) => {
                 let res = self.compile_expr(ex)?;
                 self.add_copy_inst(res);
@@ -615,6 +1284,21 @@ impl Compiler {
                 self.target_stack.push(Target::Temp);
                 self.stack_top()
             }
+            ExprEnum::Cast(ex, td) => {
+                let res = self.compile_expr(ex)?;
+                self.add_copy_inst(res);
+                // Declarations don't convert to `str`, so only `as` casts to it
+                if *td == TypeDecl::Str {
+                    self.instructions.push(Instruction::new(
+                        OpCode::Cast,
+                        0,
+                        ValueKind::Str as u16,
+                    ));
+                } else {
+                    self.add_cast_inst(self.stack_top(), td);
+                }
+                self.stack_top()
+            }
             ExprEnum::Gt(lhs, rhs) => self.bin_op(OpCode::Lt, rhs, lhs)?,
             ExprEnum::Lt(lhs, rhs) => self.bin_op(OpCode::Lt, lhs, rhs)?,
             ExprEnum::Eq(lhs, rhs) => self.bin_op(OpCode::Eq, lhs, rhs)?,
@@ -627,19 +1311,52 @@ impl Compiler {
                 self.stack_top()
             }
             ExprEnum::FnInvoke(name, args) => {
+                if MAX_CALL_ARGS < args.len() {
+                    return Err(format!(
+                        "Too many arguments in a call to {}: {} (max {MAX_CALL_ARGS})",
+                        name,
+                        args.len()
+                    )
+                    .into());
+                }
                 let stack_before_args = self.target_stack.len();
-                let name = self.add_literal(Value::Str(name.to_string()));
+                // A variable holding a function value shadows the function of the same name
+                let func_var = self.target_stack.iter().rposition(|target| {
+                    matches!(target, Target::Local(id, Some(TypeDecl::Func)) if id == name.fragment())
+                });
+                let func_global = self
+                    .global(name)
+                    .filter(|global| global.td == TypeDecl::Func);
+                let self_closure = self.self_closure(name);
+                let fn_name = name;
+                // A function called by name is referred to by index, without a value for it
+                let fn_id = (func_var.is_none() && func_global.is_none() && self_closure.is_none())
+                    .then(|| self.fn_id(name));
                 let args = args
                     .iter()
-                    .map(|arg| self.compile_expr(arg))
+                    .map(|arg| match &arg.expr {
+                        // an argument takes a single slot, so an array literal is a dynamic array
+                        ExprEnum::ArrayLiteral(values) => self.compile_array_value(values),
+                        _ => self.compile_expr(arg),
+                    })
                     .collect::<Result<Vec<_>, _>>()?;
                 let stack_before_call = self.target_stack.len();
-                self.add_load_literal_inst(name);
+                if let Some(func_var) = func_var {
+                    self.add_copy_inst(StkIdx(func_var));
+                } else if let Some(func_global) = func_global {
+                    self.add_load_global_inst(&func_global);
+                } else if let Some(num_captures) = self_closure {
+                    self.add_make_closure_inst(fn_name, (0..num_captures).map(StkIdx));
+                }
                 for arg in &args {
                     self.add_copy_inst(*arg);
                 }
 
-                self.add_inst(OpCode::Call, args.len() as u16);
+                let call = self.add_inst(OpCode::Call, args.len() as u16);
+                if let Some(fn_id) = fn_id {
+                    // 0 is for a call through the function value below the arguments
+                    self.instructions[call.0].arg1 = self.operand(fn_id as usize + 1);
+                }
                 self.target_stack
                     .resize(stack_before_call + 1, Target::Temp);
                 self.coerce_stack(StkIdx(stack_before_args));
@@ -651,24 +1368,45 @@ impl Compiler {
                 self.add_copy_inst(cond);
                 let jf_inst = self.add_jf_inst(None);
                 let stack_size_before = self.target_stack.len();
-                self.compile_stmts_or_zero(true_branch)?;
+                self.compile_branch(true_branch)?;
                 self.coerce_stack(StkIdx(stack_size_before + 1));
                 let jmp_inst = self.add_inst(Jmp, 0);
                 self.fixup_jmp(jf_inst);
                 self.target_stack.resize(stack_size_before, Target::Temp);
-                if let Some(false_branch) = false_branch.as_ref() {
-                    self.compile_stmts_or_zero(false_branch)?;
-                }
+                // Without `else`, a false condition leaves no value
+                match false_branch.as_ref() {
+                    Some(false_branch) => self.compile_branch(false_branch)?,
+                    None => self.add_unit_inst(),
+                };
                 self.coerce_stack(StkIdx(stack_size_before + 1));
                 self.fixup_jmp(jmp_inst);
                 self.stack_top()
             }
+            ExprEnum::Lambda(args, ret_type, stmts) => {
+                let name = format!("lambda#{}", self.lambdas);
+                self.lambdas += 1;
+                match self.compile_fn(&name, args, ret_type, stmts, false, false)? {
+                    Some(closure) => closure,
+                    None => {
+                        let id = self.add_literal(Value::Func(name));
+                        self.add_load_literal_inst(id);
+                        self.stack_top()
+                    }
+                }
+            }
             ExprEnum::Await(ex) => {
                 let res = self.compile_expr(ex)?;
                 self.add_copy_inst(res);
                 self.add_inst(OpCode::Await, 0);
                 self.stack_top()
             }
+            ExprEnum::Yield(ex) => {
+                let res = self.compile_expr(ex)?;
+                // Yield replaces the copy with the value sent in on resume
+                self.add_copy_inst(res);
+                self.add_inst(OpCode::Yield, 0);
+                self.stack_top()
+            }
         })
     }
 
@@ -689,6 +1427,66 @@ impl Compiler {
         Ok(self.stack_top())
     }
 
+    /// Compile `&&` or `||`, evaluating `rhs` only if `lhs` doesn't determine the result.
+    ///
+    /// The result is computed by `op` on both paths to keep the value type of the
+    /// non-short-circuit evaluation; when short-circuited, `lhs` is used in place of `rhs`.
+    fn short_circuit_op(
+        &mut self,
+        op: OpCode,
+        lhs: &Expression,
+        rhs: &Expression,
+    ) -> Result<StkIdx, Box<dyn Error>> {
+        let lhs = self.compile_expr(lhs)?;
+        let stack_size_before = self.target_stack.len();
+        self.add_copy_inst(lhs);
+        if matches!(op, OpCode::Or) {
+            self.add_inst(OpCode::Not, 0);
+        }
+        let jf_inst = self.add_jf_inst(None);
+
+        let rhs = self.compile_expr(rhs)?;
+        self.add_copy_inst(lhs);
+        self.add_copy_inst(rhs);
+        self.add_inst(op, 0);
+        self.target_stack.pop();
+        self.target_stack.pop();
+        self.target_stack.push(Target::Temp);
+        self.coerce_stack(StkIdx(stack_size_before));
+        let jmp_inst = self.add_inst(OpCode::Jmp, 0);
+
+        self.fixup_jmp(jf_inst);
+        self.target_stack.resize(stack_size_before, Target::Temp);
+        self.add_copy_inst(lhs);
+        self.add_copy_inst(lhs);
+        self.add_inst(op, 0);
+        self.target_stack.pop();
+        self.fixup_jmp(jmp_inst);
+        Ok(self.stack_top())
+    }
+
+    /// Compile an array literal into a single dynamic array value
+    fn compile_array_value(&mut self, values: &[Expression]) -> Result<StkIdx, Box<dyn Error>> {
+        let stack_before = self.target_stack.len();
+        let values = values
+            .iter()
+            .map(|value| match &value.expr {
+                ExprEnum::ArrayLiteral(values) => self.compile_array_value(values),
+                _ => self.compile_expr(value),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for value in &values {
+            self.add_copy_inst(*value);
+        }
+        let len = self.operand(values.len());
+        self.add_inst(OpCode::MakeArray, len);
+        self.target_stack
+            .resize(self.target_stack.len() - values.len(), Target::Temp);
+        self.target_stack.push(Target::Temp);
+        self.coerce_stack(StkIdx(stack_before));
+        Ok(self.stack_top())
+    }
+
     fn coerce_stack(&mut self, target: StkIdx) {
         match target {
             StkIdx(val) if val < self.target_stack.len() - 1 => {
@@ -730,7 +1528,7 @@ impl Compiler {
                                 }
                                 TypeDecl::Str => {
                                     for _ in 0..entire_len {
-                                        let id = self.add_literal(Value::Str("".to_string()));
+                                        let id = self.add_literal(Value::str(""));
                                         self.add_load_literal_inst(id);
                                         if stk_idx0.is_none() {
                                             *stk_idx0 = Some(self.stack_top());
@@ -773,7 +1571,7 @@ impl Compiler {
                                     self.add_load_literal_inst(id);
                                 }
                                 TypeDecl::Str => {
-                                    let id = self.add_literal(Value::Str("".to_string()));
+                                    let id = self.add_literal(Value::str(""));
                                     self.add_load_literal_inst(id);
                                 }
                                 _ => panic!("Unsupported type"),
@@ -793,12 +1591,23 @@ impl Compiler {
     }
 
     fn compile_stmts(&mut self, stmts: &Statements) -> Result<Option<StkIdx>, Box<dyn Error>> {
+        self.block_depth += 1;
+        let res = self.compile_block(stmts);
+        self.block_depth -= 1;
+        res
+    }
+
+    fn compile_block(&mut self, stmts: &Statements) -> Result<Option<StkIdx>, Box<dyn Error>> {
         let mut last_result = None;
         for stmt in stmts {
+            // Line 0 stands for the statements without a location
+            self.set_line(stmt.span().map_or(0, |span| span.location_line()));
             match stmt {
                 Statement::Expression(ex) => {
                     last_result = Some(self.compile_expr(ex)?);
                 }
+                // A comment has no value, so the value of the statement before it is kept
+                Statement::Comment => (),
                 Statement::VarDef { name, ex, td, .. } => match td {
                     TypeDecl::Array(_, _) => {
                         let mut stk_idx = None;
@@ -816,39 +1625,63 @@ impl Compiler {
                         }
 
                         if let Some(stk_idx) = stk_idx {
-                            self.target_stack[stk_idx.0 + sum_len] =
-                                Target::Local(name.to_string(), Some(td.clone()));
+                            self.define_local(StkIdx(stk_idx.0 + sum_len), name, td.clone());
                         } else {
                             panic!("Array index not found");
                         }
                     }
                     _ => {
-                        let mut stk_idx = self.compile_expr(ex)?;
+                        let mut stk_idx = match &ex.expr {
+                            // only a dynamic array can be initialized by an array literal here
+                            ExprEnum::ArrayLiteral(values) => self.compile_array_value(values)?,
+                            _ => self.compile_expr(ex)?,
+                        };
                         if !matches!(self.target_stack[stk_idx.0], Target::Temp) {
                             self.add_copy_inst(stk_idx);
                             stk_idx = self.stack_top();
                         }
-                        self.target_stack[stk_idx.0] =
-                            Target::Local(name.to_string(), Some(td.clone()));
+                        self.add_cast_inst(stk_idx, td);
+                        self.define_local(stk_idx, name, td.clone());
+                        // A variable in a block at the toplevel goes out of scope with the block
+                        if self.fn_stack.is_empty() && self.block_depth == 1 {
+                            self.add_store_global_inst(name, stk_idx, td);
+                        }
                     }
                 },
                 Statement::VarAssign { name, ex, .. } => {
                     let stk_ex = self.compile_expr(ex)?;
-                    let (stk_local, _) = self
+                    let (stk_local, td) = self
                         .target_stack
-                        .iter_mut()
+                        .iter()
                         .enumerate()
                         .rev()
-                        .find(|(_, tgt)| {
-                            if let Target::Local(tgt, _) = tgt {
-                                tgt == name.fragment()
-                            } else {
-                                false
+                        .find_map(|(i, tgt)| match tgt {
+                            Target::Local(tgt, td) if tgt == name.fragment() => {
+                                Some((i, td.clone()))
                             }
+                            _ => None,
                         })
-                        .ok_or_else(|| format!("Variable name not found: {name}"))?;
+                        .ok_or_else(|| {
+                            if self.globals.contains_key(*name.fragment()) {
+                                format!("Toplevel variable {name} can't be assigned in a function")
+                            } else {
+                                format!("Variable name not found: {name}")
+                            }
+                        })?;
                     self.add_copy_inst(stk_ex);
+                    if let Some(td) = &td {
+                        self.add_cast_inst(self.stack_top(), td);
+                    }
                     self.add_store_inst(StkIdx(stk_local));
+                    // Only the toplevel variable itself has a global slot, not a local of the same name
+                    let global = self
+                        .globals
+                        .get(*name.fragment())
+                        .filter(|global| self.fn_stack.is_empty() && global.stk_idx.0 == stk_local)
+                        .cloned();
+                    if let Some(global) = global {
+                        self.add_store_global_inst(name, global.stk_idx, &global.td);
+                    }
                 }
                 Statement::ArrayIndexAssign {
                     name, indices, ex, ..
@@ -907,14 +1740,7 @@ impl Compiler {
                     let index_idx = self.add_literal(Value::F64(0.)); // temp sum
                     self.add_load_literal_inst(index_idx);
                     for (i, stk_idx) in stk_idxs.iter().enumerate() {
-                        // check index out of range
-                        self.add_copy_inst(*stk_idx);
-                        let index_inst_idx = self.add_literal(Value::F64(array_lengths[i] as f64));
-                        self.add_load_literal_inst(index_inst_idx); // max index
-                        self.add_inst(OpCode::Lt, 0);
-                        self.add_inst(OpCode::Exitf, 0);
-                        self.target_stack.pop();
-                        self.target_stack.pop();
+                        self.add_bounds_check_inst(*stk_idx, array_lengths[i]);
 
                         self.add_copy_inst(*stk_idx);
                         let idx = self.add_literal(Value::F64(cumulative_lengths[i] as f64));
@@ -935,20 +1761,42 @@ impl Compiler {
                     loop_var,
                     start,
                     end,
+                    step,
                     stmts,
                     ..
                 } => {
                     let stk_start = self.compile_expr(start)?;
                     let stk_end = self.compile_expr(end)?;
-                    dprintln!("start: {stk_start:?} end: {stk_end:?}");
+                    let stk_step = step
+                        .as_ref()
+                        .map(|step| self.compile_expr(step))
+                        .transpose()?;
+                    dprintln!("start: {stk_start:?} end: {stk_end:?} step: {stk_step:?}");
+                    let stk_step = stk_step.map(|stk_step| {
+                        self.add_copy_inst(stk_step);
+                        self.add_cast_inst(self.stack_top(), &TypeDecl::I64);
+                        self.stack_top()
+                    });
                     self.add_copy_inst(stk_start);
                     let stk_loop_var = self.stack_top();
-                    self.target_stack[stk_loop_var.0] =
-                        Target::Local(loop_var.to_string(), Some(TypeDecl::I64));
+                    self.add_cast_inst(stk_loop_var, &TypeDecl::I64);
+                    self.define_local(stk_loop_var, loop_var, TypeDecl::I64);
                     dprintln!("after start: {:?}", self.target_stack);
                     let inst_check_exit = self.instructions.len();
-                    self.add_copy_inst(stk_loop_var);
-                    self.add_copy_inst(stk_end);
+                    if let Some(stk_step) = stk_step {
+                        // The sign of the step is known only at runtime, so continue while
+                        // `0 < (end - i) * step`, which covers counting down as well.
+                        let zero = self.add_literal(Value::F64(0.));
+                        self.add_load_literal_inst(zero);
+                        self.add_copy_inst(stk_end);
+                        self.add_copy_inst(stk_loop_var);
+                        self.add_binop_inst(OpCode::Sub);
+                        self.add_copy_inst(stk_step);
+                        self.add_binop_inst(OpCode::Mul);
+                    } else {
+                        self.add_copy_inst(stk_loop_var);
+                        self.add_copy_inst(stk_end);
+                    }
                     dprintln!("before cmp: {:?}", self.target_stack);
                     self.add_binop_inst(OpCode::Lt);
                     let jf_inst = self.add_jf_inst(None);
@@ -956,10 +1804,14 @@ impl Compiler {
                     self.loop_stack.push(LoopFrame::new(stk_loop_var));
                     self.compile_stmts(stmts)?;
                     self.fixup_continues()?;
-                    let one = self.add_literal(Value::F64(1.0));
                     dprintln!("end in loop: {:?}", self.target_stack);
                     self.add_copy_inst(stk_loop_var);
-                    self.add_load_literal_inst(one);
+                    if let Some(stk_step) = stk_step {
+                        self.add_copy_inst(stk_step);
+                    } else {
+                        let one = self.add_literal(Value::I64(1));
+                        self.add_load_literal_inst(one);
+                    }
                     self.add_inst(OpCode::Add, 0);
                     self.target_stack.pop();
                     self.add_store_inst(stk_loop_var);
@@ -967,13 +1819,22 @@ impl Compiler {
                     self.add_inst(OpCode::Jmp, inst_check_exit as u16);
                     self.fixup_jmp(jf_inst);
                     self.fixup_breaks()?;
+                    // The loop variable goes out of scope, but its value stays on the stack
+                    self.target_stack[stk_loop_var.0] = Target::Temp;
+                    // A loop has no value, whatever its body does
+                    last_result = Some(self.add_unit_inst());
                 }
                 Statement::While { cond, stmts, .. } => {
+                    // The loop pops back to the value below the condition, so there has to be one
+                    if self.target_stack.is_empty() {
+                        self.add_zero_inst();
+                    }
                     let inst_check_exit = self.instructions.len();
                     let stk_before_cond = self.stack_top();
 
                     self.compile_expr(cond)?;
-                    let jf_inst = self.add_jf_inst(Some((stk_before_cond.0 + 1) as u16));
+                    let size = self.operand(stk_before_cond.0 + 1);
+                    let jf_inst = self.add_jf_inst(Some(size));
                     dprintln!("start in loop: {:?}", self.target_stack);
 
                     self.loop_stack.push(LoopFrame::new(stk_before_cond));
@@ -985,6 +1846,41 @@ impl Compiler {
                     self.add_inst(OpCode::Jmp, inst_check_exit as u16);
                     self.fixup_jmp(jf_inst);
                     self.fixup_breaks()?;
+                    last_result = Some(self.add_unit_inst());
+                }
+                Statement::Match {
+                    scrutinee,
+                    arms,
+                    default,
+                    ..
+                } => {
+                    // Lowered to a chain of `Eq` and `Jf`, leaving only the scrutinee on the stack
+                    let stk_scrutinee = self.compile_expr(scrutinee)?;
+                    let stack_size = self.target_stack.len();
+                    let mut end_jmps = vec![];
+                    for (pattern, stmts) in arms {
+                        let stk_pattern = self.compile_expr(pattern)?;
+                        self.add_copy_inst(stk_scrutinee);
+                        self.add_copy_inst(stk_pattern);
+                        self.add_binop_inst(OpCode::Eq);
+                        let size = self.operand(stack_size);
+                        let jf_inst = self.add_jf_inst(Some(size));
+                        self.compile_stmts(stmts)?;
+                        self.add_pop_until_inst(StkIdx(stack_size - 1));
+                        end_jmps.push(self.add_inst(OpCode::Jmp, 0));
+                        self.fixup_jmp(jf_inst);
+                        self.target_stack.resize(stack_size, Target::Temp);
+                    }
+                    if let Some(stmts) = default {
+                        self.compile_stmts(stmts)?;
+                        self.add_pop_until_inst(StkIdx(stack_size - 1));
+                    }
+                    for jmp_inst in end_jmps {
+                        self.fixup_jmp(jmp_inst);
+                    }
+                    self.target_stack.resize(stack_size, Target::Temp);
+                    // The arms are statements, so a match has no value like a loop
+                    last_result = Some(self.add_unit_inst());
                 }
                 Statement::Break => {
                     let start = self
@@ -1018,67 +1914,412 @@ impl Compiler {
                 Statement::FnDef {
                     name,
                     args,
+                    ret_type,
                     stmts,
                     cofn,
-                    ..
+                    export,
                 } => {
-                    let literals = std::mem::take(&mut self.literals);
-                    let instructions = std::mem::take(&mut self.instructions);
-                    let target_stack = std::mem::take(&mut self.target_stack);
-                    self.target_stack = args
-                        .iter()
-                        .map(|arg| {
-                            let ty = (arg.1).clone();
-                            Target::Local(arg.0.to_string(), Some(ty))
-                        })
-                        .collect();
-                    self.compile_stmts(stmts)?;
-                    self.add_fn(name.to_string(), args, *cofn);
-                    self.literals = literals;
-                    self.instructions = instructions;
-                    self.target_stack = target_stack;
+                    if let Some(closure) =
+                        self.compile_fn(name, args, ret_type, stmts, *cofn, *export)?
+                    {
+                        // The closure is a local variable which shadows the function by name
+                        self.define_local(closure, name, TypeDecl::Func);
+                    }
                 }
                 Statement::Return(ex) => {
+                    if let ExprEnum::FnInvoke(name, args) = &ex.expr {
+                        if self.is_tail_call(name, args.len()) {
+                            self.compile_tail_call(args)?;
+                            continue;
+                        }
+                    }
                     let res = self.compile_expr(ex)?;
                     self.add_copy_inst(res);
-                    self.add_inst(OpCode::Ret, (self.target_stack.len() - res.0 - 1) as u16);
-                }
-                Statement::Yield(ex) => {
-                    let res = self.compile_expr(ex)?;
-                    self.add_inst(OpCode::Yield, (self.target_stack.len() - res.0 - 1) as u16);
-                    self.target_stack.pop();
+                    if let Some(ret_type) = self.ret_type.clone() {
+                        self.add_cast_inst(self.stack_top(), &ret_type);
+                    }
+                    self.add_inst(OpCode::Ret, 0);
                 }
             }
         }
         Ok(last_result)
     }
 
-    fn compile_stmts_or_zero(&mut self, stmts: &Statements) -> Result<StkIdx, Box<dyn Error>> {
-        Ok(self.compile_stmts(stmts)?.unwrap_or_else(|| {
-            let id = self.add_literal(Value::F64(0.));
-            self.add_load_literal_inst(id);
-            self.stack_top()
-        }))
+    /// Compile a function definition into `funcs`. A function nested in another one captures
+    /// the variables of the enclosing function, and then the closure is pushed to the stack.
+    fn compile_fn(
+        &mut self,
+        name: &str,
+        args: &[(Span, TypeDecl)],
+        ret_type: &TypeDecl,
+        stmts: &Statements,
+        cofn: bool,
+        export: bool,
+    ) -> Result<Option<StkIdx>, Box<dyn Error>> {
+        let literals = std::mem::take(&mut self.literals);
+        let literal_index = std::mem::take(&mut self.literal_index);
+        self.sync_lines();
+        let instructions = std::mem::take(&mut self.instructions);
+        let lines = std::mem::take(&mut self.lines);
+        let locals = std::mem::take(&mut self.locals);
+        let target_stack = std::mem::take(&mut self.target_stack);
+        let mut captures = Self::captures(&target_stack);
+        if self.fn_stack.is_empty() {
+            // Toplevel variables are read through their global slots, so only the variables of
+            // the blocks at the toplevel are captured
+            captures.retain(|(stk_idx, target)| {
+                !matches!(target, Target::Local(name, _)
+                    if self.globals.get(name).is_some_and(|global| global.stk_idx == *stk_idx))
+            });
+        }
+        self.target_stack = captures
+            .iter()
+            .map(|(_, target)| target.clone())
+            .chain(args.iter().map(|arg| {
+                let ty = (arg.1).clone();
+                Target::Local(arg.0.to_string(), Some(ty))
+            }))
+            .collect();
+        let outer_ret_type = self.ret_type.replace(ret_type.clone());
+        self.fn_stack.push(name.to_string());
+        // A coroutine doesn't call itself but starts another one, so it has no tail calls.
+        // A closure can't either, since the call by name doesn't pass the captures.
+        let outer_tail_fn = std::mem::replace(
+            &mut self.tail_fn,
+            (!cofn && captures.is_empty()).then(|| (name.to_string(), args.len())),
+        );
+        let outer_closure = std::mem::replace(
+            &mut self.closure,
+            (!captures.is_empty()).then(|| (name.to_string(), captures.len())),
+        );
+        for (i, (_, td)) in args.iter().enumerate() {
+            self.add_cast_inst(StkIdx(captures.len() + i), td);
+        }
+        if let Some(res) = self.compile_stmts(stmts)? {
+            // The function returns the stack top, which may not be the last value
+            // if it is a variable
+            if res.0 + 1 != self.target_stack.len() {
+                self.add_copy_inst(res);
+            }
+            if !cofn {
+                self.add_cast_inst(self.stack_top(), ret_type);
+            }
+        }
+        self.ret_type = outer_ret_type;
+        self.tail_fn = outer_tail_fn;
+        self.closure = outer_closure;
+        self.fn_stack.pop();
+        let capture_names = captures
+            .iter()
+            .filter_map(|(_, target)| match target {
+                Target::Local(name, _) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        self.add_fn(name.to_string(), args, capture_names, cofn, export)?;
+        self.literals = literals;
+        self.literal_index = literal_index;
+        self.instructions = instructions;
+        self.lines = lines;
+        self.locals = locals;
+        self.target_stack = target_stack;
+        if captures.is_empty() {
+            return Ok(None);
+        }
+        let stk_idxs = captures.iter().map(|(stk_idx, _)| *stk_idx);
+        Ok(Some(self.add_make_closure_inst(name, stk_idxs)))
+    }
+
+    /// Variables of the enclosing function which a nested function captures: the innermost
+    /// one of each name, except fixed size arrays which span several stack slots.
+    fn captures(target_stack: &[Target]) -> Vec<(StkIdx, Target)> {
+        let mut captures: Vec<(StkIdx, Target)> = vec![];
+        for (i, target) in target_stack.iter().enumerate().rev() {
+            let Target::Local(name, Some(td)) = target else {
+                continue;
+            };
+            let seen = captures.iter().any(
+                |(_, capture)| matches!(capture, Target::Local(capture, _) if capture == name),
+            );
+            if !seen && !matches!(td, TypeDecl::Array(_, _)) {
+                captures.push((StkIdx(i), target.clone()));
+            }
+        }
+        captures.reverse();
+        captures
+    }
+
+    /// Index a dynamic array at `stk_idx` by each of the indices in turn
+    fn compile_dyn_index(
+        &mut self,
+        mut stk_idx: StkIdx,
+        indices: &[Expression],
+    ) -> Result<StkIdx, Box<dyn Error>> {
+        for index in indices {
+            let index = self.compile_expr(index)?;
+            self.add_copy_inst(stk_idx);
+            self.add_copy_inst(index);
+            self.add_inst(OpCode::Index, 0);
+            self.target_stack.pop();
+            stk_idx = self.stack_top();
+        }
+        Ok(stk_idx)
+    }
+
+    /// Whether `return name(args)` calls the function being compiled, so it can reuse its frame
+    fn is_tail_call(&self, name: &str, args: usize) -> bool {
+        let Some((tail_fn, arity)) = &self.tail_fn else {
+            return false;
+        };
+        // A variable holding a function value shadows the function of the same name
+        let shadowed = self
+            .target_stack
+            .iter()
+            .any(|target| matches!(target, Target::Local(id, Some(TypeDecl::Func)) if id == name));
+        tail_fn == name && *arity == args && !shadowed
+    }
+
+    fn compile_tail_call(&mut self, args: &[Expression]) -> Result<(), Box<dyn Error>> {
+        let args = args
+            .iter()
+            .map(|arg| self.compile_expr(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        for arg in &args {
+            self.add_copy_inst(*arg);
+        }
+        self.add_inst(OpCode::TailCall, args.len() as u16);
+        // Like a `Ret`, nothing after this runs, but the result takes the place of the arguments
+        self.target_stack
+            .truncate(self.target_stack.len() - args.len());
+        self.target_stack.push(Target::Temp);
+        Ok(())
+    }
+
+    fn compile_stmts_or_unit(&mut self, stmts: &Statements) -> Result<StkIdx, Box<dyn Error>> {
+        Ok(self
+            .compile_stmts(stmts)?
+            .unwrap_or_else(|| self.add_unit_inst()))
+    }
+
+    /// Compile a branch of `if`, leaving its value on the stack top.
+    ///
+    /// The value of a branch ending with a variable is the slot of the variable, which may not be
+    /// the stack top.
+    fn compile_branch(&mut self, stmts: &Statements) -> Result<StkIdx, Box<dyn Error>> {
+        let res = self.compile_stmts_or_unit(stmts)?;
+        if res.0 + 1 != self.target_stack.len() {
+            self.add_copy_inst(res);
+        }
+        Ok(self.stack_top())
     }
 
+    /// Compile a program, whose toplevel statements are the body of a synthetic `main`.
+    ///
+    /// A program can define `main` instead, taking no arguments or an `Array<str>` of the
+    /// arguments of the script. Then the toplevel can only have function definitions, since
+    /// nothing else would run.
     fn compile(&mut self, stmts: &Statements) -> Result<(), Box<dyn std::error::Error>> {
         let name = "main";
-        self.compile_stmts_or_zero(stmts)?;
-        self.add_fn(name.to_string(), &[], false);
+        let entry = stmts.iter().find_map(|stmt| match stmt {
+            Statement::FnDef {
+                name: fn_name,
+                args,
+                cofn,
+                ..
+            } if **fn_name == name => Some((fn_name, args, cofn)),
+            _ => None,
+        });
+        let Some((fn_name, args, cofn)) = entry else {
+            // `main` returns the stack top, which may not be the last value if it is a variable
+            let res = self.compile_stmts_or_unit(stmts)?;
+            if res.0 + 1 != self.target_stack.len() {
+                self.add_copy_inst(res);
+            }
+            self.add_fn(name.to_string(), &[], vec![], false, true)?;
+            return Ok(());
+        };
+
+        let location = format!(
+            "{}:{}: ",
+            fn_name.location_line(),
+            fn_name.get_utf8_column()
+        );
+        if *cofn {
+            return Err(format!("{location}Function main can't be a coroutine").into());
+        }
+        let valid_args = match &args[..] {
+            [] => true,
+            [(_, TypeDecl::DynArray(td))] => **td == TypeDecl::Str,
+            _ => false,
+        };
+        if !valid_args {
+            return Err(format!(
+                "{location}Function main should take no arguments or an Array<str> of arguments"
+            )
+            .into());
+        }
+        Self::check_fn_defs_only(stmts, "with a main function")?;
+        self.compile_stmts(stmts)?;
+        // `main` is the entry point whether it is marked `export` or not
+        if let Some(main) = self.funcs.get_mut(name).and_then(Rc::get_mut) {
+            main.export = true;
+        }
         Ok(())
     }
 
-    fn disasm(&self, writer: &mut impl Write) -> std::io::Result<()> {
-        for (name, fn_def) in &self.funcs {
-            if fn_def.cofn {
-                writeln!(writer, "Coroutine {name:?}:")?;
-            } else {
-                writeln!(writer, "Function {name:?}:")?;
+    /// Compile a library, which consists only of function definitions, without a synthetic `main`.
+    fn compile_lib(&mut self, stmts: &Statements) -> Result<(), Box<dyn std::error::Error>> {
+        Self::check_fn_defs_only(stmts, "in a library")?;
+        self.compile_stmts(stmts)?;
+        Ok(())
+    }
+
+    fn check_fn_defs_only(stmts: &Statements, context: &str) -> Result<(), Box<dyn Error>> {
+        for stmt in stmts {
+            if !matches!(stmt, Statement::FnDef { .. } | Statement::Comment) {
+                let location = stmt
+                    .span()
+                    .map(|span| format!("{}:{}: ", span.location_line(), span.get_utf8_column()))
+                    .unwrap_or_default();
+                return Err(
+                    format!("{location}Only function definitions are allowed {context}").into(),
+                );
             }
-            fn_def.disasm(writer)?;
         }
         Ok(())
     }
+
+    /// Compile the top-level statements into `main` on top of the state left by the previous calls.
+    ///
+    /// Functions compiled so far are kept, and redefining a function replaces the previous
+    /// definition by name. A name keeps its index in `fn_names`, and the `ByteCode` built for
+    /// each input links the index to the latest definition, so every caller compiled earlier
+    /// calls the new one.
+    /// Variables stay in the stack of `main`, and redefining a variable shadows the previous one.
+    /// The literal pool of `main` is carried over, so literals are deduped across calls.
+    /// On error, the state is rolled back to before the call.
+    ///
+    /// Returns the stack index of the value of the last expression.
+    fn compile_incremental(
+        &mut self,
+        stmts: &Statements,
+    ) -> Result<Option<StkIdx>, Box<dyn Error>> {
+        let target_stack = self.target_stack.clone();
+        let funcs = self.funcs.clone();
+        let fn_names = self.fn_names.len();
+        let globals = self.globals.clone();
+        // An error in a function body returns before the function restores these
+        let locals = self.locals.clone();
+        let ret_type = self.ret_type.clone();
+        let tail_fn = self.tail_fn.clone();
+        let closure = self.closure.clone();
+        if let Some(main) = self.funcs.get("main") {
+            self.literals = main.literals.clone();
+            self.literal_index = self
+                .literals
+                .iter()
+                .enumerate()
+                .filter_map(|(i, lit)| Some((lit.literal_key()?, i as u16)))
+                .collect();
+        }
+        let res = self.compile_stmts(stmts).and_then(|res| {
+            self.add_fn("main".to_string(), &[], vec![], false, true)?;
+            Ok(res)
+        });
+        if res.is_err() {
+            self.literals.clear();
+            self.literal_index.clear();
+            self.instructions.clear();
+            self.lines.clear();
+            self.loop_stack.clear();
+            self.target_stack = target_stack;
+            self.funcs = funcs;
+            for name in self.fn_names.drain(fn_names..) {
+                self.fn_ids.remove(&name);
+            }
+            self.operand_overflow = None;
+            self.fn_stack.clear();
+            self.globals = globals;
+            self.locals = locals;
+            self.ret_type = ret_type;
+            self.tail_fn = tail_fn;
+            self.closure = closure;
+        }
+        res
+    }
+
+    /// Drop the temporary values from the top of the stack, keeping the variables.
+    /// Returns the new stack length.
+    fn truncate_temps(&mut self) -> usize {
+        let locals = self
+            .target_stack
+            .iter()
+            .rposition(|target| matches!(target, Target::Local(..)))
+            .map_or(0, |pos| pos + 1);
+        self.target_stack.truncate(locals);
+        locals
+    }
+
+    /// Build a `ByteCode` directly from the compiled functions, without serialization.
+    fn bytecode(&self) -> ByteCode {
+        ByteCode::link(
+            self.funcs
+                .iter()
+                .map(|(name, func)| (name.clone(), func.clone())),
+            self.fn_names.clone(),
+        )
+    }
+
+    fn disasm(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        disasm_funcs(
+            self.funcs.iter().map(|(name, func)| (name, func.as_ref())),
+            &self.fn_names,
+            writer,
+        )
+    }
+}
+
+fn disasm_funcs<'a>(
+    funcs: impl Iterator<Item = (&'a String, &'a FnByteCode)>,
+    fn_names: &[String],
+    writer: &mut impl Write,
+) -> std::io::Result<()> {
+    let mut funcs: Vec<_> = funcs.collect();
+    funcs.sort_unstable_by_key(|(name, _)| *name);
+    for (name, fn_def) in funcs {
+        let export = if fn_def.export { "Exported " } else { "" };
+        if fn_def.cofn {
+            writeln!(writer, "{export}Coroutine {name:?}:")?;
+        } else {
+            writeln!(writer, "{export}Function {name:?}:")?;
+        }
+        fn_def.disasm(fn_names, writer)?;
+    }
+    Ok(())
+}
+
+/// Format an error as `file:line:col: message` followed by the offending source line.
+fn render_error(source_file: Option<&str>, source: &str, span: Span, msg: impl Display) -> String {
+    let line = span.location_line();
+    let column = span.get_utf8_column();
+    let file = source_file
+        .map(|file| format!("{file}:"))
+        .unwrap_or_default();
+    format!(
+        "{file}{line}:{column}: {msg}\n{}",
+        source_snippet(source, line, column)
+    )
+}
+
+fn parse_error_msg(e: &nom::error::Error<Span>) -> String {
+    if e.code == nom::error::ErrorKind::TooLarge {
+        return format!(
+            "[{}] Syntax error: nested deeper than the limit of {}",
+            ErrorCode::E004,
+            max_expr_depth()
+        );
+    }
+    format!("[{}] Syntax error ({:?})", ErrorCode::E004, e.code)
 }
 
 fn write_program(
@@ -1089,51 +2330,53 @@ fn write_program(
     args: &Args,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut compiler = Compiler::new();
-    let stmts = statements_finish(Span::new(source)).map_err(|e| {
-        format!(
-            "{}:{}:{}: {}",
-            source_file,
-            e.input.location_line(),
-            e.input.get_utf8_column(),
-            e
-        )
-    })?;
+    compiler.max_functions = args.max_functions;
+    let mut stmts = statements_finish(Span::new(source))
+        .map_err(|e| render_error(Some(source_file), source, e.input, parse_error_msg(&e)))?;
 
     if args.show_ast {
         // dprintln!("AST: {stmts:#?}");
         println!("AST: {stmts:#?}");
     }
 
-    match type_check(&stmts, &mut TypeCheckContext::new()) {
+    let mut tc_ctx = TypeCheckContext::new();
+    match type_check(&stmts, &mut tc_ctx) {
         Ok(_) => println!("Typecheck Ok"),
-        Err(e) => {
-            return Err(format!(
-                "{}:{}:{}: {}",
-                source_file,
-                e.span.location_line(),
-                e.span.get_utf8_column(),
-                e
-            )
-            .into())
-        }
+        Err(e) => return Err(render_error(Some(source_file), source, e.span, &e).into()),
+    }
+
+    if args.dump_symbols {
+        tc_ctx.dump_symbols(&mut std::io::stdout())?;
     }
 
     if matches!(args.run_mode, RunMode::TypeCheck) {
         return Ok(());
     }
 
-    compiler.compile(&stmts)?;
+    if args.optimize || args.fast_math {
+        normalize(&mut stmts);
+        if args.fast_math {
+            reassociate(&mut stmts);
+        }
+        fold_constants(&mut stmts);
+    }
+
+    if args.lib {
+        compiler.compile_lib(&stmts)?;
+    } else {
+        compiler.compile(&stmts)?;
+    }
 
     if args.disasm {
         compiler.disasm(&mut std::io::stdout())?;
     }
 
     compiler.write_funcs(writer)?;
-    println!(
-        "Writeen {} literals and {} instructions to {out_file:?}",
-        compiler.literals.len(),
-        compiler.instructions.len()
-    );
+    // Every function has its own literal pool, which `add_fn` moved out of the compiler
+    let (literals, instructions) = compiler.funcs.values().fold((0, 0), |(l, i), f| {
+        (l + f.literals.len(), i + f.instructions.len())
+    });
+    println!("Writeen {literals} literals and {instructions} instructions to {out_file:?}");
     Ok(())
 }
 
@@ -1143,45 +2386,130 @@ enum FnDef {
 }
 
 pub struct ByteCode {
-    funcs: HashMap<String, FnDef>,
+    /// Every function by index. The ones `Call` refers to by index come first.
+    funcs: Vec<(String, FnDef)>,
+    /// Index of `funcs` by name, for the calls through function values and the entry points
+    fn_index: HashMap<String, usize>,
+    /// Names of the functions `Call` refers to by index, as compiled
+    fn_names: Vec<String>,
+}
+
+fn native_functions() -> HashMap<String, FnDef> {
+    standard_functions()
+        .into_iter()
+        .filter_map(|(name, f)| {
+            if let FnDecl::Native(f) = f {
+                Some((name, FnDef::Native(f)))
+            } else {
+                None
+            }
+        })
+        .collect()
 }
 
 impl ByteCode {
-    fn new() -> Self {
+    /// Gather the compiled functions and the natives, placing the functions named by `fn_names`
+    /// at the indices `Call` refers to.
+    fn link(
+        user_funcs: impl Iterator<Item = (String, Rc<FnByteCode>)>,
+        fn_names: Vec<String>,
+    ) -> Self {
+        let mut defs = native_functions();
+        defs.extend(user_funcs.map(|(name, func)| (name, FnDef::User(func))));
+        let mut funcs: Vec<_> = fn_names
+            .iter()
+            .map(|name| {
+                // A native which the host registered when compiling may be missing now
+                let fn_def = defs
+                    .remove(name)
+                    .unwrap_or_else(|| undefined_function(name));
+                (name.clone(), fn_def)
+            })
+            .collect();
+        funcs.extend(defs);
+        let fn_index = funcs
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| (name.clone(), i))
+            .collect();
         Self {
-            funcs: HashMap::new(),
+            funcs,
+            fn_index,
+            fn_names,
         }
     }
 
-    fn read_funcs(&mut self, reader: &mut impl Read) -> std::io::Result<()> {
+    fn get(&self, name: &str) -> Option<&FnDef> {
+        self.fn_index.get(name).map(|i| &self.funcs[*i].1)
+    }
+
+    /// Write the instructions of the functions compiled from the source, without the natives.
+    pub fn disasm(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        disasm_funcs(
+            self.funcs.iter().filter_map(|(name, fn_def)| match fn_def {
+                FnDef::User(func) => Some((name, func.as_ref())),
+                FnDef::Native(_) => None,
+            }),
+            &self.fn_names,
+            writer,
+        )
+    }
+
+    fn read_funcs(reader: &mut impl Read) -> std::io::Result<Self> {
         let num_funcs = deserialize_size(reader)?;
-        let mut funcs: HashMap<_, _> = standard_functions()
-            .into_iter()
-            .filter_map(|(name, f)| {
-                if let FnDecl::Native(f) = f {
-                    Some((name, FnDef::Native(f)))
-                } else {
-                    None
-                }
-            })
-            .collect();
+        let mut funcs = Vec::with_capacity(num_funcs);
         for _ in 0..num_funcs {
             let name = deserialize_str(reader)?;
-            funcs.insert(name, FnDef::User(Rc::new(FnByteCode::deserialize(reader)?)));
+            funcs.push((name, Rc::new(FnByteCode::deserialize(reader)?)));
         }
-        self.funcs = funcs;
-        Ok(())
+        let num_names = deserialize_size(reader)?;
+        let fn_names = (0..num_names)
+            .map(|_| deserialize_str(reader))
+            .collect::<std::io::Result<_>>()?;
+        Ok(Self::link(funcs.into_iter(), fn_names))
     }
 }
 
+/// A placeholder for a function which is called by name but not defined, which fails
+/// only if it is actually called
+fn undefined_function(name: &str) -> FnDef {
+    let name = name.to_string();
+    FnDef::Native(NativeFn {
+        args: vec![],
+        ret_type: TypeDecl::Any,
+        code: Box::new(move |_| Err(RuntimeError::UndefinedFunction(name.clone()))),
+    })
+}
+
 pub enum YieldResult {
     Finished(Value),
     Suspend(Value),
+    /// Stopped before the first instruction of a line in `Vm::set_breakpoint`
+    Breakpoint(u32),
+}
+
+/// Result of executing a single instruction by `Vm::step`
+pub enum StepResult {
+    Running,
+    Yielded(Value),
+    Finished(Value),
+}
+
+impl From<YieldResult> for StepResult {
+    fn from(res: YieldResult) -> Self {
+        match res {
+            YieldResult::Finished(value) => Self::Finished(value),
+            YieldResult::Suspend(value) => Self::Yielded(value),
+            YieldResult::Breakpoint(_) => unreachable!("Only interpret stops at breakpoints"),
+        }
+    }
 }
 
 struct StackFrame {
     fn_def: Rc<FnByteCode>,
-    args: usize,
+    /// Values on the stack of the caller which the returned value replaces: the arguments,
+    /// and the function value if it was called through one
+    caller_slots: usize,
     stack: Vec<Value>,
     ip: usize,
 }
@@ -1190,7 +2518,7 @@ impl StackFrame {
     fn new(fn_def: Rc<FnByteCode>, args: Vec<Value>) -> Self {
         Self {
             fn_def,
-            args: args.len(),
+            caller_slots: args.len(),
             stack: args,
             ip: 0,
         }
@@ -1203,9 +2531,35 @@ impl StackFrame {
     }
 }
 
+/// Default of `Vm::max_stack_frames`
+pub const DEFAULT_MAX_STACK_FRAMES: usize = 1024;
+
+/// Maximum depth of function values called by natives like `map`, one inside another
+const MAX_CALLBACK_DEPTH: usize = 64;
+
 pub struct Vm {
     bytecode: Rc<ByteCode>,
     stack_frames: Vec<StackFrame>,
+    /// Maximum depth of nested calls, to stop runaway recursion
+    pub max_stack_frames: usize,
+    /// Source lines executed so far, shared with the coroutines started by this `Vm`
+    coverage: Option<Rc<RefCell<BTreeSet<u32>>>>,
+    /// Values of the toplevel variables by global slot, shared with the coroutines
+    globals: Rc<RefCell<Vec<Value>>>,
+    /// Maximum number of instructions to execute, or `None` for no limit
+    gas_limit: Option<u64>,
+    /// Instructions executed so far, shared with the coroutines
+    gas_used: Rc<Cell<u64>>,
+    /// Bytes the print natives can still write, shared with the coroutines
+    output: Rc<OutputBudget>,
+    /// Source lines where `interpret` stops. The coroutines started by the `Vm` don't stop.
+    breakpoints: HashSet<u32>,
+    /// Whether `interpret` stopped at a breakpoint, which it passes when resumed
+    at_breakpoint: bool,
+    /// Number of function values called by natives, one inside another, to run this `Vm`
+    callback_depth: usize,
+    /// Frames of the `Vm`s whose natives called this one, which count toward `max_stack_frames`
+    outer_frames: usize,
 }
 
 impl std::fmt::Debug for Vm {
@@ -1219,7 +2573,143 @@ impl Vm {
         Self {
             bytecode,
             stack_frames: vec![],
+            max_stack_frames: DEFAULT_MAX_STACK_FRAMES,
+            coverage: None,
+            globals: Rc::new(RefCell::new(vec![])),
+            gas_limit: None,
+            gas_used: Rc::new(Cell::new(0)),
+            output: Rc::new(OutputBudget::default()),
+            breakpoints: HashSet::new(),
+            at_breakpoint: false,
+            callback_depth: 0,
+            outer_frames: 0,
+        }
+    }
+
+    /// Stop with a runtime error once `limit` instructions have been executed, counting the
+    /// ones executed by the coroutines started by this `Vm`.
+    pub fn with_gas(mut self, limit: u64) -> Self {
+        self.gas_limit = Some(limit);
+        self.gas_used.set(0);
+        self
+    }
+
+    /// Stop with a runtime error once the print natives of this `Vm` and the coroutines started
+    /// by it would write more than `limit` bytes. The output beyond the limit is not written.
+    pub fn with_output_limit(mut self, limit: usize) -> Self {
+        self.output = Rc::new(OutputBudget::new(Some(limit)));
+        self
+    }
+
+    /// Bytes written so far by the print natives of this `Vm` and its coroutines
+    pub fn output_written(&self) -> usize {
+        self.output.written()
+    }
+
+    /// Instructions executed so far by this `Vm` and its coroutines. They are only counted with
+    /// a limit set by `with_gas`.
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used.get()
+    }
+
+    /// Start recording the source lines executed by this `Vm` and its coroutines.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Rc::new(RefCell::new(BTreeSet::new())));
+    }
+
+    /// Write the number of executed lines out of the lines with instructions, and the lines
+    /// which were not executed. Blank and comment lines have no instructions, so they don't count.
+    pub fn write_coverage(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let Some(coverage) = &self.coverage else {
+            return Ok(());
+        };
+        let executed = coverage.borrow();
+        let lines: BTreeSet<u32> = self
+            .bytecode
+            .funcs
+            .iter()
+            .filter_map(|(_, func)| match func {
+                FnDef::User(user) => Some(user.lines.iter().copied().filter(|line| *line != 0)),
+                FnDef::Native(_) => None,
+            })
+            .flatten()
+            .collect();
+        let missed: Vec<String> = lines
+            .difference(&executed)
+            .map(|line| line.to_string())
+            .collect();
+        let hit = lines.len() - missed.len();
+        let percent = if lines.is_empty() {
+            100.
+        } else {
+            hit as f64 * 100. / lines.len() as f64
+        };
+        writeln!(
+            writer,
+            "Coverage: {hit}/{} lines ({percent:.1}%)",
+            lines.len()
+        )?;
+        if !missed.is_empty() {
+            writeln!(writer, "Not executed: {}", missed.join(", "))?;
         }
+        Ok(())
+    }
+
+    /// Write the state of the toplevel function suspended by a `yield`, so a `Vm` running the
+    /// same bytecode can resume from it with `read_snapshot`.
+    ///
+    /// Numbers, strings, the unit, function values named by a function and arrays of them can
+    /// be saved, but coroutines and closures can't, as `Value::is_persistable` defines.
+    /// Arrays are saved by value, so variables sharing an array get copies of it when restored.
+    pub fn write_snapshot(&self, writer: &mut impl Write) -> Result<(), Box<dyn Error>> {
+        let [frame] = &self.stack_frames[..] else {
+            return Err(
+                "Snapshot can only be taken while the toplevel function is suspended".into(),
+            );
+        };
+        let globals = self.globals.borrow();
+        if let Some(value) = frame
+            .stack
+            .iter()
+            .chain(globals.iter())
+            .find(|value| !value.is_persistable())
+        {
+            return Err(format!(
+                "A variable holds a {}, which can't be saved in a snapshot",
+                value.type_name()
+            )
+            .into());
+        }
+        serialize_size(frame.ip, writer)?;
+        for values in [&frame.stack, &*globals] {
+            serialize_size(values.len(), writer)?;
+            for value in values {
+                value.serialize(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore the toplevel function initialized by `init_fn` to the state written by
+    /// `write_snapshot`.
+    pub fn read_snapshot(&mut self, reader: &mut impl Read) -> Result<(), Box<dyn Error>> {
+        let [frame] = &mut self.stack_frames[..] else {
+            return Err("Snapshot can only be restored to a toplevel function".into());
+        };
+        let ip = deserialize_size(reader)?;
+        if frame.fn_def.instructions.len() < ip {
+            return Err(format!("Snapshot instruction pointer {ip} is out of the function").into());
+        }
+        let mut read_values = || -> std::io::Result<Vec<Value>> {
+            let len = deserialize_size(reader)?;
+            (0..len).map(|_| Value::deserialize(reader)).collect()
+        };
+        let stack = read_values()?;
+        let globals = read_values()?;
+        frame.ip = ip;
+        frame.stack = stack;
+        *self.globals.borrow_mut() = globals;
+        Ok(())
     }
 
     fn top(&self) -> Result<&StackFrame, String> {
@@ -1234,20 +2724,37 @@ impl Vm {
             .ok_or_else(|| "Stack frame underflow".to_string())
     }
 
+    /// Clear the state of the previous run so the same bytecode can be run again from scratch.
+    ///
+    /// The stack frames, the toplevel variables, the gas used and the recorded coverage are all
+    /// cleared, while the limits, the breakpoints and whether coverage is recorded are kept.
+    /// The coroutines started by the previous run keep the state they shared with it.
+    pub fn reset(&mut self) {
+        self.stack_frames.clear();
+        self.globals = Rc::new(RefCell::new(vec![]));
+        self.gas_used = Rc::new(Cell::new(0));
+        self.output = Rc::new(OutputBudget::new(self.output.limit()));
+        self.at_breakpoint = false;
+        self.callback_depth = 0;
+        self.outer_frames = 0;
+        if self.coverage.is_some() {
+            self.enable_coverage();
+        }
+    }
+
     #[allow(dead_code)]
-    fn run_fn(
+    pub fn run_fn(
         &mut self,
         fn_name: &str,
         args: &[Value],
     ) -> Result<Value, Box<dyn std::error::Error>> {
         let fn_def = self
             .bytecode
-            .funcs
             .get(fn_name)
             .ok_or_else(|| format!("Function {fn_name:?} was not found"))?;
         let fn_def = match fn_def {
-            FnDef::User(user) => user.clone(),
-            FnDef::Native(n) => return Ok((*n.code)(args)),
+            FnDef::User(user) => Self::entry_point(fn_name, user)?,
+            FnDef::Native(n) => return Ok((*n.code)(args)?),
         };
         self.stack_frames
             .push(StackFrame::new(fn_def, args.to_vec()));
@@ -1255,6 +2762,9 @@ impl Vm {
         match self.interpret()? {
             YieldResult::Finished(val) => Ok(val),
             YieldResult::Suspend(_) => Err("Yielded at toplevel".into()),
+            YieldResult::Breakpoint(line) => {
+                Err(format!("Stopped at breakpoint at line {line}").into())
+            }
         }
     }
 
@@ -1265,11 +2775,10 @@ impl Vm {
     ) -> Result<(), Box<dyn std::error::Error>> {
         let fn_def = self
             .bytecode
-            .funcs
             .get(fn_name)
             .ok_or_else(|| format!("Function {fn_name:?} was not found"))?;
         let fn_def = match fn_def {
-            FnDef::User(user) => user.clone(),
+            FnDef::User(user) => Self::entry_point(fn_name, user)?,
             FnDef::Native(_) => {
                 return Err(
                     "Native function cannot be called as a coroutine. Use `run_fn` instead.".into(),
@@ -1281,6 +2790,35 @@ impl Vm {
         Ok(())
     }
 
+    /// Initialize `main` to run by `interpret`, passing `args` as an array of strings if `main`
+    /// takes it.
+    pub fn init_main(&mut self, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let arity = match self.bytecode.get("main") {
+            Some(FnDef::User(user)) => user.args.len(),
+            _ => 0,
+        };
+        match arity {
+            0 => self.init_fn("main", &[]),
+            1 => {
+                let args = args.iter().map(|arg| Value::str(arg)).collect();
+                self.init_fn("main", &[Value::array(args)])
+            }
+            _ => Err(
+                format!("Function main takes {arity} arguments, but it should take 0 or 1").into(),
+            ),
+        }
+    }
+
+    fn entry_point(
+        fn_name: &str,
+        user: &Rc<FnByteCode>,
+    ) -> Result<Rc<FnByteCode>, Box<dyn std::error::Error>> {
+        if !user.export {
+            return Err(format!("Function {fn_name:?} is not exported").into());
+        }
+        Ok(user.clone())
+    }
+
     fn return_fn(&mut self, stack_pos: u16) -> Result<Option<YieldResult>, Box<dyn Error>> {
         let top_frame = self
             .stack_frames
@@ -1291,7 +2829,7 @@ impl Vm {
             .get(top_frame.stack.len() - stack_pos as usize - 1)
             .ok_or_else(|| "Stack underflow at last".to_string())?
             .clone();
-        let args = top_frame.args;
+        let caller_slots = top_frame.caller_slots;
 
         if self.stack_frames.is_empty() {
             return Ok(Some(YieldResult::Finished(res)));
@@ -1300,268 +2838,812 @@ impl Vm {
         dprintln!("Return {}", res);
 
         let stack = &mut self.top_mut()?.stack;
-        stack.resize(stack.len() - args - 1, Value::F64(0.));
+        stack.resize(stack.len() - caller_slots, Value::F64(0.));
         stack.push(res);
         self.top_mut()?.ip += 1;
         Ok(None)
     }
 
-    pub fn interpret(&mut self) -> Result<YieldResult, Box<dyn std::error::Error>> {
+    pub fn interpret(&mut self) -> Result<YieldResult, VmError> {
         loop {
-            let instruction = if let Some(instruction) = self.top()?.inst() {
-                instruction
-            } else {
-                if let Some(res) = self.return_fn(0)? {
-                    return Ok(res);
+            let resumed = std::mem::take(&mut self.at_breakpoint);
+            if let Some(line) = self.breakpoint().filter(|_| !resumed) {
+                self.at_breakpoint = true;
+                return Ok(YieldResult::Breakpoint(line));
+            }
+            if let Some(limit) = self.gas_limit {
+                if limit <= self.gas_used.get() {
+                    return Err(VmError {
+                        line: self.current_line(),
+                        error: RuntimeError::GasExhausted(limit).into(),
+                    });
                 }
-                continue;
-            };
-            // debug instruction
-            // println!("- instruction: {:?}", instruction,);
+                self.gas_used.set(self.gas_used.get() + 1);
+            }
+            let res = self.step().map_err(|error| VmError {
+                line: self.current_line(),
+                error,
+            });
+            match res? {
+                StepResult::Running => {}
+                StepResult::Yielded(value) => return Ok(YieldResult::Suspend(value)),
+                StepResult::Finished(value) => return Ok(YieldResult::Finished(value)),
+            }
+        }
+    }
 
-            match instruction.op {
-                OpCode::LoadLiteral => {
-                    let stack_frame = self.top_mut()?;
-                    stack_frame
-                        .stack
-                        .push(stack_frame.fn_def.literals[instruction.arg0 as usize].clone())
-                }
-                OpCode::Store => {
-                    let stack = &mut self.top_mut()?.stack;
-                    let idx = stack.len() - instruction.arg0 as usize - 1;
-                    let value = stack.pop().expect("Store needs an argument");
-                    stack[idx] = value;
-                }
-                OpCode::IndexStore => {
-                    let stack = &mut self.top_mut()?.stack;
-                    let stack_length = stack.len();
-                    let array_index = stack.pop().expect("IndexStore needs an array index");
-                    // TODO: index should be i64 (or usize)
-                    let array_index = if let Value::F64(index) = array_index {
-                        index as usize
-                    } else if let Value::I64(index) = array_index {
-                        index as usize
-                    } else {
-                        return Err("IndexStore needs an array index".into());
-                    };
-                    let idx = stack_length - instruction.arg0 as usize + array_index - 1;
-                    let value = stack.pop().expect("IndexStore needs an argument");
-                    stack[idx] = value;
-                }
-                OpCode::Copy => {
-                    let stack = &mut self.top_mut()?.stack;
-                    stack.push(stack[stack.len() - instruction.arg0 as usize - 1].clone());
-                }
-                OpCode::IndexCopy => {
-                    let stack = &mut self.top_mut()?.stack;
-                    let stack_length = stack.len();
-                    let array_index = stack.pop().expect("IndexCopy needs an array index");
-                    // TODO: index should be i64 (or usize)
-                    let array_index = if let Value::F64(index) = array_index {
-                        index as usize
-                    } else if let Value::I64(index) = array_index {
-                        index as usize
-                    } else {
-                        return Err("IndexCopy needs an array index".into());
-                    };
-                    let idx = stack_length - instruction.arg0 as usize + array_index - 1;
-                    stack.push(stack[idx].clone());
+    /// Resume the coroutine, sending `value` in as the value of the `yield` it is suspended at.
+    ///
+    /// The value is discarded if the coroutine has not started yet.
+    pub fn resume(&mut self, value: Value) -> Result<YieldResult, VmError> {
+        if let Some(frame) = self.stack_frames.last_mut() {
+            let suspended = frame
+                .ip
+                .checked_sub(1)
+                .is_some_and(|ip| frame.fn_def.instructions[ip].op == OpCode::Yield);
+            if let (true, Some(top)) = (suspended, frame.stack.last_mut()) {
+                *top = value;
+            }
+        }
+        self.interpret()
+    }
+
+    /// Stop `interpret` before the first instruction of the source line.
+    pub fn set_breakpoint(&mut self, line: u32) {
+        self.breakpoints.insert(line);
+    }
+
+    /// Remove the breakpoint, and return whether it was set.
+    pub fn delete_breakpoint(&mut self, line: u32) -> bool {
+        self.breakpoints.remove(&line)
+    }
+
+    /// Lines with a breakpoint in ascending order
+    pub fn breakpoints(&self) -> Vec<u32> {
+        let mut lines: Vec<u32> = self.breakpoints.iter().copied().collect();
+        lines.sort_unstable();
+        lines
+    }
+
+    /// The line of the next instruction if it starts a line with a breakpoint
+    fn breakpoint(&self) -> Option<u32> {
+        if self.breakpoints.is_empty() {
+            return None;
+        }
+        let line = self.current_line()?;
+        let frame = self.stack_frames.last()?;
+        let starts_line = frame.ip == 0 || frame.fn_def.lines.get(frame.ip - 1) != Some(&line);
+        (starts_line && self.breakpoints.contains(&line)).then_some(line)
+    }
+
+    /// Source line of the instruction to be executed next, if known
+    pub fn current_line(&self) -> Option<u32> {
+        let frame = self.stack_frames.last()?;
+        frame
+            .fn_def
+            .lines
+            .get(frame.ip)
+            .copied()
+            .filter(|line| *line != 0)
+    }
+
+    /// Whether the coroutine has run to completion (or was never started).
+    pub fn is_finished(&self) -> bool {
+        self.stack_frames.is_empty()
+    }
+
+    /// Resume the coroutine up to `n` times and collect the yielded values.
+    ///
+    /// If the coroutine finishes before yielding `n` values, only the values yielded
+    /// so far are returned.
+    pub fn take(&mut self, n: usize) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        let mut values = vec![];
+        while values.len() < n && !self.is_finished() {
+            match self.interpret()? {
+                YieldResult::Suspend(value) => values.push(value),
+                YieldResult::Finished(_) | YieldResult::Breakpoint(_) => break,
+            }
+        }
+        Ok(values)
+    }
+
+    /// Resume the coroutine and discard the first `n` yielded values.
+    /// Returns the number of values actually discarded.
+    pub fn skip(&mut self, n: usize) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut skipped = 0;
+        while skipped < n && !self.is_finished() {
+            match self.interpret()? {
+                YieldResult::Suspend(_) => skipped += 1,
+                YieldResult::Finished(_) | YieldResult::Breakpoint(_) => break,
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Execute exactly one instruction and return the control.
+    pub fn step(&mut self) -> Result<StepResult, Box<dyn std::error::Error>> {
+        if let Some((coverage, line)) = self.coverage.as_ref().zip(self.current_line()) {
+            coverage.borrow_mut().insert(line);
+        }
+        let instruction = if let Some(instruction) = self.top()?.inst() {
+            instruction
+        } else {
+            if let Some(res) = self.return_fn(0)? {
+                return Ok(res.into());
+            }
+            return Ok(StepResult::Running);
+        };
+        // debug instruction
+        // println!("- instruction: {:?}", instruction,);
+
+        match instruction.op {
+            OpCode::LoadLiteral => {
+                let stack_frame = self.top_mut()?;
+                stack_frame
+                    .stack
+                    .push(stack_frame.fn_def.literals[instruction.arg0 as usize].clone())
+            }
+            OpCode::Store => {
+                let stack = &mut self.top_mut()?.stack;
+                let idx = stack.len() - instruction.arg0 as usize - 1;
+                let value = stack.pop().expect("Store needs an argument");
+                stack[idx] = value;
+            }
+            OpCode::IndexStore => {
+                let stack = &mut self.top_mut()?.stack;
+                let stack_length = stack.len();
+                let array_index = stack.pop().expect("IndexStore needs an array index");
+                // TODO: index should be i64 (or usize)
+                let array_index = if let Value::F64(index) = array_index {
+                    index as usize
+                } else if let Value::I64(index) = array_index {
+                    index as usize
+                } else {
+                    return Err(RuntimeError::TypeError {
+                        expected: "i64",
+                        got: array_index.type_name(),
+                    }
+                    .into());
+                };
+                let idx = stack_length - instruction.arg0 as usize + array_index - 1;
+                let value = stack.pop().expect("IndexStore needs an argument");
+                stack[idx] = value;
+            }
+            OpCode::Copy => {
+                let stack = &mut self.top_mut()?.stack;
+                stack.push(stack[stack.len() - instruction.arg0 as usize - 1].clone());
+            }
+            OpCode::IndexCopy => {
+                let stack = &mut self.top_mut()?.stack;
+                let stack_length = stack.len();
+                let array_index = stack.pop().expect("IndexCopy needs an array index");
+                // TODO: index should be i64 (or usize)
+                let array_index = if let Value::F64(index) = array_index {
+                    index as usize
+                } else if let Value::I64(index) = array_index {
+                    index as usize
+                } else {
+                    return Err(RuntimeError::TypeError {
+                        expected: "i64",
+                        got: array_index.type_name(),
+                    }
+                    .into());
+                };
+                let idx = stack_length - instruction.arg0 as usize + array_index - 1;
+                stack.push(stack[idx].clone());
+            }
+            OpCode::Dup => {
+                let stack = &mut self.top_mut()?.stack;
+                let top = stack.last().unwrap().clone();
+                stack.extend((0..instruction.arg0).map(|_| top.clone()));
+            }
+            OpCode::Add => Self::interpret_bin_op_str(
+                &mut self.top_mut()?.stack,
+                instruction.op,
+                |lhs, rhs| lhs + rhs,
+                i64::checked_add,
+                |lhs, rhs| {
+                    // The lhs is unshared by now, so its buffer is extended in place
+                    lhs.push_str(rhs);
+                    true
+                },
+            )?,
+            OpCode::Sub => Self::interpret_bin_op(
+                &mut self.top_mut()?.stack,
+                instruction.op,
+                |lhs, rhs| lhs - rhs,
+                i64::checked_sub,
+            )?,
+            OpCode::Mul => {
+                let stack = &mut self.top_mut()?.stack;
+                match &stack[..] {
+                    [.., Value::Str(_), Value::F64(_) | Value::I64(_)]
+                    | [.., Value::F64(_) | Value::I64(_), Value::Str(_)] => {
+                        Self::interpret_str_repeat(stack)?
+                    }
+                    _ => Self::interpret_bin_op(
+                        stack,
+                        instruction.op,
+                        |lhs, rhs| lhs * rhs,
+                        i64::checked_mul,
+                    )?,
                 }
-                OpCode::Dup => {
-                    let stack = &mut self.top_mut()?.stack;
-                    let top = stack.last().unwrap().clone();
-                    stack.extend((0..instruction.arg0).map(|_| top.clone()));
+            }
+            OpCode::Div => {
+                let stack = &mut self.top_mut()?.stack;
+                // Division of floats by zero yields an infinity or NaN as IEEE 754 defines
+                if let [.., Value::I64(_), Value::I64(0)] = &stack[..] {
+                    return Err(RuntimeError::DivByZero.into());
                 }
-                OpCode::Add => Self::interpret_bin_op_str(
-                    &mut self.top_mut()?.stack,
-                    |lhs, rhs| lhs + rhs,
-                    |lhs, rhs| lhs + rhs,
-                    |lhs, rhs| Some(format!("{lhs}{rhs}")),
-                ),
-                OpCode::Sub => Self::interpret_bin_op(
-                    &mut self.top_mut()?.stack,
-                    |lhs, rhs| lhs - rhs,
-                    |lhs, rhs| lhs - rhs,
-                ),
-                OpCode::Mul => Self::interpret_bin_op(
-                    &mut self.top_mut()?.stack,
-                    |lhs, rhs| lhs * rhs,
-                    |lhs, rhs| lhs * rhs,
-                ),
-                OpCode::Div => Self::interpret_bin_op(
-                    &mut self.top_mut()?.stack,
+                Self::interpret_bin_op(
+                    stack,
+                    instruction.op,
                     |lhs, rhs| lhs / rhs,
-                    |lhs, rhs| lhs / rhs,
-                ),
-                OpCode::And => Self::interpret_bin_op(
-                    &mut self.top_mut()?.stack,
-                    |lhs, rhs| (lhs != 0. && rhs != 0.) as i32 as f64,
-                    |lhs, rhs| (lhs != 0 && rhs != 0) as i64,
-                ),
-                OpCode::Or => Self::interpret_bin_op(
-                    &mut self.top_mut()?.stack,
-                    |lhs, rhs| (lhs != 0. || rhs != 0.) as i32 as f64,
-                    |lhs, rhs| (lhs != 0 || rhs != 0) as i64,
-                ),
-                OpCode::Call => {
-                    let stack = &self.top()?.stack;
-                    let args = &stack[stack.len() - instruction.arg0 as usize..];
-                    let fname = &stack[stack.len() - instruction.arg0 as usize - 1];
-                    let Value::Str(fname) = fname else {
-                        panic!(
-                            "Function name shall be a string: {fname:?} in fn {:?}",
-                            self.top()?.stack
-                        );
+                    // `i64::MIN / -1` overflows
+                    i64::checked_div,
+                )?
+            }
+            OpCode::And => {
+                Self::interpret_logic_op(&mut self.top_mut()?.stack, |lhs, rhs| lhs && rhs)
+            }
+            OpCode::Or => {
+                Self::interpret_logic_op(&mut self.top_mut()?.stack, |lhs, rhs| lhs || rhs)
+            }
+            OpCode::Call => {
+                let stack = &self.top()?.stack;
+                let args = &stack[stack.len() - instruction.arg0 as usize..];
+                // A function called by name is looked up by index, and a function value is
+                // below the arguments
+                let (fname, fn_def, captures, caller_slots) = if instruction.arg1 == 0 {
+                    let f = &stack[stack.len() - args.len() - 1];
+                    let (fname, captures) = match f {
+                        Value::Func(fname) => (fname.as_str(), &[][..]),
+                        Value::Closure(closure) => (closure.name.as_str(), &closure.captures[..]),
+                        _ => return Err(f.coercion_error("fn").into()),
                     };
                     let fn_def = self
                         .bytecode
-                        .funcs
                         .get(fname)
-                        .ok_or_else(|| format!("Function name shall be a string: {fname:?}"))?;
-                    match fn_def {
-                        FnDef::User(user_fn) => {
-                            if user_fn.cofn {
-                                let mut vm = Vm::new(self.bytecode.clone());
-                                vm.stack_frames
-                                    .push(StackFrame::new(user_fn.clone(), args.to_vec()));
-                                let stack = &mut self.top_mut()?.stack;
-                                stack.resize(
-                                    stack.len() - instruction.arg0 as usize - 1,
-                                    Value::F64(0.),
+                        .ok_or_else(|| RuntimeError::UndefinedFunction(fname.to_string()))?;
+                    (fname, fn_def, captures, args.len() + 1)
+                } else {
+                    let (fname, fn_def) = &self.bytecode.funcs[instruction.arg1 as usize - 1];
+                    (fname.as_str(), fn_def, &[][..], args.len())
+                };
+                match fn_def {
+                    FnDef::User(user_fn) => {
+                        if user_fn.args.len() != args.len() {
+                            return Err(RuntimeError::ArityMismatch {
+                                fn_name: fname.to_string(),
+                                expected: user_fn.args.len(),
+                                got: args.len(),
+                            }
+                            .into());
+                        }
+                        if user_fn.captures.len() != captures.len() {
+                            return Err(RuntimeError::ClosureCalledByName(fname.to_string()).into());
+                        }
+                        // The captured values come before the arguments, but only the arguments
+                        // are popped from the caller's stack on return
+                        let mut frame = StackFrame::new(
+                            user_fn.clone(),
+                            captures.iter().chain(args).cloned().collect(),
+                        );
+                        frame.caller_slots = caller_slots;
+                        if user_fn.cofn {
+                            let coro = Value::Coro(Rc::new(RefCell::new(self.child_vm(frame))));
+                            let stack = &mut self.top_mut()?.stack;
+                            stack.resize(stack.len() - caller_slots, Value::F64(0.));
+                            stack.push(coro);
+                        } else {
+                            if self.max_stack_frames <= self.outer_frames + self.stack_frames.len()
+                            {
+                                return Err(
+                                    RuntimeError::StackOverflow(self.max_stack_frames).into()
                                 );
-                                stack.push(Value::Coro(Rc::new(RefCell::new(vm))));
-                            } else {
-                                self.stack_frames
-                                    .push(StackFrame::new(user_fn.clone(), args.to_vec()));
-                                continue;
                             }
+                            self.stack_frames.push(frame);
+                            return Ok(StepResult::Running);
+                        }
+                    }
+                    FnDef::Native(native) => {
+                        if fname == DUMP_STACK {
+                            Self::dump_stack(&stack[..stack.len() - caller_slots]);
                         }
-                        FnDef::Native(native) => {
-                            let res = (native.code)(args);
-                            let stack = &mut (self.top_mut()?.stack);
-                            stack.resize(
-                                stack.len() - instruction.arg0 as usize - 1,
-                                Value::F64(0.),
-                            );
-                            stack.push(res);
+                        let res = output::with_budget(&self.output, || {
+                            self.call_native(fname, native, args)
+                        })?;
+                        if self.output.exceeded() {
+                            return Err(RuntimeError::OutputLimitExceeded(
+                                self.output.limit().unwrap_or_default(),
+                            )
+                            .into());
                         }
+                        let stack = &mut (self.top_mut()?.stack);
+                        stack.resize(stack.len() - caller_slots, Value::F64(0.));
+                        stack.push(res);
                     }
                 }
-                OpCode::Jmp => {
+            }
+            OpCode::Jmp => {
+                self.top_mut()?.ip = instruction.arg0 as usize;
+                return Ok(StepResult::Running);
+            }
+            OpCode::Jf => {
+                let stack = &mut self.top_mut()?.stack;
+                let cond = stack.pop().expect("Jf needs an argument");
+                if !cond.is_truthy() {
                     self.top_mut()?.ip = instruction.arg0 as usize;
-                    continue;
-                }
-                OpCode::Jf => {
-                    let stack = &mut self.top_mut()?.stack;
-                    let cond = stack.pop().expect("Jf needs an argument");
-                    if cond.coerce_f64() == 0. {
-                        self.top_mut()?.ip = instruction.arg0 as usize;
-                        self.top_mut()?
-                            .stack
-                            .resize(instruction.arg1 as usize, Value::F64(0.));
-                        continue;
-                    }
+                    self.top_mut()?
+                        .stack
+                        .resize(instruction.arg1 as usize, Value::F64(0.));
+                    return Ok(StepResult::Running);
                 }
-                OpCode::Exitf => {
-                    let stack = &mut self.top_mut()?.stack;
-                    let cond = stack.pop().expect("Exitf needs an argument");
-                    if cond.coerce_f64() == 0. {
-                        return Err("Index out of bounds".into()); // TODO: customize message
-                    }
+            }
+            OpCode::BoundsCheck => {
+                let stack = &mut self.top_mut()?.stack;
+                let index = stack
+                    .pop()
+                    .expect("BoundsCheck needs an index")
+                    .coerce_i64();
+                let len = instruction.arg0 as usize;
+                if usize::try_from(index).map_or(true, |index| len <= index) {
+                    return Err(RuntimeError::IndexOutOfBounds { index, len }.into());
                 }
-                OpCode::Not => {
-                    let stack = &mut self.top_mut()?.stack;
-                    let top = stack.last().unwrap().clone();
-                    stack.pop();
-                    stack.push(Value::F64(if top.coerce_f64() == 0. { 1. } else { 0. }));
+            }
+            OpCode::Not => {
+                let stack = &mut self.top_mut()?.stack;
+                let top = stack.last().unwrap().clone();
+                stack.pop();
+                stack.push(Value::I64(!top.is_truthy() as i64));
+            }
+            OpCode::Lt => Self::interpret_lt(&mut self.top_mut()?.stack)?,
+            OpCode::Eq => {
+                let stack = &mut self.top_mut()?.stack;
+                let rhs = stack.pop().expect("Stack underflow");
+                let lhs = stack.pop().expect("Stack underflow");
+                stack.push(Value::I64(lhs.value_eq(&rhs) as i64));
+            }
+            OpCode::Pop => {
+                let stack = &mut self.top_mut()?.stack;
+                stack.resize(stack.len() - instruction.arg0 as usize, Value::default());
+            }
+            OpCode::MakeClosure => {
+                let stack = &mut self.top_mut()?.stack;
+                let captures = stack.split_off(stack.len() - instruction.arg0 as usize);
+                let Some(Value::Str(name)) = stack.pop() else {
+                    return Err("MakeClosure needs a function name".into());
+                };
+                stack.push(Value::Closure(Rc::new(Closure {
+                    name: name.to_string(),
+                    captures,
+                })));
+            }
+            OpCode::LoadGlobal => {
+                let value = self
+                    .globals
+                    .borrow()
+                    .get(instruction.arg0 as usize)
+                    .cloned()
+                    .ok_or(RuntimeError::UndefinedGlobal)?;
+                self.top_mut()?.stack.push(value);
+            }
+            OpCode::StoreGlobal => {
+                let value = self
+                    .top_mut()?
+                    .stack
+                    .pop()
+                    .ok_or_else(|| "Stack underflow".to_string())?;
+                let mut globals = self.globals.borrow_mut();
+                let slot = instruction.arg0 as usize;
+                if globals.len() <= slot {
+                    globals.resize(slot + 1, Value::default());
                 }
-                OpCode::Lt => Self::interpret_bin_op(
-                    &mut self.top_mut()?.stack,
-                    |lhs, rhs| (lhs < rhs) as i32 as f64,
-                    |lhs, rhs| (lhs < rhs) as i64,
-                ),
-                OpCode::Eq => Self::interpret_bin_op(
-                    &mut self.top_mut()?.stack,
-                    |lhs, rhs| (lhs == rhs) as i32 as f64,
-                    |lhs, rhs| (lhs == rhs) as i64,
-                ),
-                OpCode::Pop => {
-                    let stack = &mut self.top_mut()?.stack;
-                    stack.resize(stack.len() - instruction.arg0 as usize, Value::default());
+                globals[slot] = value;
+            }
+            OpCode::TailCall => {
+                let top_frame = self.top_mut()?;
+                let args = top_frame
+                    .stack
+                    .split_off(top_frame.stack.len() - instruction.arg0 as usize);
+                top_frame.stack = args;
+                top_frame.ip = 0;
+                return Ok(StepResult::Running);
+            }
+            OpCode::Ret => {
+                if let Some(res) = self.return_fn(instruction.arg0)? {
+                    return Ok(res.into());
                 }
-                OpCode::Ret => {
-                    if let Some(res) = self.return_fn(instruction.arg0)? {
-                        return Ok(res);
+                return Ok(StepResult::Running);
+            }
+            OpCode::Yield => {
+                let top_frame = self.top_mut()?;
+                let res = top_frame
+                    .stack
+                    .pop()
+                    .ok_or_else(|| "Stack underflow".to_string())?;
+                // The value of the `yield` expression, unless `resume` sends another one
+                top_frame.stack.push(Value::Unit);
+                top_frame.ip += 1;
+                return Ok(StepResult::Yielded(res));
+            }
+            OpCode::Await => {
+                let vms = self
+                    .top_mut()?
+                    .stack
+                    .pop()
+                    .ok_or_else(|| "Stack underflow".to_string())?;
+                let Value::Coro(vm) = vms else {
+                    return Err(RuntimeError::TypeError {
+                        expected: "cofn",
+                        got: vms.type_name(),
                     }
-                    continue;
-                }
-                OpCode::Yield => {
-                    let top_frame = self.top_mut()?;
-                    let res = top_frame
-                        .stack
-                        .pop()
-                        .ok_or_else(|| "Stack underflow".to_string())?;
-                    top_frame.ip += 1;
-                    return Ok(YieldResult::Suspend(res));
-                }
-                OpCode::Await => {
-                    let vms = self
-                        .top_mut()?
-                        .stack
-                        .pop()
-                        .ok_or_else(|| "Stack underflow".to_string())?;
-                    let Value::Coro(vm) = vms else {
-                        return Err("Await keyword applied to a non-coroutine".into());
-                    };
-                    match vm.borrow_mut().interpret() {
-                        Ok(YieldResult::Finished(_)) => (),
-                        Ok(YieldResult::Suspend(value)) => {
-                            self.top_mut()?.stack.push(value);
-                        }
-                        Err(e) => {
-                            eprintln!("Runtime error: {e:?}");
-                        }
-                    };
-                }
+                    .into());
+                };
+                match vm.borrow_mut().interpret() {
+                    Ok(YieldResult::Finished(_) | YieldResult::Breakpoint(_)) => (),
+                    Ok(YieldResult::Suspend(value)) => {
+                        self.top_mut()?.stack.push(value);
+                    }
+                    Err(e) => {
+                        eprintln!("Runtime error: {e}");
+                    }
+                };
+            }
+            OpCode::Cast => {
+                let stack = &mut self.top_mut()?.stack;
+                let idx = stack.len() - instruction.arg0 as usize - 1;
+                let (kind, expected) = match instruction.arg1 {
+                    kind if kind == ValueKind::I64 as u16 => (ValueKind::I64, "i64"),
+                    kind if kind == ValueKind::Str as u16 => (ValueKind::Str, "str"),
+                    _ => (ValueKind::F64, "f64"),
+                };
+                stack[idx] = stack[idx]
+                    .cast(kind)
+                    .ok_or_else(|| RuntimeError::TypeError {
+                        expected,
+                        got: stack[idx].type_name(),
+                    })?;
+            }
+            OpCode::Index => {
+                let stack = &mut self.top_mut()?.stack;
+                let index = stack.pop().expect("Index needs an array index");
+                let array = stack.pop().expect("Index needs an array");
+                let Value::Array(array) = array else {
+                    return Err(RuntimeError::TypeError {
+                        expected: "Array",
+                        got: array.type_name(),
+                    }
+                    .into());
+                };
+                let index = index.coerce_i64();
+                let array = array.borrow();
+                let value = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| array.get(index).cloned())
+                    .ok_or_else(|| RuntimeError::IndexOutOfBounds {
+                        index,
+                        len: array.len(),
+                    })?;
+                stack.push(value);
+            }
+            OpCode::MakeArray => {
+                let stack = &mut self.top_mut()?.stack;
+                let values = stack.split_off(stack.len() - instruction.arg0 as usize);
+                stack.push(Value::array(values));
             }
-            // debug stack
-            // println!("stack: {:?}", self.top()?.stack);
-            self.top_mut()?.ip += 1;
         }
+        // debug stack
+        // println!("stack: {:?}", self.top()?.stack);
+        self.top_mut()?.ip += 1;
+        Ok(StepResult::Running)
     }
 
     fn interpret_bin_op_str(
         stack: &mut Vec<Value>,
+        op: OpCode,
         op_f64: impl FnOnce(f64, f64) -> f64,
-        op_i64: impl FnOnce(i64, i64) -> i64,
-        op_str: impl FnOnce(&str, &str) -> Option<String>,
-    ) {
+        op_i64: impl FnOnce(i64, i64) -> Option<i64>,
+        op_str: impl FnOnce(&mut String, &str) -> bool,
+    ) -> Result<(), RuntimeError> {
         use Value::*;
         let rhs = stack.pop().expect("Stack underflow");
         let lhs = stack.pop().expect("Stack underflow");
         let res = match (lhs, rhs) {
             (F64(lhs), F64(rhs)) => F64(op_f64(lhs, rhs)),
-            (I64(lhs), I64(rhs)) => I64(op_i64(lhs, rhs)),
+            (I64(lhs), I64(rhs)) => I64(op_i64(lhs, rhs).ok_or(RuntimeError::IntegerOverflow(op))?),
             (F64(lhs), I64(rhs)) => F64(op_f64(lhs, rhs as f64)),
             (I64(lhs), F64(rhs)) => F64(op_f64(lhs as f64, rhs)),
-            (Str(lhs), Str(rhs)) => {
-                if let Some(res) = op_str(&lhs, &rhs) {
-                    Str(res)
+            (Str(mut lhs), Str(rhs)) => {
+                // Copied only if a variable or another slot still shares the string
+                if op_str(Rc::make_mut(&mut lhs), &rhs) {
+                    Str(lhs)
                 } else {
-                    panic!("Operation not supported for strings: {lhs:?} {rhs:?}");
+                    return Err(RuntimeError::UnsupportedOperation {
+                        op,
+                        lhs: "str",
+                        rhs: "str",
+                    });
                 }
             }
-            (lhs, rhs) => panic!("Operation not supported: {lhs:?} {rhs:?}"),
+            (lhs, rhs) => {
+                return Err(RuntimeError::UnsupportedOperation {
+                    op,
+                    lhs: lhs.type_name(),
+                    rhs: rhs.type_name(),
+                })
+            }
         };
         stack.push(res);
+        Ok(())
+    }
+
+    /// Compare two numbers of either type by value, giving `I64` 1 or 0 like `true` and `false`.
+    fn interpret_lt(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+        use Value::*;
+        let rhs = stack.pop().expect("Stack underflow");
+        let lhs = stack.pop().expect("Stack underflow");
+        let lt = match (&lhs, &rhs) {
+            (I64(lhs), I64(rhs)) => lhs < rhs,
+            (F64(lhs), F64(rhs)) => lhs < rhs,
+            (F64(lhs), I64(rhs)) => *lhs < *rhs as f64,
+            (I64(lhs), F64(rhs)) => (*lhs as f64) < *rhs,
+            _ => {
+                // `a > b` is compiled to `b < a`, so neither the operator nor the order is the
+                // one written in the source. Sort the types to report both the same way.
+                let (lhs, rhs) = (lhs.type_name(), rhs.type_name());
+                return Err(RuntimeError::Incomparable {
+                    lhs: lhs.min(rhs),
+                    rhs: lhs.max(rhs),
+                });
+            }
+        };
+        stack.push(I64(lt as i64));
+        Ok(())
+    }
+
+    /// Repeat the string operand by the numeric one, in either order.
+    /// A negative count yields an empty string and a fractional one is truncated.
+    fn interpret_str_repeat(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+        let rhs = stack.pop().expect("Stack underflow");
+        let lhs = stack.pop().expect("Stack underflow");
+        let (s, count) = match (lhs, rhs) {
+            (Value::Str(s), count) | (count, Value::Str(s)) => (s, count),
+            _ => unreachable!("string repetition without a string operand"),
+        };
+        let count = match count {
+            Value::I64(i) => i.max(0) as usize,
+            count => count.try_coerce_f64()?.max(0.) as usize,
+        };
+        if s.len()
+            .checked_mul(count)
+            .is_none_or(|len| MAX_STR_REPEAT_LEN < len)
+        {
+            return Err(RuntimeError::StringTooLong(MAX_STR_REPEAT_LEN));
+        }
+        stack.push(Value::Str(s.repeat(count).into()));
+        Ok(())
     }
 
     fn interpret_bin_op(
         stack: &mut Vec<Value>,
+        op: OpCode,
         op_f64: impl FnOnce(f64, f64) -> f64,
-        op_i64: impl FnOnce(i64, i64) -> i64,
-    ) {
-        Self::interpret_bin_op_str(stack, op_f64, op_i64, |_, _| None);
+        op_i64: impl FnOnce(i64, i64) -> Option<i64>,
+    ) -> Result<(), RuntimeError> {
+        Self::interpret_bin_op_str(stack, op, op_f64, op_i64, |_, _| false)
+    }
+
+    /// Apply a logical operator to the truthiness of the operands.
+    /// The result is always `I64`, 1 or 0, like the comparison operators.
+    fn interpret_logic_op(stack: &mut Vec<Value>, op: impl FnOnce(bool, bool) -> bool) {
+        let rhs = stack.pop().expect("Stack underflow");
+        let lhs = stack.pop().expect("Stack underflow");
+        stack.push(Value::I64(op(lhs.is_truthy(), rhs.is_truthy()) as i64));
+    }
+
+    /// A `Vm` to run the frame apart from this one, sharing the globals and the limits
+    fn child_vm(&self, frame: StackFrame) -> Vm {
+        let mut vm = Vm::new(self.bytecode.clone());
+        vm.coverage = self.coverage.clone();
+        vm.globals = self.globals.clone();
+        vm.gas_limit = self.gas_limit;
+        vm.gas_used = self.gas_used.clone();
+        vm.output = self.output.clone();
+        vm.stack_frames.push(frame);
+        vm
+    }
+
+    /// Call a native, or run the natives which call function values on behalf of them.
+    fn call_native(
+        &self,
+        fname: &str,
+        native: &NativeFn,
+        args: &[Value],
+    ) -> Result<Value, Box<dyn Error>> {
+        match fname {
+            MAP => {
+                let mut args = args.iter();
+                let values = Self::array_elements(next_arg(&mut args)?)?;
+                let f = next_arg(&mut args)?;
+                let mapped = values
+                    .into_iter()
+                    .map(|value| self.call_value(f, vec![value]))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::array(mapped))
+            }
+            FILTER => {
+                let mut args = args.iter();
+                let values = Self::array_elements(next_arg(&mut args)?)?;
+                let pred = next_arg(&mut args)?;
+                let mut filtered = vec![];
+                for value in values {
+                    if self.call_value(pred, vec![value.clone()])?.is_truthy() {
+                        filtered.push(value);
+                    }
+                }
+                Ok(Value::array(filtered))
+            }
+            REDUCE => {
+                let mut args = args.iter();
+                let values = Self::array_elements(next_arg(&mut args)?)?;
+                let init = next_arg(&mut args)?.clone();
+                let f = next_arg(&mut args)?;
+                values
+                    .into_iter()
+                    .try_fold(init, |acc, value| self.call_value(f, vec![acc, value]))
+            }
+            SORT => {
+                let mut args = args.iter();
+                let values = Self::array_elements(next_arg(&mut args)?)?;
+                let sorted = match args.next() {
+                    // The comparison returns a negative number if the first argument comes first,
+                    // a positive one if the second does, and zero if they are equal
+                    Some(cmp) => merge_sort_by(values, &mut |lhs, rhs| {
+                        let order = self
+                            .call_value(cmp, vec![lhs.clone(), rhs.clone()])?
+                            .try_coerce_f64()?;
+                        Ok::<_, Box<dyn Error>>(order.partial_cmp(&0.).unwrap_or(Ordering::Equal))
+                    })?,
+                    None => merge_sort_by(values, &mut Value::try_cmp)?,
+                };
+                Ok(Value::array(sorted))
+            }
+            _ => Ok((native.code)(args)?),
+        }
+    }
+
+    /// Copy of the elements of an array, so that a function value called for each of them
+    /// can modify the array
+    fn array_elements(arr: &Value) -> Result<Vec<Value>, RuntimeError> {
+        match arr {
+            Value::Array(values) => Ok(values.borrow().clone()),
+            _ => Err(arr.coercion_error("array")),
+        }
+    }
+
+    /// Call a function value from a native and run it to completion.
+    ///
+    /// A user function runs in a child `Vm` like a coroutine, so that the frames of this one
+    /// are left intact, but it can't yield.
+    fn call_value(&self, f: &Value, args: Vec<Value>) -> Result<Value, Box<dyn Error>> {
+        let (fname, captures) = match f {
+            Value::Func(fname) => (fname.as_str(), &[][..]),
+            Value::Closure(closure) => (closure.name.as_str(), &closure.captures[..]),
+            _ => return Err(f.coercion_error("fn").into()),
+        };
+        let fn_def = self
+            .bytecode
+            .get(fname)
+            .ok_or_else(|| RuntimeError::UndefinedFunction(fname.to_string()))?;
+        let user_fn = match fn_def {
+            FnDef::User(user_fn) => user_fn,
+            FnDef::Native(native) => return self.call_native(fname, native, &args),
+        };
+        if user_fn.args.len() != args.len() {
+            return Err(RuntimeError::ArityMismatch {
+                fn_name: fname.to_string(),
+                expected: user_fn.args.len(),
+                got: args.len(),
+            }
+            .into());
+        }
+        if user_fn.captures.len() != captures.len() {
+            return Err(RuntimeError::ClosureCalledByName(fname.to_string()).into());
+        }
+        let frame = StackFrame::new(
+            user_fn.clone(),
+            captures.iter().cloned().chain(args).collect(),
+        );
+        let mut vm = self.child_vm(frame);
+        if user_fn.cofn {
+            return Ok(Value::Coro(Rc::new(RefCell::new(vm))));
+        }
+        // The calls in the child count toward the depth of this `Vm`. Each callback also nests
+        // `interpret` on the native stack, so the depth of callbacks has a tighter limit.
+        vm.max_stack_frames = self.max_stack_frames;
+        vm.outer_frames = self.outer_frames + self.stack_frames.len();
+        vm.callback_depth = self.callback_depth + 1;
+        if MAX_CALLBACK_DEPTH < vm.callback_depth {
+            return Err(RuntimeError::StackOverflow(MAX_CALLBACK_DEPTH).into());
+        }
+        if self.max_stack_frames <= vm.outer_frames {
+            return Err(RuntimeError::StackOverflow(self.max_stack_frames).into());
+        }
+        loop {
+            match vm.interpret().map_err(|e| e.error)? {
+                YieldResult::Finished(value) => return Ok(value),
+                YieldResult::Suspend(_) => {
+                    return Err(RuntimeError::YieldFromNative(fname.to_string()).into())
+                }
+                YieldResult::Breakpoint(_) => {}
+            }
+        }
+    }
+
+    /// Print the values on the stack, from the bottom
+    fn dump_stack(stack: &[Value]) {
+        output::write(&format!("dump_stack: {} values\n", stack.len()));
+        for (i, value) in stack.iter().enumerate() {
+            output::write(&format!("  [{i}] {value:?}\n"));
+        }
+    }
+
+    /// Disassemble the function of the top frame, marking the instruction to be executed next.
+    fn disasm_top(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let Some(frame) = self.stack_frames.last() else {
+            return Ok(());
+        };
+        disasm_common(
+            &frame.fn_def.literals,
+            &frame.fn_def.instructions,
+            &self.bytecode.fn_names,
+            Some(frame.ip),
+            writer,
+        )
+    }
+
+    /// Named values on the stack of the top frame, with their stack slots.
+    ///
+    /// A variable which went out of scope keeps its name until another variable takes its slot.
+    pub fn locals(&self) -> Vec<(usize, String, Value)> {
+        let Some(frame) = self.stack_frames.last() else {
+            return vec![];
+        };
+        frame
+            .stack
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, value)| {
+                let name = frame.fn_def.local_name(slot, frame.ip)?;
+                Some((slot, name.to_string(), value.clone()))
+            })
+            .collect()
+    }
+
+    /// Overwrite the value at the stack slot of the top frame. A number is converted to the
+    /// kind of the number it replaces, so that a variable keeps its declared type.
+    pub fn set_local(&mut self, slot: usize, value: Value) -> Result<(), String> {
+        let frame = self.top_mut()?;
+        let len = frame.stack.len();
+        let target = frame
+            .stack
+            .get_mut(slot)
+            .ok_or_else(|| format!("Slot {slot} out of bounds for the stack of length {len}"))?;
+        *target = value.cast(target.kind()).unwrap_or(value);
+        Ok(())
+    }
+
+    /// Print the instruction to be executed next and the stack of the top frame.
+    fn print_next(&self) {
+        let Some(frame) = self.stack_frames.last() else {
+            return;
+        };
+        match frame.fn_def.instructions.get(frame.ip) {
+            Some(inst) => println!("Next: [{}] {inst:?}", frame.ip),
+            None => println!("Next: return"),
+        }
+        println!("Stack: {:?}", frame.stack);
     }
 
     fn back_trace(&self) {
@@ -1577,8 +3659,7 @@ pub fn compile(
     out_file: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let src = args.source.as_ref().ok_or_else(|| {
-        Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        Box::new(std::io::Error::other(
             "Please specify source file to compile after -c".to_string(),
         ))
     })?;
@@ -1586,25 +3667,298 @@ pub fn compile(
     write_program(src, &source, writer, out_file, args)
 }
 
+/// Parse, type check, compile and run a program, and return the value of its last expression.
+///
+/// Nothing but the output of the print natives is written, which can be redirected
+/// by `output::set_writer`.
+pub fn run_source(src: &str) -> Result<Value, RuscalError> {
+    let mut vm = load_source(src)?;
+    loop {
+        match vm.interpret() {
+            Ok(YieldResult::Finished(value)) => return Ok(value),
+            Ok(YieldResult::Suspend(_) | YieldResult::Breakpoint(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Parse, type check and compile a program, and return a `Vm` ready to run its toplevel code
+/// by `Vm::interpret`.
+pub fn load_source(src: &str) -> Result<Vm, RuscalError> {
+    let stmts = statements_finish(Span::new(src))
+        .map_err(|e| RuscalError::Parse(render_error(None, src, e.input, parse_error_msg(&e))))?;
+
+    type_check(&stmts, &mut TypeCheckContext::new())
+        .map_err(|e| RuscalError::TypeCheck(render_error(None, src, e.span, &e)))?;
+
+    let mut compiler = Compiler::new();
+    compiler
+        .compile(&stmts)
+        .map_err(|e| RuscalError::Compile(e.to_string()))?;
+
+    let mut buf = vec![];
+    compiler
+        .write_funcs(&mut buf)
+        .map_err(|e| RuscalError::Compile(e.to_string()))?;
+    let bytecode = read_program(&mut std::io::Cursor::new(&mut buf))
+        .map_err(|e| RuscalError::Compile(e.to_string()))?;
+
+    let mut vm = Vm::new(Rc::new(bytecode));
+    vm.init_main(&[])
+        .map_err(|error| VmError { line: None, error })?;
+    Ok(vm)
+}
+
+/// State of the read-eval-print loop kept across the inputs.
+///
+/// Each input is compiled into a fresh `main` function and run on a new `Vm`.
+/// The `Compiler` and the stack of the toplevel frame are kept across the inputs,
+/// so functions and variables defined in earlier inputs are still available.
+pub struct Repl {
+    compiler: Compiler,
+    tc_ctx: TypeCheckContext<'static>,
+    stack: Vec<Value>,
+    globals: Rc<RefCell<Vec<Value>>>,
+    /// The limit bounds the output of the whole session rather than each input
+    output: Rc<OutputBudget>,
+}
+
+impl Repl {
+    pub fn new(args: &Args) -> Self {
+        let mut compiler = Compiler::new();
+        compiler.max_functions = args.max_functions;
+        Self {
+            compiler,
+            tc_ctx: TypeCheckContext::new(),
+            stack: vec![],
+            globals: Rc::new(RefCell::new(vec![])),
+            output: Rc::new(OutputBudget::new(args.limit_output_bytes)),
+        }
+    }
+
+    /// Run an input and return the value of its last expression, if it ends with one.
+    ///
+    /// An input failing to type check or compile leaves the state as it was before, and an input
+    /// failing at runtime drops the variables it defined.
+    pub fn eval(&mut self, src: &str) -> Result<Option<Value>, RuscalError> {
+        let stmts = statements_finish(Span::new(src)).map_err(|e| {
+            RuscalError::Parse(render_error(None, src, e.input, parse_error_msg(&e)))
+        })?;
+        // The definitions of the input are checked in a scope of their own, and kept only as
+        // long as the compiler keeps them
+        let mut tc_ctx = TypeCheckContext::push_pending(&self.tc_ctx);
+        type_check(&stmts, &mut tc_ctx)
+            .map_err(|e| RuscalError::TypeCheck(render_error(None, src, e.span, &e)))?;
+        let (vars, funcs) = tc_ctx.take_definitions();
+
+        let res = self
+            .compiler
+            .compile_incremental(&stmts)
+            .map_err(|e| RuscalError::Compile(e.to_string()))?;
+        self.tc_ctx.define_fns(funcs);
+
+        let mut vm = Vm::new(Rc::new(self.compiler.bytecode()));
+        vm.globals = self.globals.clone();
+        vm.output = self.output.clone();
+        vm.init_fn("main", &self.stack)
+            .map_err(|error| VmError { line: None, error })?;
+
+        match run_repl_main(&mut vm) {
+            Ok(Some(mut new_stack)) => {
+                let value = res.and_then(|res| new_stack.get(res.0).cloned());
+                // Keep only the variables for the next input
+                new_stack.truncate(self.compiler.truncate_temps());
+                self.stack = new_stack;
+                self.tc_ctx.define_vars(vars);
+                Ok(value)
+            }
+            Ok(None) => {
+                self.compiler.target_stack.truncate(self.stack.len());
+                Ok(None)
+            }
+            Err(e) => {
+                self.compiler.target_stack.truncate(self.stack.len());
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Names of the variables defined so far with their values, in the order of the stack.
+    pub fn vars(&self) -> Vec<(String, Value)> {
+        self.compiler
+            .target_stack
+            .iter()
+            .zip(&self.stack)
+            .filter_map(|(target, value)| match target {
+                Target::Local(name, _) => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether a function of the name is defined by the inputs so far.
+    pub fn has_fn(&self, name: &str) -> bool {
+        self.compiler.funcs.contains_key(name)
+    }
+}
+
+/// Read-eval-print loop on the standard input.
+pub fn repl(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut repl = Repl::new(args);
+    let stdin = std::io::stdin();
+    let mut input = String::new();
+
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { ". " });
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        if input.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        // A blank line evaluates the input even if it looks incomplete, to report the error
+        let force = line.trim().is_empty();
+        input.push_str(&line);
+        if !force && is_incomplete(&input) {
+            continue;
+        }
+        let line = std::mem::take(&mut input);
+
+        match repl.eval(&line) {
+            Ok(Some(value)) => println!("{value}"),
+            Ok(None) => {}
+            Err(RuscalError::Parse(msg) | RuscalError::TypeCheck(msg)) => eprintln!("{msg}"),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+/// Whether the REPL input needs more lines: it has unclosed brackets, or fails to parse only
+/// because it ends early.
+fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0i32;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            '"' => {
+                // There are no escape sequences in string literals
+                chars.by_ref().find(|c| *c == '"');
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            _ => {}
+        }
+    }
+    if 0 < depth {
+        return true;
+    }
+    match statements_finish(Span::new(input)) {
+        Ok(_) => false,
+        Err(e) => e.input.fragment().trim().is_empty(),
+    }
+}
+
+/// Run the toplevel function and return its stack at the end, or `None` if it returned explicitly.
+fn run_repl_main(vm: &mut Vm) -> Result<Option<Vec<Value>>, VmError> {
+    loop {
+        if vm.stack_frames.len() == 1 && vm.top().is_ok_and(|frame| frame.inst().is_none()) {
+            return Ok(vm.stack_frames.pop().map(|frame| frame.stack));
+        }
+        let res = vm.step().map_err(|error| VmError {
+            line: vm.current_line(),
+            error,
+        });
+        match res? {
+            StepResult::Running => {}
+            StepResult::Yielded(value) => {
+                println!("Execution suspended with a yielded value {value}")
+            }
+            StepResult::Finished(value) => {
+                println!("{value}");
+                return Ok(None);
+            }
+        }
+    }
+}
+
 pub fn read_program(reader: &mut impl Read) -> std::io::Result<ByteCode> {
-    let mut bytecode = ByteCode::new();
-    bytecode.read_funcs(reader)?;
-    Ok(bytecode)
+    ByteCode::read_funcs(reader)
+}
+
+const DEBUGGER_COMMANDS: &str =
+    "[c]ontinue/[s]tep/[p]rint/[e]xit/[bt]race/disasm/b <line>/delete <line>/list/locals/set <slot> <value>";
+
+/// Overwrite a stack slot by `<slot> <value>`, where the value is written in JSON.
+fn set_command(vm: &mut Vm, arg: &str) -> Result<(), String> {
+    let (slot, value) = arg
+        .split_once(' ')
+        .ok_or_else(|| "Please say set <slot> <value>".to_string())?;
+    let slot = slot.parse().map_err(|_| format!("Invalid slot {slot:?}"))?;
+    vm.set_local(slot, Value::from_json(value.trim())?)
 }
 
-pub fn debugger(vm: &Vm) -> bool {
-    println!("[c]ontinue/[p]rint/[e]xit/[bt]race?");
+/// Prompt for debugger commands until the user continues or exits.
+/// Returns whether to stop the program, which is also the case when a step finishes it.
+pub fn debugger(vm: &mut Vm) -> bool {
+    println!("{DEBUGGER_COMMANDS}?");
     loop {
         let mut buffer = String::new();
         if std::io::stdin().read_line(&mut buffer).is_ok() {
             match buffer.trim() {
                 "c" => return false,
+                "s" => {
+                    match vm.step() {
+                        Ok(StepResult::Running) => {}
+                        Ok(StepResult::Yielded(value)) => {
+                            println!("Execution suspended with a yielded value {value}");
+                        }
+                        Ok(StepResult::Finished(value)) => {
+                            println!("Execution finished with {value}");
+                            return true;
+                        }
+                        Err(e) => {
+                            eprintln!("Runtime error: {e}");
+                            return true;
+                        }
+                    }
+                    vm.print_next();
+                }
                 "p" => {
                     println!("Stack: {:?}", vm.top().unwrap().stack);
                 }
                 "e" => return true,
                 "bt" => vm.back_trace(),
-                _ => println!("Please say [c]ontinue/[p]rint/[b]reak/[bt]race"),
+                "disasm" => vm.disasm_top(&mut std::io::stdout()).unwrap(),
+                "list" => println!("Breakpoints: {:?}", vm.breakpoints()),
+                "locals" => {
+                    for (slot, name, value) in vm.locals() {
+                        println!("[{slot}] {name} = {value:?}");
+                    }
+                }
+                command => {
+                    let (command, arg) = command.split_once(' ').unwrap_or((command, ""));
+                    let arg = arg.trim();
+                    match (command, arg.parse()) {
+                        ("b", Ok(line)) => vm.set_breakpoint(line),
+                        ("delete", Ok(line)) => {
+                            if !vm.delete_breakpoint(line) {
+                                println!("No breakpoint at line {line}");
+                            }
+                        }
+                        ("set", _) => {
+                            if let Err(e) = set_command(vm, arg) {
+                                println!("{e}");
+                            }
+                        }
+                        _ => println!("Please say {DEBUGGER_COMMANDS}"),
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,162 @@
+use std::fmt::Display;
+
+/// Short code attached to common errors, which can be looked up with `--explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// Variable not found
+    E001,
+    /// Type mismatch
+    E002,
+    /// Function not found
+    E003,
+    /// Syntax error
+    E004,
+    /// Index out of bounds
+    E005,
+    /// `break` or `continue` outside of a loop
+    E006,
+    /// Output limit exceeded
+    E007,
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl ErrorCode {
+    pub fn parse(code: &str) -> Option<Self> {
+        use ErrorCode::*;
+        Some(match code.to_ascii_uppercase().as_str() {
+            "E001" => E001,
+            "E002" => E002,
+            "E003" => E003,
+            "E004" => E004,
+            "E005" => E005,
+            "E006" => E006,
+            "E007" => E007,
+            _ => return None,
+        })
+    }
+
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            Self::E001 => {
+                r#"A variable was used before it was defined.
+
+Erroneous code example:
+
+    println(a);
+
+Every variable has to be defined with `var` before it is used:
+
+    var a: f64 = 10;
+    println(a);
+"#
+            }
+            Self::E002 => {
+                r#"A value of one type was used where another type was expected.
+
+Erroneous code example:
+
+    var a: f64 = "Hello";
+
+The type of the value must match the declared type:
+
+    var a: str = "Hello";
+
+Numbers can be converted with `i64(...)`, `f64(...)` and `str(...)`.
+"#
+            }
+            Self::E003 => {
+                r#"A function was called which is not defined.
+
+Erroneous code example:
+
+    var a: f64 = square(3);
+
+Define the function before calling it, or check the spelling of its name:
+
+    fn square(x: f64) -> f64 {
+        x * x
+    }
+    var a: f64 = square(3);
+"#
+            }
+            Self::E004 => {
+                r#"The source could not be parsed.
+
+Erroneous code example:
+
+    var a: f64 = 1
+
+Every statement except the last one in a block needs a trailing `;`:
+
+    var a: f64 = 1;
+"#
+            }
+            Self::E005 => {
+                r#"An array was indexed beyond its length.
+
+Erroneous code example:
+
+    var arr: Array<f64>[3] = [1, 2, 3];
+    println(arr[3]);
+
+Indices start from 0, so the last valid index is the length minus 1:
+
+    var arr: Array<f64>[3] = [1, 2, 3];
+    println(arr[2]);
+"#
+            }
+            Self::E006 => {
+                r#"A `break` or `continue` statement was used outside of a loop.
+
+Erroneous code example:
+
+    var a: f64 = 1;
+    break;
+
+`break` and `continue` are only allowed in the body of `for` or `while`:
+
+    for i in 0 to 10 {
+        break;
+    }
+"#
+            }
+            Self::E007 => {
+                r#"The script printed more bytes than allowed by `--limit-output-bytes`.
+
+Erroneous code example (with `--limit-output-bytes 100`):
+
+    var i: f64 = 0;
+    while 1 {
+        println("Hello");
+    }
+
+The output beyond the limit is suppressed and the execution stops.
+Make sure that loops printing values terminate, or raise the limit.
+"#
+            }
+        }
+    }
+}
+
+/// Render the source line at `line` with a `^` marker under `column` (1-based), like rustc.
+///
+/// Tabs before the column are kept in the marker line, so the marker lines up with the source
+/// regardless of the tab width of the terminal.
+pub fn source_snippet(source: &str, line: u32, column: usize) -> String {
+    let Some(src_line) = source.lines().nth(line.saturating_sub(1) as usize) else {
+        return String::new();
+    };
+    let line_no = line.to_string();
+    let pad = " ".repeat(line_no.len());
+    let indent: String = src_line
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!("{pad} |\n{line_no} | {src_line}\n{pad} | {indent}^")
+}
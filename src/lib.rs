@@ -2,12 +2,34 @@
 // use std::{collections::HashMap, sync::atomic::AtomicBool};
 use std::sync::atomic::AtomicBool;
 
+// Allow the modules and `dprintln!` to refer to this crate as `ruscal` like the binary does.
+extern crate self as ruscal;
+
+pub mod compiler;
+pub mod explain;
+pub mod optimizer;
+pub mod output;
+pub mod parser;
+pub mod random;
+pub mod unparser;
+pub mod value;
+
+pub use compiler::{load_source, run_source, RuntimeError};
+pub use parser::{register_native, register_time_natives, TypeDecl};
+pub use value::Value;
+
 pub enum RunMode {
     None,
     TypeCheck,
     Compile,
     Run(String),
+    /// Disassemble a compiled bytecode file without running it
+    Disasm(String),
     CompileAndRun,
+    /// Print the explanation of an error code
+    Explain(String),
+    /// Read-eval-print loop
+    Repl,
 }
 
 pub struct Args {
@@ -18,7 +40,25 @@ pub struct Args {
     pub show_ast: bool,
     pub show_debug_ast: bool,
     pub optimize: bool,
+    /// Let the optimizer reassociate arithmetic to fold more literals, which implies `optimize`
+    pub fast_math: bool,
     pub debug_output: bool,
+    /// Compile the source as a library, without a synthetic `main` function
+    pub lib: bool,
+    /// Print the symbols of every scope after type checking
+    pub dump_symbols: bool,
+    /// Print the source lines executed and not executed after the run
+    pub coverage: bool,
+    /// Maximum number of functions (including `main`) a program can define
+    pub max_functions: Option<usize>,
+    /// Maximum number of bytes a script can write by the print natives
+    pub limit_output_bytes: Option<usize>,
+    /// Maximum depth of nested expressions and blocks in the source
+    pub max_expr_depth: Option<usize>,
+    /// Arguments after `--`, passed to `main` if it takes an array of them
+    pub script_args: Vec<String>,
+    /// Source lines where the run stops into the debugger
+    pub breakpoints: Vec<u32>,
     // Because Args is passed as a shared reference, NativeFn can be requested to be generated multiple times.
     // Having a function to return one is an easy trick to allow it without breaking API.
     // pub additional_funcs: HashMap<String, Box<dyn Fn() -> NativeFn<'static>>>,
@@ -34,7 +74,16 @@ impl Args {
             show_ast: false,
             show_debug_ast: false,
             optimize: false,
+            fast_math: false,
             debug_output: false,
+            lib: false,
+            dump_symbols: false,
+            coverage: false,
+            max_functions: None,
+            limit_output_bytes: None,
+            max_expr_depth: None,
+            script_args: vec![],
+            breakpoints: vec![],
             // additional_funcs: HashMap::new(),
         }
     }
@@ -46,6 +95,38 @@ impl Default for Args {
     }
 }
 
+/// Error returned by `run_source`, classified by the stage where it happened.
+#[derive(Debug)]
+pub enum RuscalError {
+    Parse(String),
+    TypeCheck(String),
+    Compile(String),
+    /// The kind of the error with the source line of the failing instruction, if known
+    Runtime {
+        error: RuntimeError,
+        line: Option<u32>,
+    },
+}
+
+impl std::fmt::Display for RuscalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(msg) => write!(f, "Parse error: {msg}"),
+            Self::TypeCheck(msg) => write!(f, "TypeCheck error: {msg}"),
+            Self::Compile(msg) => write!(f, "Compile error: {msg}"),
+            Self::Runtime { error, line } => {
+                write!(f, "Runtime error: {error}")?;
+                if let Some(line) = line {
+                    write!(f, " at line {line}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuscalError {}
+
 pub static DEBUG: AtomicBool = AtomicBool::new(false);
 
 pub fn parse_args(compilable: bool) -> Option<Args> {
@@ -56,9 +137,18 @@ pub fn parse_args(compilable: bool) -> Option<Args> {
     let mut show_ast = false;
     let mut show_debug_ast = false;
     let mut optimize = false;
+    let mut fast_math = false;
     let mut show_help = false;
     let mut args_is_empty = true;
     let mut debug_output = false;
+    let mut lib = false;
+    let mut dump_symbols = false;
+    let mut coverage = false;
+    let mut max_functions = None;
+    let mut limit_output_bytes = None;
+    let mut max_expr_depth = None;
+    let mut script_args = vec![];
+    let mut breakpoints = vec![];
 
     let mut args = std::env::args();
     let exe = args.next();
@@ -69,15 +159,67 @@ pub fn parse_args(compilable: bool) -> Option<Args> {
             "-c" => run_mode = RunMode::Compile,
             "-o" => output = args.next(),
             "-O" => optimize = true,
+            "--fast-math" => fast_math = true,
             "-r" => {
                 let bytecode = args.next().unwrap_or_else(|| "bytecode.bin".to_string());
                 run_mode = RunMode::Run(bytecode);
             }
             "-R" => run_mode = RunMode::CompileAndRun,
+            "--disasm" => {
+                let Some(bytecode) = args.next() else {
+                    println!("Please specify a bytecode file after --disasm");
+                    return None;
+                };
+                run_mode = RunMode::Disasm(bytecode);
+            }
             "-d" => disasm = true,
             "-a" => show_ast = true,
             "-A" => show_debug_ast = true,
             "-t" => run_mode = RunMode::TypeCheck,
+            "--repl" => run_mode = RunMode::Repl,
+            "--lib" => lib = true,
+            "--dump-symbols" => dump_symbols = true,
+            "--coverage" => coverage = true,
+            "--max-functions" => {
+                let Some(max) = args.next().and_then(|max| max.parse().ok()) else {
+                    println!("Please specify a number after --max-functions");
+                    return None;
+                };
+                max_functions = Some(max);
+            }
+            "--limit-output-bytes" => {
+                let Some(limit) = args.next().and_then(|limit| limit.parse().ok()) else {
+                    println!("Please specify a number after --limit-output-bytes");
+                    return None;
+                };
+                limit_output_bytes = Some(limit);
+            }
+            "--max-expr-depth" => {
+                let Some(depth) = args.next().and_then(|depth| depth.parse().ok()) else {
+                    println!("Please specify a number after --max-expr-depth");
+                    return None;
+                };
+                max_expr_depth = Some(depth);
+            }
+            "--break" => {
+                let Some(line) = args.next().and_then(|line| line.parse().ok()) else {
+                    println!("Please specify a line number after --break");
+                    return None;
+                };
+                breakpoints.push(line);
+            }
+            "--explain" => {
+                let Some(code) = args.next() else {
+                    println!("Please specify an error code after --explain");
+                    return None;
+                };
+                run_mode = RunMode::Explain(code);
+            }
+            "--" => {
+                script_args = args.by_ref().collect();
+                args_is_empty = false;
+                break;
+            }
             "-D" => {
                 DEBUG.store(true, std::sync::atomic::Ordering::Relaxed);
                 debug_output = true;
@@ -102,7 +244,17 @@ pub fn parse_args(compilable: bool) -> Option<Args> {
     -r       Run bytecode
     -R       Compile and run
     -d       Disassemble compiled code
-    -O       Optimize code by constant folding/propagation
+    --disasm file
+             Disassemble a compiled bytecode file without running it
+    -O       Optimize code by folding arithmetic on literals
+    --fast-math
+             Optimize code and reassociate arithmetic to fold more literals,
+             which may change the rounding of the results
+    --lib    Compile source file as a library without main function
+    --max-functions n
+             Limit the number of functions a program can define
+    --break line
+             Stop into the debugger before the source line runs
     -a       Show parsed AST
     -A       Show parsed AST in debug form"#
         } else {
@@ -115,6 +267,18 @@ Options:
 {compiler_options}
     -a       Show AST
     -t       Typecheck only, no execution of the code
+    --dump-symbols
+             Show variables and functions of every scope after type checking
+    --repl   Start an interactive read-eval-print loop
+    --coverage
+             Report the source lines which were executed and not executed
+    --limit-output-bytes n
+             Stop the script when it prints more than n bytes
+    --max-expr-depth n
+             Limit the depth of nested expressions and blocks (default 200)
+    --explain code
+             Explain the error code (e.g. E002)
+    -- args  Pass the rest of the arguments to the main function of the script
     -h       Display help
 "#,
             exe.unwrap_or_else(|| "29-full-stmt".to_string())
@@ -130,7 +294,16 @@ Options:
         show_ast,
         show_debug_ast,
         optimize,
+        fast_math,
         debug_output,
+        lib,
+        dump_symbols,
+        coverage,
+        max_functions,
+        limit_output_bytes,
+        max_expr_depth,
+        script_args,
+        breakpoints,
         // additional_funcs: HashMap::new(),
     })
 }
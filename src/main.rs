@@ -1,38 +1,60 @@
-mod compiler;
-mod parser;
-mod value;
-use compiler::{compile, debugger, read_program, Vm, YieldResult};
-use ruscal::{parse_args, RunMode};
+use ruscal::compiler::{compile, debugger, read_program, repl, Vm, YieldResult};
+use ruscal::explain::ErrorCode;
+use ruscal::Value;
+use ruscal::{output, parse_args, parser, RunMode};
 use std::{
     io::{BufReader, BufWriter},
     rc::Rc,
 };
-use value::Value;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let Some(args) = parse_args(true) else {
         return Ok(());
     };
 
+    // The command line isn't sandboxed, so scripts can measure time for benchmarks
+    ruscal::register_time_natives();
+    if let Some(depth) = args.max_expr_depth {
+        parser::set_max_expr_depth(depth);
+    }
+
     let run_coro = |mut vm: Vm| {
-        if let Err(e) = vm.init_fn("main", &[]) {
+        if let Some(limit) = args.limit_output_bytes {
+            vm = vm.with_output_limit(limit);
+        }
+        if args.coverage {
+            vm.enable_coverage();
+        }
+        for line in &args.breakpoints {
+            vm.set_breakpoint(*line);
+        }
+        if let Err(e) = vm.init_main(&args.script_args) {
             eprintln!("init_fn error: {e:?}");
+            return;
         }
         loop {
             match vm.interpret() {
                 Ok(YieldResult::Finished(_)) => break,
                 Ok(YieldResult::Suspend(value)) => {
                     println!("Execution suspended with a yielded value {value}");
-                    if value == Value::Str("break".to_string()) && debugger(&vm) {
+                    if value == Value::str("break") && debugger(&mut vm) {
+                        break;
+                    }
+                }
+                Ok(YieldResult::Breakpoint(line)) => {
+                    println!("Stopped at breakpoint at line {line}");
+                    if debugger(&mut vm) {
                         break;
                     }
                 }
                 Err(e) => {
-                    eprintln!("Runtime error: {e:?}");
+                    eprintln!("Runtime error: {e}");
                     break;
                 }
             }
         }
+        output::flush();
+        vm.write_coverage(&mut std::io::stdout()).ok();
     };
 
     match args.run_mode {
@@ -54,6 +76,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let bytecode = Rc::new(read_program(&mut reader)?);
             run_coro(Vm::new(bytecode));
         }
+        RunMode::Disasm(code_file) => {
+            let reader = std::fs::File::open(&code_file)?;
+            let bytecode = read_program(&mut BufReader::new(reader))?;
+            bytecode.disasm(&mut std::io::stdout())?;
+        }
         RunMode::CompileAndRun => {
             let mut buf = vec![];
             if let Err(e) = compile(&mut std::io::Cursor::new(&mut buf), &args, "<Memory>") {
@@ -63,7 +90,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let bytecode = Rc::new(read_program(&mut std::io::Cursor::new(&mut buf))?);
             run_coro(Vm::new(bytecode));
         }
-        _ => println!("Please specify -c, -r, -t or -R as an argument"),
+        RunMode::Repl => repl(&args)?,
+        RunMode::Explain(code) => match ErrorCode::parse(&code) {
+            Some(code) => print!("{}", code.explanation()),
+            None => eprintln!("Unknown error code: {code}"),
+        },
+        _ => println!("Please specify -c, -r, -t, -R or --repl as an argument"),
     }
     Ok(())
 }
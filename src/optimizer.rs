@@ -0,0 +1,295 @@
+use crate::parser::{ExprEnum, Expression, Span, Statement, Statements};
+
+/// Rewrite the tree into a canonical form, recursively, so that later passes have fewer shapes
+/// to handle.
+///
+/// * `a > b` becomes `b < a`, and `a != b` becomes `!(a == b)`, the same lowering the compiler
+///   does, so the evaluation order of the operands doesn't change.
+/// * A number literal on the left of `*` or `==` is moved to the right, e.g. `2 * x` becomes
+///   `x * 2`. Literals have no side effects and both operators are commutative for numbers.
+///
+/// Every rewrite gives bit-exact results. Chains of `+` and `*` are left-nested by the parser
+/// already, and regrouping them or turning `a - b` into `a + (-b)` would reassociate floats,
+/// which is left to `reassociate`. Parentheses don't leave any node in the tree to collapse.
+pub fn normalize(stmts: &mut Statements) {
+    for stmt in stmts {
+        walk_stmt(stmt, normalize_expr, normalize);
+    }
+}
+
+fn normalize_expr(ex: &mut Expression) {
+    use ExprEnum::*;
+    walk_sub_exprs(ex, normalize_expr, normalize);
+
+    let span = ex.span;
+    let expr = std::mem::replace(&mut ex.expr, NumLiteral(0.));
+    ex.expr = match expr {
+        Gt(lhs, rhs) => Lt(rhs, lhs),
+        Neq(lhs, rhs) => Not(Box::new(Expression::new(Eq(lhs, rhs), span))),
+        Mul(lhs, rhs) if is_num_literal(&lhs) && !is_num_literal(&rhs) => Mul(rhs, lhs),
+        Eq(lhs, rhs) if is_num_literal(&lhs) && !is_num_literal(&rhs) => Eq(rhs, lhs),
+        expr => expr,
+    };
+}
+
+fn is_num_literal(ex: &Expression) -> bool {
+    matches!(ex.expr, ExprEnum::NumLiteral(_))
+}
+
+/// Fold arithmetic on literals into a single literal, recursively.
+///
+/// Number literals are `f64`, so folding gives the same result as the `Vm` would.
+/// Divisions by zero are left for the runtime.
+pub fn fold_constants(stmts: &mut Statements) {
+    for stmt in stmts {
+        walk_stmt(stmt, fold_expr, fold_constants);
+    }
+}
+
+/// Regroup chains of arithmetic to fold the number literals in them, recursively, for
+/// `--fast-math`. The results may differ from IEEE 754 arithmetic in rounding.
+///
+/// * Operands of a chain of `+` and `-` are collected, and the literals in it are summed up
+///   and added last, e.g. `(a + 1) - b + 2` becomes `a - b + 3`.
+/// * A subtraction of a literal is an addition of its negation, e.g. `a - 1` becomes `a + -1`.
+/// * Operands of a chain of `*` are collected likewise, and a division by a nonzero literal is
+///   a multiplication by its reciprocal, e.g. `(a * 2) / 4` becomes `a * 0.5`.
+///   A division by anything else is not regrouped, since integer division truncates.
+///
+/// Operands other than literals are evaluated in the same order as before. A literal `0` in a
+/// sum or `1` in a product is kept, since it turns an `i64` operand into `f64`.
+pub fn reassociate(stmts: &mut Statements) {
+    for stmt in stmts {
+        walk_stmt(stmt, reassociate_expr, reassociate);
+    }
+}
+
+fn reassociate_expr(ex: &mut Expression) {
+    walk_sub_exprs(ex, reassociate_expr, reassociate);
+
+    let chain = match ex.expr {
+        ExprEnum::Add(..) | ExprEnum::Sub(..) => Chain::Sum,
+        ExprEnum::Mul(..) | ExprEnum::Div(..) => Chain::Product,
+        _ => return,
+    };
+    let mut operands = vec![];
+    if let Some(literal) = chain.collect(ex, &mut operands) {
+        *ex = chain.rebuild(operands, literal, ex.span);
+    }
+}
+
+/// A chain of `+` and `-`, or `*` and `/`, which `reassociate` regroups
+#[derive(Clone, Copy)]
+enum Chain {
+    Sum,
+    Product,
+}
+
+impl Chain {
+    /// Split an operation in the chain into its operands, with whether the right one is
+    /// subtracted or divided by
+    fn split<'a, 'b>(
+        self,
+        ex: &'b Expression<'a>,
+    ) -> Option<(&'b Expression<'a>, &'b Expression<'a>, bool)> {
+        use ExprEnum::*;
+        match (self, &ex.expr) {
+            (Chain::Sum, Add(lhs, rhs)) | (Chain::Product, Mul(lhs, rhs)) => {
+                Some((lhs, rhs, false))
+            }
+            (Chain::Sum, Sub(lhs, rhs)) => Some((lhs, rhs, true)),
+            (Chain::Product, Div(lhs, rhs)) if matches!(rhs.expr, NumLiteral(rhs) if rhs != 0.) => {
+                Some((lhs, rhs, true))
+            }
+            _ => None,
+        }
+    }
+
+    fn combine(self, acc: f64, value: f64, inverted: bool) -> f64 {
+        match (self, inverted) {
+            (Chain::Sum, false) => acc + value,
+            (Chain::Sum, true) => acc - value,
+            (Chain::Product, false) => acc * value,
+            (Chain::Product, true) => acc / value,
+        }
+    }
+
+    fn make<'a>(self, inverted: bool, lhs: Expression<'a>, rhs: Expression<'a>) -> ExprEnum<'a> {
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        match (self, inverted) {
+            (Chain::Sum, false) => ExprEnum::Add(lhs, rhs),
+            (Chain::Sum, true) => ExprEnum::Sub(lhs, rhs),
+            (Chain::Product, false) => ExprEnum::Mul(lhs, rhs),
+            (Chain::Product, true) => ExprEnum::Div(lhs, rhs),
+        }
+    }
+
+    /// Collect the operands other than literals from left to right into `operands`, with
+    /// whether each is inverted, and return the combination of the literals. It is `None` if
+    /// there is no literal to combine or a string is concatenated.
+    fn collect<'a>(
+        self,
+        ex: &Expression<'a>,
+        operands: &mut Vec<(bool, Expression<'a>)>,
+    ) -> Option<f64> {
+        let mut literal = None;
+        let mut stack = vec![(ex, false)];
+        while let Some((ex, inverted)) = stack.pop() {
+            if let Some((lhs, rhs, invert_rhs)) = self.split(ex) {
+                stack.push((rhs, inverted != invert_rhs));
+                stack.push((lhs, inverted));
+                continue;
+            }
+            match ex.expr {
+                ExprEnum::NumLiteral(value) => {
+                    let identity = match self {
+                        Chain::Sum => 0.,
+                        Chain::Product => 1.,
+                    };
+                    literal = Some(self.combine(literal.unwrap_or(identity), value, inverted));
+                }
+                ExprEnum::StrLiteral(_) => return None,
+                _ => operands.push((inverted, ex.clone())),
+            }
+        }
+        literal
+    }
+
+    /// Build the chain of `operands` followed by the `literal`, or preceded by it if the first
+    /// operand is inverted.
+    fn rebuild<'a>(
+        self,
+        operands: Vec<(bool, Expression<'a>)>,
+        literal: f64,
+        span: Span<'a>,
+    ) -> Expression<'a> {
+        let literal = Expression::new(ExprEnum::NumLiteral(literal), span);
+        let mut operands = operands.into_iter();
+        let (mut acc, mut literal) = match operands.next() {
+            None => return literal,
+            Some((true, first)) => (Expression::new(self.make(true, literal, first), span), None),
+            Some((false, first)) => (first, Some(literal)),
+        };
+        for (inverted, operand) in operands {
+            acc = Expression::new(self.make(inverted, acc, operand), span);
+        }
+        if let Some(literal) = literal.take() {
+            acc = Expression::new(self.make(false, acc, literal), span);
+        }
+        acc
+    }
+}
+
+/// Apply `on_expr` to the expressions directly in a statement and `on_stmts` to its blocks.
+fn walk_stmt<'a>(
+    stmt: &mut Statement<'a>,
+    on_expr: fn(&mut Expression<'a>),
+    on_stmts: fn(&mut Statements<'a>),
+) {
+    match stmt {
+        Statement::Expression(ex) | Statement::Return(ex) => on_expr(ex),
+        Statement::VarDef { ex, .. } | Statement::VarAssign { ex, .. } => on_expr(ex),
+        Statement::ArrayIndexAssign { indices, ex, .. } => {
+            indices.iter_mut().for_each(on_expr);
+            on_expr(ex);
+        }
+        Statement::For {
+            start,
+            end,
+            step,
+            stmts,
+            ..
+        } => {
+            on_expr(start);
+            on_expr(end);
+            if let Some(step) = step {
+                on_expr(step);
+            }
+            on_stmts(stmts);
+        }
+        Statement::While { cond, stmts, .. } => {
+            on_expr(cond);
+            on_stmts(stmts);
+        }
+        Statement::Match {
+            scrutinee,
+            arms,
+            default,
+            ..
+        } => {
+            on_expr(scrutinee);
+            for (_, stmts) in arms {
+                on_stmts(stmts);
+            }
+            if let Some(stmts) = default {
+                on_stmts(stmts);
+            }
+        }
+        Statement::FnDef { stmts, .. } => on_stmts(stmts),
+        Statement::Break | Statement::Continue | Statement::Comment => {}
+    }
+}
+
+/// Apply `on_expr` to the operands of an expression and `on_stmts` to its blocks.
+fn walk_sub_exprs<'a>(
+    ex: &mut Expression<'a>,
+    on_expr: fn(&mut Expression<'a>),
+    on_stmts: fn(&mut Statements<'a>),
+) {
+    use ExprEnum::*;
+    match &mut ex.expr {
+        Ident(_) | NumLiteral(_) | StrLiteral(_) | BoolLiteral(_) => {}
+        ArrayLiteral(values) | ArrayIndexAccess(_, values) | FnInvoke(_, values) => {
+            values.iter_mut().for_each(on_expr);
+        }
+        Add(lhs, rhs)
+        | Sub(lhs, rhs)
+        | Mul(lhs, rhs)
+        | Div(lhs, rhs)
+        | And(lhs, rhs)
+        | Or(lhs, rhs)
+        | Gt(lhs, rhs)
+        | Lt(lhs, rhs)
+        | Eq(lhs, rhs)
+        | Neq(lhs, rhs) => {
+            on_expr(lhs);
+            on_expr(rhs);
+        }
+        Not(ex) | Cast(ex, _) | Await(ex) | Yield(ex) => on_expr(ex),
+        Lambda(_, _, stmts) => on_stmts(stmts),
+        If(cond, true_branch, false_branch) => {
+            on_expr(cond);
+            on_stmts(true_branch);
+            if let Some(false_branch) = false_branch {
+                on_stmts(false_branch);
+            }
+        }
+    }
+}
+
+fn fold_expr(ex: &mut Expression) {
+    use ExprEnum::*;
+    walk_sub_exprs(ex, fold_expr, fold_constants);
+
+    let folded = match &ex.expr {
+        Add(lhs, rhs) => match (&lhs.expr, &rhs.expr) {
+            (NumLiteral(lhs), NumLiteral(rhs)) => NumLiteral(lhs + rhs),
+            (StrLiteral(lhs), StrLiteral(rhs)) => StrLiteral(format!("{lhs}{rhs}")),
+            _ => return,
+        },
+        Sub(lhs, rhs) => match (&lhs.expr, &rhs.expr) {
+            (NumLiteral(lhs), NumLiteral(rhs)) => NumLiteral(lhs - rhs),
+            _ => return,
+        },
+        Mul(lhs, rhs) => match (&lhs.expr, &rhs.expr) {
+            (NumLiteral(lhs), NumLiteral(rhs)) => NumLiteral(lhs * rhs),
+            _ => return,
+        },
+        Div(lhs, rhs) => match (&lhs.expr, &rhs.expr) {
+            (NumLiteral(lhs), NumLiteral(rhs)) if *rhs != 0. => NumLiteral(lhs / rhs),
+            _ => return,
+        },
+        _ => return,
+    };
+    ex.expr = folded;
+}
@@ -0,0 +1,104 @@
+use std::{
+    cell::{Cell, RefCell},
+    io::Write,
+    rc::Rc,
+};
+
+/// Limit of the bytes a `Vm` and its coroutines can write by the print natives.
+///
+/// The `Vm` owns the budget and shares it with the coroutines it starts, so every `Vm` counts
+/// its own output even when several run on the same thread.
+#[derive(Debug, Default)]
+pub struct OutputBudget {
+    limit: Option<usize>,
+    written: Cell<usize>,
+    exceeded: Cell<bool>,
+}
+
+impl OutputBudget {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Bytes written so far
+    pub fn written(&self) -> usize {
+        self.written.get()
+    }
+
+    /// Whether some output was suppressed because it would have exceeded the limit
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.get()
+    }
+}
+
+/// Output sink of the print natives.
+///
+/// Natives don't have access to the `Vm`, so the sink is kept per thread, and the `Vm` lends
+/// its budget to it while a native runs. Once the total bytes would exceed the limit, further
+/// output is suppressed and the `Vm` reports a runtime error after the native returns.
+struct Output {
+    /// Redirected destination, or `None` for stdout
+    writer: Option<Box<dyn Write>>,
+    /// Budget of the `Vm` running the current native, or `None` outside of a `Vm`
+    budget: Option<Rc<OutputBudget>>,
+}
+
+thread_local! {
+    static OUTPUT: RefCell<Output> = const {
+        RefCell::new(Output {
+            writer: None,
+            budget: None,
+        })
+    };
+}
+
+/// Run `f` with the output counted against `budget`, restoring the budget of the enclosing
+/// call afterwards, since a native can run another `Vm`.
+pub(crate) fn with_budget<T>(budget: &Rc<OutputBudget>, f: impl FnOnce() -> T) -> T {
+    let outer = OUTPUT.with_borrow_mut(|output| output.budget.replace(budget.clone()));
+    let res = f();
+    OUTPUT.with_borrow_mut(|output| output.budget = outer);
+    res
+}
+
+/// Redirect the output of the print natives to the writer, or back to stdout with `None`.
+pub fn set_writer(writer: Option<Box<dyn Write>>) {
+    OUTPUT.with_borrow_mut(|output| output.writer = writer);
+}
+
+pub fn write(s: &str) {
+    OUTPUT.with_borrow_mut(|output| {
+        if let Some(budget) = &output.budget {
+            if budget.exceeded.get() {
+                return;
+            }
+            if let Some(limit) = budget.limit {
+                if limit < budget.written.get() + s.len() {
+                    budget.exceeded.set(true);
+                    return;
+                }
+            }
+            budget.written.set(budget.written.get() + s.len());
+        }
+        match &mut output.writer {
+            Some(writer) => {
+                writer.write_all(s.as_bytes()).ok();
+            }
+            None => print!("{s}"),
+        }
+    });
+}
+
+pub fn flush() {
+    OUTPUT.with_borrow_mut(|output| match &mut output.writer {
+        Some(writer) => writer.flush().ok(),
+        None => std::io::stdout().flush().ok(),
+    });
+}
@@ -2,30 +2,141 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, take_until},
     character::complete::{alpha1, alphanumeric1, char, multispace0, multispace1, none_of},
-    combinator::{cut, map_res, opt, recognize},
-    error::ParseError,
-    multi::{fold_many0, many0, many1, separated_list0},
+    combinator::{consumed, cut, map_res, opt, peek, recognize, verify},
+    error::{ErrorKind, ParseError},
+    multi::{many0, many1, separated_list0},
     number::complete::recognize_float,
     sequence::{delimited, pair, preceded, terminated},
     Finish, IResult, InputTake, Offset, Parser,
 };
 use nom_locate::LocatedSpan;
-use std::{collections::HashMap, error::Error};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    error::Error,
+    io::Write,
+    rc::Rc,
+};
 
-use crate::value::Value;
+use crate::{
+    compiler::{RuntimeError, YieldResult},
+    explain::ErrorCode,
+    output, random,
+    value::Value,
+};
 
 pub type Functions<'src> = HashMap<String, FnDecl<'src>>;
 
+/// Default of the limit set by `set_max_expr_depth`
+pub const DEFAULT_MAX_EXPR_DEPTH: usize = 200;
+
+thread_local! {
+    static MAX_EXPR_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_EXPR_DEPTH) };
+    /// Number of expressions and blocks being parsed, one inside another
+    static NESTING: Cell<usize> = const { Cell::new(0) };
+    static HOST_FUNCTIONS: RefCell<Vec<HostFn>> = const { RefCell::new(vec![]) };
+}
+
+/// Native function registered by the host with `register_native`
+struct HostFn {
+    name: String,
+    args: Vec<(&'static str, TypeDecl)>,
+    ret_type: TypeDecl,
+    code: Rc<NativeFnCode>,
+}
+
+/// Add a native function which the scripts compiled afterwards on this thread can call,
+/// replacing a standard or registered function of the same name.
+///
+/// The type checker zips the arguments of a call with `args`, and `code` receives all the
+/// arguments passed. The compiled bytecode keeps the function for as long as it runs, so the
+/// closure can't borrow from the host and must be `'static`; share state with it through an
+/// `Rc` instead.
+pub fn register_native(
+    name: &str,
+    args: Vec<(&'static str, TypeDecl)>,
+    ret_type: TypeDecl,
+    code: impl Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+) {
+    HOST_FUNCTIONS.with_borrow_mut(|funcs| {
+        funcs.retain(|f| f.name != name);
+        funcs.push(HostFn {
+            name: name.to_string(),
+            args,
+            ret_type,
+            code: Rc::new(code),
+        });
+    });
+}
+
+/// Register `now()`, the milliseconds since the Unix epoch, and `clock()`, the seconds elapsed
+/// since this call on a monotonic clock.
+///
+/// They aren't standard functions because their results differ every run, so a sandboxed host
+/// has to opt in to let scripts see the time.
+pub fn register_time_natives() {
+    register_native("now", vec![], TypeDecl::I64, |_| {
+        let elapsed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| RuntimeError::Native(format!("now: {e}")))?;
+        Ok(Value::I64(elapsed.as_millis() as i64))
+    });
+    let start = std::time::Instant::now();
+    register_native("clock", vec![], TypeDecl::F64, move |_| {
+        Ok(Value::F64(start.elapsed().as_secs_f64()))
+    });
+}
+
+/// Set the maximum depth of nested expressions, and of nested expressions and blocks while
+/// parsing. Deeper source is a syntax error rather than overflowing the stack of the
+/// recursive parser, type checker and compiler.
+pub fn set_max_expr_depth(depth: usize) {
+    MAX_EXPR_DEPTH.set(depth);
+}
+
+pub fn max_expr_depth() -> usize {
+    MAX_EXPR_DEPTH.get()
+}
+
+fn too_deep<T>(i: Span) -> IResult<Span, T> {
+    Err(nom::Err::Failure(nom::error::Error::new(
+        i,
+        ErrorKind::TooLarge,
+    )))
+}
+
+/// Run a parser which can recurse into `nested` again, failing once the nesting is too deep.
+fn nested<'src, O>(
+    i: Span<'src>,
+    f: impl FnOnce(Span<'src>) -> IResult<Span<'src>, O>,
+) -> IResult<Span<'src>, O> {
+    let nesting = NESTING.get();
+    if max_expr_depth() <= nesting {
+        return too_deep(i);
+    }
+    NESTING.set(nesting + 1);
+    let res = f(i);
+    NESTING.set(nesting);
+    res
+}
+
+fn missing_arg() -> RuntimeError {
+    RuntimeError::Native("function missing argument".to_string())
+}
+
+/// The next argument of a native, or an error if the call passed fewer than it needs
+pub(crate) fn next_arg<'a>(
+    args: &mut impl Iterator<Item = &'a Value>,
+) -> Result<&'a Value, RuntimeError> {
+    args.next().ok_or_else(missing_arg)
+}
+
 fn unary_fn<'a>(f: fn(f64) -> f64) -> FnDecl<'a> {
     FnDecl::Native(NativeFn {
         args: vec![("arg", TypeDecl::F64)],
         ret_type: TypeDecl::F64,
         code: Box::new(move |args| {
-            Value::F64(f(args
-                .iter()
-                .next()
-                .expect("function missing argument")
-                .coerce_f64()))
+            Ok(Value::F64(f(next_arg(&mut args.iter())?.try_coerce_f64()?)))
         }),
     })
 }
@@ -36,40 +147,529 @@ fn binary_fn<'a>(f: fn(f64, f64) -> f64) -> FnDecl<'a> {
         ret_type: TypeDecl::F64,
         code: Box::new(move |args| {
             let mut args = args.iter();
-            let lhs = args.next().expect("function missing argument").coerce_f64();
-            let rhs = args.next().expect("function missing argument").coerce_f64();
-            Value::F64(f(lhs, rhs))
+            let lhs = next_arg(&mut args)?.try_coerce_f64()?;
+            let rhs = next_arg(&mut args)?.try_coerce_f64()?;
+            Ok(Value::F64(f(lhs, rhs)))
+        }),
+    })
+}
+
+/// A numeric function which keeps integers exact, and promotes to `F64` if either argument is one
+fn num_binary_fn<'a>(f_i64: fn(i64, i64) -> i64, f_f64: fn(f64, f64) -> f64) -> FnDecl<'a> {
+    FnDecl::Native(NativeFn {
+        args: vec![("lhs", TypeDecl::Any), ("rhs", TypeDecl::Any)],
+        ret_type: TypeDecl::Any,
+        code: Box::new(move |args| {
+            let mut args = args.iter();
+            let lhs = next_arg(&mut args)?;
+            let rhs = next_arg(&mut args)?;
+            Ok(match (lhs, rhs) {
+                (Value::I64(lhs), Value::I64(rhs)) => Value::I64(f_i64(*lhs, *rhs)),
+                _ => Value::F64(f_f64(lhs.try_coerce_f64()?, rhs.try_coerce_f64()?)),
+            })
+        }),
+    })
+}
+
+fn abs_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    match next_arg(&mut args.iter())? {
+        Value::I64(value) => value
+            .checked_abs()
+            .map(Value::I64)
+            .ok_or_else(|| RuntimeError::Native(format!("abs: {value} overflows i64"))),
+        value => Ok(Value::F64(value.try_coerce_f64()?.abs())),
+    }
+}
+
+/// Integer arithmetic which wraps around on overflow instead of raising an error
+fn wrapping_fn<'a>(f: fn(i64, i64) -> i64) -> FnDecl<'a> {
+    FnDecl::Native(NativeFn {
+        args: vec![("lhs", TypeDecl::I64), ("rhs", TypeDecl::I64)],
+        ret_type: TypeDecl::I64,
+        code: Box::new(move |args| {
+            let mut args = args.iter();
+            let lhs = next_arg(&mut args)?.try_coerce_i64()?;
+            let rhs = next_arg(&mut args)?.try_coerce_i64()?;
+            Ok(Value::I64(f(lhs, rhs)))
+        }),
+    })
+}
+
+fn unary_str_fn<'a>(f: fn(&str) -> String) -> FnDecl<'a> {
+    FnDecl::Native(NativeFn {
+        args: vec![("s", TypeDecl::Str)],
+        ret_type: TypeDecl::Str,
+        code: Box::new(move |args| {
+            Ok(Value::Str(
+                f(&next_arg(&mut args.iter())?.try_coerce_str()?).into(),
+            ))
         }),
     })
 }
 
-fn print_fn(args: &[Value]) -> Value {
+/// A native testing a string against another one, named `other`, which returns 1 or 0
+fn str_test_fn<'a>(other: &'static str, f: fn(&str, &str) -> bool) -> FnDecl<'a> {
+    FnDecl::Native(NativeFn {
+        args: vec![("s", TypeDecl::Str), (other, TypeDecl::Str)],
+        ret_type: TypeDecl::I64,
+        code: Box::new(move |args| {
+            let mut args = args.iter();
+            let s = next_arg(&mut args)?.try_coerce_str()?;
+            let other = next_arg(&mut args)?.try_coerce_str()?;
+            Ok(Value::I64(f(&s, &other) as i64))
+        }),
+    })
+}
+
+fn str_len_fn<'a>(f: fn(&str) -> usize) -> FnDecl<'a> {
+    FnDecl::Native(NativeFn {
+        args: vec![("s", TypeDecl::Str)],
+        ret_type: TypeDecl::I64,
+        code: Box::new(move |args| {
+            Ok(Value::I64(
+                f(&next_arg(&mut args.iter())?.try_coerce_str()?) as i64,
+            ))
+        }),
+    })
+}
+
+fn print_fn(args: &[Value]) -> Result<Value, RuntimeError> {
     for arg in args {
-        print!("{}", arg);
+        output::write(&format!("{}", arg));
     }
-    Value::F64(0.)
+    Ok(Value::F64(0.))
 }
 
-fn println_fn(args: &[Value]) -> Value {
+fn println_fn(args: &[Value]) -> Result<Value, RuntimeError> {
     for arg in args {
-        print!("{}", arg);
+        output::write(&format!("{}", arg));
     }
-    println!();
-    Value::F64(0.)
+    output::write("\n");
+    Ok(Value::F64(0.))
 }
 
-fn dbg_fn(values: &[Value]) -> Value {
-    println!("dbg: {:?}", values[0]);
-    Value::I64(0)
+/// Print the value with the location and the source of the argument, which the parser passes
+/// first, and return the value.
+fn dbg_fn(values: &[Value]) -> Result<Value, RuntimeError> {
+    let value = match values {
+        [Value::Str(label), value] => {
+            output::write(&format!("{label} = {value}\n"));
+            value
+        }
+        // Called through a function value, which the parser can't see
+        [value, ..] => {
+            output::write(&format!("{value}\n"));
+            value
+        }
+        [] => return Err(missing_arg()),
+    };
+    Ok(value.clone())
 }
 
-fn puts_fn(args: &[Value]) -> Value {
+fn puts_fn(args: &[Value]) -> Result<Value, RuntimeError> {
     for arg in args {
-        print!("{}", arg);
+        output::write(&format!("{}", arg));
+    }
+    Ok(Value::F64(0.))
+}
+
+/// Format an integer in the given radix, zero-padded to the optional width argument.
+/// Negative numbers are represented with a sign and the magnitude, e.g. `-ff`.
+fn radix_fn<'a>(f: fn(u64) -> String) -> FnDecl<'a> {
+    FnDecl::Native(NativeFn {
+        args: vec![("n", TypeDecl::I64), ("width", TypeDecl::I64)],
+        ret_type: TypeDecl::Str,
+        code: Box::new(move |args| {
+            let mut args = args.iter();
+            let n = next_arg(&mut args)?.try_coerce_i64()?;
+            let width = match args.next() {
+                Some(width) => width.try_coerce_i64()?.max(0) as usize,
+                None => 0,
+            };
+            let sign = if n < 0 { "-" } else { "" };
+            Ok(Value::Str(
+                format!("{sign}{:0>width$}", f(n.unsigned_abs())).into(),
+            ))
+        }),
+    })
+}
+
+/// Read one line from stdin. The source is read from a file, so stdin is left for the script.
+fn read_line_fn(_: &[Value]) -> Result<Value, RuntimeError> {
+    // flush the prompt printed by `print` before waiting for input
+    output::flush();
+    let mut buffer = String::new();
+    std::io::stdin()
+        .read_line(&mut buffer)
+        .map_err(|e| RuntimeError::Native(format!("read_line failed: {e}")))?;
+    let len = buffer.trim_end_matches(['\n', '\r']).len();
+    buffer.truncate(len);
+    Ok(Value::Str(buffer.into()))
+}
+
+/// A native which parses its string argument, ignoring the surrounding whitespace,
+/// and raises an error naming the expected kind if it isn't a valid number
+fn parse_fn<'a, T: std::str::FromStr + 'static>(
+    name: &'static str,
+    kind: &'static str,
+    ret_type: TypeDecl,
+    f: fn(T) -> Value,
+) -> FnDecl<'a> {
+    FnDecl::Native(NativeFn {
+        args: vec![("s", TypeDecl::Str)],
+        ret_type,
+        code: Box::new(move |args| {
+            let s = next_arg(&mut args.iter())?.try_coerce_str()?;
+            s.trim()
+                .parse()
+                .map(f)
+                .map_err(|_| RuntimeError::Native(format!("{name}: {s:?} is not a valid {kind}")))
+        }),
+    })
+}
+
+/// Arrays are reference types, so `push` appends to the array in place and returns it.
+/// An array can't be pushed into itself or an array inside it, since that would make a cycle.
+fn push_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let arr = next_arg(&mut args)?;
+    let value = next_arg(&mut args)?;
+    let Value::Array(values) = arr else {
+        return Err(arr.coercion_error("array"));
+    };
+    if value.contains_array(values) {
+        return Err(RuntimeError::Native(
+            "push: an array can't contain itself".to_string(),
+        ));
+    }
+    values.borrow_mut().push(value.clone());
+    Ok(arr.clone())
+}
+
+fn pop_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let arr = next_arg(&mut args.iter())?;
+    let Value::Array(values) = arr else {
+        return Err(arr.coercion_error("array"));
+    };
+    let value = values.borrow_mut().pop();
+    value.ok_or_else(|| RuntimeError::Native("pop from an empty array".to_string()))
+}
+
+/// A longer range fails with an error instead of aborting on the allocation.
+const MAX_RANGE_LEN: usize = 1 << 24;
+
+/// `range(end)`, `range(start, end)` or `range(start, end, step)`, an array of the integers from
+/// `start` (0 by default) up to but not including `end`, counting down if `step` is negative
+fn range_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let args = args
+        .iter()
+        .map(Value::try_coerce_i64)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (start, end, step) = match args[..] {
+        [end] => (0, end, 1),
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step),
+        [] => return Err(missing_arg()),
+        _ => {
+            return Err(RuntimeError::Native(format!(
+                "range takes 1 to 3 arguments, but {} were given",
+                args.len()
+            )))
+        }
+    };
+    if step == 0 {
+        return Err(RuntimeError::Native(
+            "range: step must not be zero".to_string(),
+        ));
+    }
+    // Counted in i128 up front, since the distance and the last value can overflow i64
+    let (start, end, step) = (start as i128, end as i128, step as i128);
+    let len = if 0 < step {
+        (end - start + step - 1).max(0) / step
+    } else {
+        (end - start + step + 1).min(0) / step
+    };
+    if (MAX_RANGE_LEN as i128) < len {
+        return Err(RuntimeError::Native(format!(
+            "range: {len} elements exceed the limit of {MAX_RANGE_LEN}"
+        )));
+    }
+    let values = (0..len)
+        .map(|i| Value::I64((start + i * step) as i64))
+        .collect();
+    Ok(Value::array(values))
+}
+
+/// A reversed copy of the array, leaving the argument as it is
+fn reverse_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let arr = next_arg(&mut args.iter())?;
+    let Value::Array(values) = arr else {
+        return Err(arr.coercion_error("array"));
+    };
+    let reversed = values.borrow().iter().rev().cloned().collect();
+    Ok(Value::array(reversed))
+}
+
+/// Resume the coroutine up to `n` times and collect the yielded values into an array.
+fn take_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let coro = next_arg(&mut args)?;
+    let n = next_arg(&mut args)?.try_coerce_i64()?;
+    let Value::Coro(vm) = coro else {
+        return Err(coro.coercion_error("cofn"));
+    };
+    let values = vm
+        .borrow_mut()
+        .take(n.max(0) as usize)
+        .map_err(|e| RuntimeError::Native(format!("in take: {e}")))?;
+    Ok(Value::array(values))
+}
+
+/// Discard the first `n` yielded values and return the coroutine.
+fn skip_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let coro = next_arg(&mut args)?;
+    let n = next_arg(&mut args)?.try_coerce_i64()?;
+    let Value::Coro(vm) = coro else {
+        return Err(coro.coercion_error("cofn"));
+    };
+    vm.borrow_mut()
+        .skip(n.max(0) as usize)
+        .map_err(|e| RuntimeError::Native(format!("in skip: {e}")))?;
+    Ok(coro.clone())
+}
+
+/// Resume the coroutine with a value for the `yield` it is suspended at, and return the next
+/// yielded value, or the return value if the coroutine finishes.
+fn resume_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let coro = next_arg(&mut args)?;
+    let value = args.next().cloned().unwrap_or(Value::Unit);
+    let Value::Coro(vm) = coro else {
+        return Err(coro.coercion_error("cofn"));
+    };
+    let mut vm = vm.borrow_mut();
+    if vm.is_finished() {
+        return Err(RuntimeError::Native(
+            "resume of a finished coroutine".to_string(),
+        ));
+    }
+    match vm
+        .resume(value)
+        .map_err(|e| RuntimeError::Native(format!("in resume: {e}")))?
+    {
+        YieldResult::Suspend(value) | YieldResult::Finished(value) => Ok(value),
+        YieldResult::Breakpoint(line) => Err(RuntimeError::Native(format!(
+            "resume stopped at breakpoint at line {line}"
+        ))),
+    }
+}
+
+fn substr_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let s = next_arg(&mut args)?.try_coerce_str()?;
+    let start = next_arg(&mut args)?.try_coerce_i64()?;
+    let len = next_arg(&mut args)?.try_coerce_i64()?;
+    // clamp the range instead of panicking, and count in chars so multibyte input isn't split
+    Ok(Value::Str(
+        s.chars()
+            .skip(start.max(0) as usize)
+            .take(len.max(0) as usize)
+            .collect::<String>()
+            .into(),
+    ))
+}
+
+fn split_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let s = next_arg(&mut args)?.try_coerce_str()?;
+    let sep = next_arg(&mut args)?.try_coerce_str()?;
+    let values = if sep.is_empty() {
+        s.chars()
+            .map(|c| Value::Str(c.to_string().into()))
+            .collect()
+    } else {
+        s.split(&*sep).map(Value::str).collect()
+    };
+    Ok(Value::array(values))
+}
+
+/// Concatenate the elements of the array with the separator between them. Elements other than
+/// strings are written as `print` does.
+fn join_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let arr = next_arg(&mut args)?;
+    let sep = next_arg(&mut args)?.try_coerce_str()?;
+    let Value::Array(values) = arr else {
+        return Err(arr.coercion_error("array"));
+    };
+    let joined = values
+        .borrow()
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(&sep);
+    Ok(Value::Str(joined.into()))
+}
+
+/// Replace `{}` in the format string with the following arguments in order, and `{n}` with the
+/// `n`-th one counting from 0. `{{` and `}}` are literal braces.
+fn format_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let (fmt, values) = args.split_first().ok_or_else(missing_arg)?;
+    let fmt = fmt.try_coerce_str()?;
+    let mut buf = String::new();
+    let mut next = 0;
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                buf.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                buf.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let Some(end) = rest.find('}') else {
+                    return Err(format_error(format!("unclosed placeholder in {fmt:?}")));
+                };
+                let index = if end == 0 {
+                    next += 1;
+                    next - 1
+                } else {
+                    rest[..end].trim().parse().map_err(|_| {
+                        format_error(format!(
+                            "invalid placeholder {{{}}} in {fmt:?}",
+                            &rest[..end]
+                        ))
+                    })?
+                };
+                let value = values.get(index).ok_or_else(|| {
+                    format_error(format!(
+                        "no argument {index} for {fmt:?}, which has {} arguments",
+                        values.len()
+                    ))
+                })?;
+                buf += &value.to_string();
+                chars = rest[end + 1..].chars();
+            }
+            '}' => return Err(format_error(format!("unmatched '}}' in {fmt:?}"))),
+            c => buf.push(c),
+        }
+    }
+    Ok(Value::Str(buf.into()))
+}
+
+fn format_error(message: String) -> RuntimeError {
+    RuntimeError::Native(format!("format: {message}"))
+}
+
+/// Numbers are equal within the tolerance `eps`, and other values fall back to the exact equality.
+fn approx_eq_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let lhs = next_arg(&mut args)?;
+    let rhs = next_arg(&mut args)?;
+    let eps = next_arg(&mut args)?.try_coerce_f64()?;
+    let eq = match (lhs, rhs) {
+        (Value::F64(_) | Value::I64(_), Value::F64(_) | Value::I64(_)) => {
+            (lhs.coerce_f64() - rhs.coerce_f64()).abs() <= eps
+        }
+        _ => lhs == rhs,
+    };
+    Ok(Value::I64(eq as i64))
+}
+
+fn random_int_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let lo = next_arg(&mut args)?.try_coerce_i64()?;
+    let hi = next_arg(&mut args)?.try_coerce_i64()?;
+    random::next_range(lo, hi)
+        .map(Value::I64)
+        .ok_or_else(|| RuntimeError::Native(format!("random_int: empty range {lo} to {hi}")))
+}
+
+/// Fail with the optional message unless the condition is truthy.
+fn assert_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let cond = next_arg(&mut args)?;
+    if cond.is_truthy() {
+        return Ok(Value::I64(0));
     }
-    Value::F64(0.)
+    Err(RuntimeError::AssertionFailed(match args.next() {
+        Some(message) => message.to_string(),
+        None => format!("condition was {cond:?}"),
+    }))
+}
+
+/// Fail unless the values are equal in the sense of `==`.
+fn assert_eq_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let lhs = next_arg(&mut args)?;
+    let rhs = next_arg(&mut args)?;
+    if lhs.value_eq(rhs) {
+        return Ok(Value::I64(0));
+    }
+    Err(RuntimeError::AssertionFailed(format!(
+        "left: {lhs}, right: {rhs}"
+    )))
+}
+
+/// Replace every occurrence of `from`, scanning from the start without overlaps
+fn replace_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let s = next_arg(&mut args)?.try_coerce_str()?;
+    let from = next_arg(&mut args)?.try_coerce_str()?;
+    let to = next_arg(&mut args)?.try_coerce_str()?;
+    Ok(Value::Str(s.replace(&*from, &to).into()))
+}
+
+fn contains_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let haystack = next_arg(&mut args)?.try_coerce_str()?;
+    let needle = next_arg(&mut args)?.try_coerce_str()?;
+    Ok(Value::I64(haystack.contains(&*needle) as i64))
+}
+
+fn index_of_fn(args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut args = args.iter();
+    let haystack = next_arg(&mut args)?.try_coerce_str()?;
+    let needle = next_arg(&mut args)?.try_coerce_str()?;
+    // convert the byte offset to a char index so that multibyte strings are consistent with substr
+    Ok(Value::I64(
+        haystack
+            .find(&*needle)
+            .map_or(-1, |pos| haystack[..pos].chars().count() as i64),
+    ))
+}
+
+/// Predefined constants, visible in every scope unless shadowed by a variable.
+///
+/// They are defined as variables by the type checker and loaded as literals by the compiler.
+pub fn standard_constants() -> [(&'static str, Value); 3] {
+    [
+        ("pi", Value::F64(std::f64::consts::PI)),
+        ("e", Value::F64(std::f64::consts::E)),
+        ("tau", Value::F64(std::f64::consts::TAU)),
+    ]
 }
 
+/// Name of the native which prints the stack of the calling frame
+pub const DUMP_STACK: &str = "dump_stack";
+
+/// Name of the native which applies a function value to each element of an array
+pub const MAP: &str = "map";
+
+/// Name of the native which keeps the elements of an array satisfying a function value
+pub const FILTER: &str = "filter";
+
+/// Name of the native which folds an array from the left with a function value
+pub const REDUCE: &str = "reduce";
+
+/// Name of the native which sorts an array, optionally by a comparison function value
+pub const SORT: &str = "sort";
+
 pub fn standard_functions<'src>() -> Functions<'src> {
     let mut funcs = Functions::new();
     funcs.insert("sqrt".to_string(), unary_fn(f64::sqrt));
@@ -81,9 +681,55 @@ pub fn standard_functions<'src>() -> Functions<'src> {
     funcs.insert("atan".to_string(), unary_fn(f64::atan));
     funcs.insert("atan2".to_string(), binary_fn(f64::atan2));
     funcs.insert("pow".to_string(), binary_fn(f64::powf));
+    funcs.insert("floor".to_string(), unary_fn(f64::floor));
+    funcs.insert("ceil".to_string(), unary_fn(f64::ceil));
+    funcs.insert("round".to_string(), unary_fn(f64::round));
+    funcs.insert("trunc".to_string(), unary_fn(f64::trunc));
     funcs.insert("exp".to_string(), unary_fn(f64::exp));
     funcs.insert("log".to_string(), binary_fn(f64::log));
     funcs.insert("log10".to_string(), unary_fn(f64::log10));
+    funcs.insert("min".to_string(), num_binary_fn(i64::min, f64::min));
+    funcs.insert("max".to_string(), num_binary_fn(i64::max, f64::max));
+    funcs.insert(
+        "abs".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("arg", TypeDecl::Any)],
+            ret_type: TypeDecl::Any,
+            code: Box::new(abs_fn),
+        }),
+    );
+    funcs.insert("wrapping_add".to_string(), wrapping_fn(i64::wrapping_add));
+    funcs.insert("wrapping_sub".to_string(), wrapping_fn(i64::wrapping_sub));
+    funcs.insert("wrapping_mul".to_string(), wrapping_fn(i64::wrapping_mul));
+    funcs.insert("to_hex".to_string(), radix_fn(|n| format!("{n:x}")));
+    funcs.insert("to_bin".to_string(), radix_fn(|n| format!("{n:b}")));
+    funcs.insert("to_upper".to_string(), unary_str_fn(str::to_uppercase));
+    funcs.insert("to_lower".to_string(), unary_str_fn(str::to_lowercase));
+    funcs.insert("trim".to_string(), unary_str_fn(|s| s.trim().to_string()));
+    funcs.insert(
+        "starts_with".to_string(),
+        str_test_fn("prefix", |s, prefix| s.starts_with(prefix)),
+    );
+    funcs.insert(
+        "ends_with".to_string(),
+        str_test_fn("suffix", |s, suffix| s.ends_with(suffix)),
+    );
+    funcs.insert(
+        "replace".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![
+                ("s", TypeDecl::Str),
+                ("from", TypeDecl::Str),
+                ("to", TypeDecl::Str),
+            ],
+            ret_type: TypeDecl::Str,
+            code: Box::new(replace_fn),
+        }),
+    );
+    funcs.insert("byte_len".to_string(), str_len_fn(str::len));
+    funcs.insert("char_len".to_string(), str_len_fn(|s| s.chars().count()));
+    // `len` counts characters, consistent with the indices of `substr` and `index_of`
+    funcs.insert("len".to_string(), str_len_fn(|s| s.chars().count()));
     funcs.insert(
         "print".to_string(),
         FnDecl::Native(NativeFn {
@@ -103,7 +749,7 @@ pub fn standard_functions<'src>() -> Functions<'src> {
     funcs.insert(
         "dbg".to_string(),
         FnDecl::Native(NativeFn {
-            args: vec![("arg", TypeDecl::Any)],
+            args: vec![("label", TypeDecl::Str), ("arg", TypeDecl::Any)],
             ret_type: TypeDecl::Any,
             code: Box::new(dbg_fn),
         }),
@@ -122,11 +768,7 @@ pub fn standard_functions<'src>() -> Functions<'src> {
             args: vec![("arg", TypeDecl::Any)],
             ret_type: TypeDecl::I64,
             code: Box::new(move |args| {
-                Value::I64(
-                    args.first()
-                        .expect("function missing argument")
-                        .coerce_i64(),
-                )
+                Ok(Value::I64(next_arg(&mut args.iter())?.try_coerce_i64()?))
             }),
         }),
     );
@@ -136,11 +778,7 @@ pub fn standard_functions<'src>() -> Functions<'src> {
             args: vec![("arg", TypeDecl::Any)],
             ret_type: TypeDecl::F64,
             code: Box::new(move |args| {
-                Value::F64(
-                    args.first()
-                        .expect("function missing argument")
-                        .coerce_f64(),
-                )
+                Ok(Value::F64(next_arg(&mut args.iter())?.try_coerce_f64()?))
             }),
         }),
     );
@@ -150,14 +788,276 @@ pub fn standard_functions<'src>() -> Functions<'src> {
             args: vec![("arg", TypeDecl::Any)],
             ret_type: TypeDecl::Str,
             code: Box::new(move |args| {
-                Value::Str(
-                    args.first()
-                        .expect("function missing argument")
-                        .coerce_str(),
-                )
+                Ok(Value::Str(next_arg(&mut args.iter())?.try_coerce_str()?))
+            }),
+        }),
+    );
+    funcs.insert(
+        "parse_int".to_string(),
+        parse_fn("parse_int", "integer", TypeDecl::I64, Value::I64),
+    );
+    funcs.insert(
+        "parse_float".to_string(),
+        parse_fn("parse_float", "number", TypeDecl::F64, Value::F64),
+    );
+    funcs.insert(
+        "read_line".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![],
+            ret_type: TypeDecl::Str,
+            code: Box::new(read_line_fn),
+        }),
+    );
+    funcs.insert(
+        DUMP_STACK.to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![],
+            ret_type: TypeDecl::I64,
+            // The `Vm` handles the call itself, since natives can't see the stack
+            code: Box::new(|_| Ok(Value::I64(0))),
+        }),
+    );
+    funcs.insert(
+        "random".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![],
+            ret_type: TypeDecl::F64,
+            code: Box::new(|_| Ok(Value::F64(random::next_f64()))),
+        }),
+    );
+    funcs.insert(
+        "random_int".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("lo", TypeDecl::I64), ("hi", TypeDecl::I64)],
+            ret_type: TypeDecl::I64,
+            code: Box::new(random_int_fn),
+        }),
+    );
+    funcs.insert(
+        "seed".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("seed", TypeDecl::I64)],
+            ret_type: TypeDecl::I64,
+            code: Box::new(|args| {
+                random::seed(next_arg(&mut args.iter())?.try_coerce_i64()? as u64);
+                Ok(Value::I64(0))
             }),
         }),
     );
+    funcs.insert(
+        MAP.to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![
+                ("arr", TypeDecl::DynArray(Box::new(TypeDecl::Any))),
+                ("f", TypeDecl::Func),
+            ],
+            ret_type: TypeDecl::DynArray(Box::new(TypeDecl::Any)),
+            // The `Vm` handles the call itself, since natives can't call back into scripts
+            code: Box::new(|_| Ok(Value::I64(0))),
+        }),
+    );
+    funcs.insert(
+        FILTER.to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![
+                ("arr", TypeDecl::DynArray(Box::new(TypeDecl::Any))),
+                ("pred", TypeDecl::Func),
+            ],
+            ret_type: TypeDecl::DynArray(Box::new(TypeDecl::Any)),
+            // The `Vm` handles the call like `map`
+            code: Box::new(|_| Ok(Value::I64(0))),
+        }),
+    );
+    funcs.insert(
+        REDUCE.to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![
+                ("arr", TypeDecl::DynArray(Box::new(TypeDecl::Any))),
+                ("init", TypeDecl::Any),
+                ("f", TypeDecl::Func),
+            ],
+            ret_type: TypeDecl::Any,
+            // The `Vm` handles the call like `map`
+            code: Box::new(|_| Ok(Value::I64(0))),
+        }),
+    );
+    funcs.insert(
+        SORT.to_string(),
+        FnDecl::Native(NativeFn {
+            // The comparison function after the array is optional
+            args: vec![("arr", TypeDecl::DynArray(Box::new(TypeDecl::Any)))],
+            ret_type: TypeDecl::DynArray(Box::new(TypeDecl::Any)),
+            // The `Vm` handles the call like `map`
+            code: Box::new(|_| Ok(Value::I64(0))),
+        }),
+    );
+    funcs.insert(
+        "range".to_string(),
+        FnDecl::Native(NativeFn {
+            // The end can be preceded by the start and followed by the step
+            args: vec![("end", TypeDecl::I64)],
+            ret_type: TypeDecl::DynArray(Box::new(TypeDecl::I64)),
+            code: Box::new(range_fn),
+        }),
+    );
+    funcs.insert(
+        "reverse".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("arr", TypeDecl::DynArray(Box::new(TypeDecl::Any)))],
+            ret_type: TypeDecl::DynArray(Box::new(TypeDecl::Any)),
+            code: Box::new(reverse_fn),
+        }),
+    );
+    funcs.insert(
+        "approx_eq".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![
+                ("lhs", TypeDecl::Any),
+                ("rhs", TypeDecl::Any),
+                ("eps", TypeDecl::F64),
+            ],
+            ret_type: TypeDecl::I64,
+            code: Box::new(approx_eq_fn),
+        }),
+    );
+    funcs.insert(
+        "assert".to_string(),
+        FnDecl::Native(NativeFn {
+            // The message after the condition is optional
+            args: vec![("cond", TypeDecl::Any)],
+            ret_type: TypeDecl::I64,
+            code: Box::new(assert_fn),
+        }),
+    );
+    funcs.insert(
+        "assert_eq".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("lhs", TypeDecl::Any), ("rhs", TypeDecl::Any)],
+            ret_type: TypeDecl::I64,
+            code: Box::new(assert_eq_fn),
+        }),
+    );
+    funcs.insert(
+        "typeof".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("arg", TypeDecl::Any)],
+            ret_type: TypeDecl::Str,
+            code: Box::new(move |args| Ok(Value::str(next_arg(&mut args.iter())?.type_name()))),
+        }),
+    );
+    funcs.insert(
+        "format".to_string(),
+        FnDecl::Native(NativeFn {
+            // The arguments after the format string can be any number of any values
+            args: vec![("fmt", TypeDecl::Str)],
+            ret_type: TypeDecl::Str,
+            code: Box::new(format_fn),
+        }),
+    );
+    funcs.insert(
+        "substr".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![
+                ("s", TypeDecl::Str),
+                ("start", TypeDecl::I64),
+                ("len", TypeDecl::I64),
+            ],
+            ret_type: TypeDecl::Str,
+            code: Box::new(substr_fn),
+        }),
+    );
+    funcs.insert(
+        "split".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("s", TypeDecl::Str), ("sep", TypeDecl::Str)],
+            ret_type: TypeDecl::DynArray(Box::new(TypeDecl::Str)),
+            code: Box::new(split_fn),
+        }),
+    );
+    funcs.insert(
+        "join".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![
+                ("arr", TypeDecl::DynArray(Box::new(TypeDecl::Any))),
+                ("sep", TypeDecl::Str),
+            ],
+            ret_type: TypeDecl::Str,
+            code: Box::new(join_fn),
+        }),
+    );
+    funcs.insert(
+        "take".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("coro", TypeDecl::Coro), ("n", TypeDecl::I64)],
+            ret_type: TypeDecl::DynArray(Box::new(TypeDecl::Any)),
+            code: Box::new(take_fn),
+        }),
+    );
+    funcs.insert(
+        "skip".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("coro", TypeDecl::Coro), ("n", TypeDecl::I64)],
+            ret_type: TypeDecl::Coro,
+            code: Box::new(skip_fn),
+        }),
+    );
+    funcs.insert(
+        "resume".to_string(),
+        FnDecl::Native(NativeFn {
+            // The value to send is optional
+            args: vec![("coro", TypeDecl::Coro)],
+            ret_type: TypeDecl::Any,
+            code: Box::new(resume_fn),
+        }),
+    );
+    funcs.insert(
+        "push".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![
+                ("arr", TypeDecl::DynArray(Box::new(TypeDecl::Any))),
+                ("value", TypeDecl::Any),
+            ],
+            ret_type: TypeDecl::DynArray(Box::new(TypeDecl::Any)),
+            code: Box::new(push_fn),
+        }),
+    );
+    funcs.insert(
+        "pop".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("arr", TypeDecl::DynArray(Box::new(TypeDecl::Any)))],
+            ret_type: TypeDecl::Any,
+            code: Box::new(pop_fn),
+        }),
+    );
+    funcs.insert(
+        "contains".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("haystack", TypeDecl::Str), ("needle", TypeDecl::Str)],
+            ret_type: TypeDecl::I64,
+            code: Box::new(contains_fn),
+        }),
+    );
+    funcs.insert(
+        "index_of".to_string(),
+        FnDecl::Native(NativeFn {
+            args: vec![("haystack", TypeDecl::Str), ("needle", TypeDecl::Str)],
+            ret_type: TypeDecl::I64,
+            code: Box::new(index_of_fn),
+        }),
+    );
+    HOST_FUNCTIONS.with_borrow(|host_funcs| {
+        for f in host_funcs {
+            let code = f.code.clone();
+            funcs.insert(
+                f.name.clone(),
+                FnDecl::Native(NativeFn {
+                    args: f.args.clone(),
+                    ret_type: f.ret_type.clone(),
+                    code: Box::new(move |args| code(args)),
+                }),
+            );
+        }
+    });
     funcs
 }
 
@@ -170,7 +1070,35 @@ pub enum TypeDecl {
     I64,
     Str,
     Array(Box<TypeDecl>, usize), // (type, length)
+    /// Dynamic-length array, declared as `Array<type>` without a length
+    DynArray(Box<TypeDecl>),
     Coro,
+    /// Function value, declared as `fn`. The signature is not tracked, so calls through it
+    /// are checked at runtime.
+    Func,
+    /// No value, which loops and `if` without `else` evaluate to. It can't be declared.
+    Unit,
+}
+
+/// The least upper bound of the types of two array elements, or `None` if they don't mix.
+///
+/// Numbers mix into `f64` unless both are `i64`. Nested arrays can be ragged, and the shorter
+/// ones are padded up to the longest one.
+fn tc_element_type(lhs: &TypeDecl, rhs: &TypeDecl) -> Option<TypeDecl> {
+    use TypeDecl::*;
+    Some(match (lhs, rhs) {
+        (Any, _) | (_, Any) => Any,
+        (I64, I64) => I64,
+        (F64 | I64, F64 | I64) => F64,
+        (Str, Str) => Str,
+        (Coro, Coro) => Coro,
+        (Func, Func) => Func,
+        (Array(lhs, lhs_len), Array(rhs, rhs_len)) => {
+            Array(Box::new(tc_element_type(lhs, rhs)?), *lhs_len.max(rhs_len))
+        }
+        (DynArray(lhs), DynArray(rhs)) => DynArray(Box::new(tc_element_type(lhs, rhs)?)),
+        _ => return None,
+    })
 }
 
 fn tc_coerce_type<'src>(
@@ -181,15 +1109,17 @@ fn tc_coerce_type<'src>(
     use TypeDecl::*;
     Ok(match (value, target) {
         (_, Any) => value.clone(),
+        // numbers are converted to the declared numeric type at runtime
+        (Any | F64 | I64, F64) => F64,
+        (Any | F64 | I64, I64) => I64,
         (Any, _) => value.clone(),
-        (F64 | I64, F64) => F64,
-        (F64, I64) => F64,
-        (I64, I64) => I64,
         (Str, Str) => Str,
+        (Func, Func) => Func,
         (Array(ty1, len1), Array(ty2, len2)) => match tc_coerce_type(ty1, ty2, span) {
             Ok(ty) => {
                 if len1 > len2 {
                     return Err(TypeCheckError::new(
+                        ErrorCode::E002,
                         format!("Array length mismatch: {} and {}", len1, len2),
                         span,
                     ));
@@ -198,14 +1128,27 @@ fn tc_coerce_type<'src>(
             }
             Err(_) => {
                 return Err(TypeCheckError::new(
+                    ErrorCode::E002,
+                    format!("{:?} cannot be assigned to {:?}", value, target),
+                    span,
+                ))
+            }
+        },
+        (DynArray(ty1), DynArray(ty2)) => match tc_coerce_type(ty1, ty2, span) {
+            Ok(ty) => DynArray(Box::new(ty)),
+            Err(_) => {
+                return Err(TypeCheckError::new(
+                    ErrorCode::E002,
                     format!("{:?} cannot be assigned to {:?}", value, target),
                     span,
                 ))
             }
         },
         (Coro, Coro) => Coro,
+        (Unit, Unit) => Unit,
         _ => {
             return Err(TypeCheckError::new(
+                ErrorCode::E002,
                 format!("{:?} cannot be assigned to {:?}", value, target),
                 span,
             ))
@@ -213,19 +1156,53 @@ fn tc_coerce_type<'src>(
     })
 }
 
-pub struct TypeCheckContext<'src, 'ctx> {
-    /// Variables table for type checking.
-    vars: HashMap<&'src str, TypeDecl>,
+pub struct TypeCheckContext<'ctx> {
+    /// Variables table for type checking. The names are copied out of the source, so that the
+    /// context can outlive it, like the REPL's does across the inputs.
+    vars: HashMap<String, TypeDecl>,
     /// Function names are owned strings because it can be either from source or native.
-    funcs: Functions<'src>,
-    super_context: Option<&'ctx TypeCheckContext<'src, 'ctx>>,
+    funcs: Functions<'static>,
+    /// Declared return type of the function being checked, or `None` at the toplevel.
+    ret_type: Option<TypeDecl>,
+    /// Every symbol seen so far, shared by all the scopes for `--dump-symbols`.
+    symbols: Rc<RefCell<Vec<ScopeSymbols>>>,
+    /// Index of this scope in `symbols`
+    scope: usize,
+    super_context: Option<&'ctx TypeCheckContext<'ctx>>,
+}
+
+/// Symbols defined in a scope, in the order of definition
+struct ScopeSymbols {
+    name: String,
+    args: Vec<(String, TypeDecl)>,
+    ret_type: Option<TypeDecl>,
+    vars: Vec<(String, TypeDecl)>,
+    funcs: Vec<String>,
+}
+
+impl<'ctx> Default for TypeCheckContext<'ctx> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<'src, 'ctx> TypeCheckContext<'src, 'ctx> {
+impl<'ctx> TypeCheckContext<'ctx> {
     pub fn new() -> Self {
         Self {
-            vars: HashMap::new(),
+            vars: standard_constants()
+                .into_iter()
+                .map(|(name, _)| (name.to_string(), TypeDecl::F64))
+                .collect(),
             funcs: standard_functions(),
+            ret_type: None,
+            symbols: Rc::new(RefCell::new(vec![ScopeSymbols {
+                name: "<toplevel>".to_string(),
+                args: vec![],
+                ret_type: None,
+                vars: vec![],
+                funcs: vec![],
+            }])),
+            scope: 0,
             super_context: None,
         }
     }
@@ -240,7 +1217,7 @@ impl<'src, 'ctx> TypeCheckContext<'src, 'ctx> {
         }
     }
 
-    pub fn get_fn(&self, name: &str) -> Option<&FnDecl<'src>> {
+    pub fn get_fn(&self, name: &str) -> Option<&FnDecl<'static>> {
         if let Some(val) = self.funcs.get(name) {
             Some(val)
         } else if let Some(super_ctx) = self.super_context {
@@ -254,48 +1231,158 @@ impl<'src, 'ctx> TypeCheckContext<'src, 'ctx> {
         Self {
             vars: HashMap::new(),
             funcs: HashMap::new(),
+            ret_type: super_ctx.ret_type.clone(),
+            symbols: super_ctx.symbols.clone(),
+            scope: super_ctx.scope,
             super_context: Some(super_ctx),
         }
     }
+
+    /// Scope for the statements whose definitions are kept only if they compile and run, like an
+    /// input of the REPL. The definitions taken out of it are added back with `define_vars` and
+    /// `define_fns`.
+    pub(crate) fn push_pending(super_ctx: &'ctx Self) -> Self {
+        Self::push_stack(super_ctx)
+    }
+
+    pub(crate) fn take_definitions(self) -> (HashMap<String, TypeDecl>, Functions<'static>) {
+        (self.vars, self.funcs)
+    }
+
+    pub(crate) fn define_vars(&mut self, vars: HashMap<String, TypeDecl>) {
+        self.vars.extend(vars);
+    }
+
+    pub(crate) fn define_fns(&mut self, funcs: Functions<'static>) {
+        self.funcs.extend(funcs);
+    }
+
+    /// Push the scope of a function body, defining its arguments.
+    fn push_fn_scope(
+        super_ctx: &'ctx Self,
+        name: &str,
+        args: &[(Span, TypeDecl)],
+        ret_type: &TypeDecl,
+    ) -> Self {
+        let mut ctx = Self::push_stack(super_ctx);
+        ctx.ret_type = Some(ret_type.clone());
+        let mut symbols = ctx.symbols.borrow_mut();
+        symbols.push(ScopeSymbols {
+            name: name.to_string(),
+            args: args
+                .iter()
+                .map(|(arg, ty)| (arg.to_string(), ty.clone()))
+                .collect(),
+            ret_type: Some(ret_type.clone()),
+            vars: vec![],
+            funcs: vec![],
+        });
+        ctx.scope = symbols.len() - 1;
+        drop(symbols);
+        for (arg, ty) in args {
+            ctx.vars.insert(arg.to_string(), ty.clone());
+        }
+        ctx
+    }
+
+    fn define_var(&mut self, name: &str, ty: TypeDecl) {
+        self.symbols.borrow_mut()[self.scope]
+            .vars
+            .push((name.to_string(), ty.clone()));
+        self.vars.insert(name.to_string(), ty);
+    }
+
+    fn define_fn(&mut self, name: &str, decl: FnDecl<'static>) {
+        self.symbols.borrow_mut()[self.scope]
+            .funcs
+            .push(name.to_string());
+        self.funcs.insert(name.to_string(), decl);
+    }
+
+    /// Write the symbols of every scope and the global functions in a line-based format.
+    pub fn dump_symbols(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        for scope in self.symbols.borrow().iter() {
+            writeln!(writer, "scope {}", scope.name)?;
+            for (name, ty) in &scope.args {
+                writeln!(writer, "  arg {name}: {ty:?}")?;
+            }
+            if let Some(ret_type) = &scope.ret_type {
+                writeln!(writer, "  ret {ret_type:?}")?;
+            }
+            for (name, ty) in &scope.vars {
+                writeln!(writer, "  var {name}: {ty:?}")?;
+            }
+            for name in &scope.funcs {
+                writeln!(writer, "  fn {name}")?;
+            }
+        }
+        writeln!(writer, "functions")?;
+        let mut funcs: Vec<_> = self.funcs.iter().collect();
+        funcs.sort_by_key(|(name, _)| *name);
+        for (name, decl) in funcs {
+            let kind = match decl {
+                FnDecl::User(user) if user.cofn => "cofn",
+                FnDecl::User(_) => "fn",
+                FnDecl::Native(_) => "native",
+            };
+            let args = decl
+                .args()
+                .iter()
+                .map(|(arg, ty)| format!("{arg}: {ty:?}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(writer, "  {kind} {name}({args}) -> {:?}", decl.ret_type())?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub struct TypeCheckError<'src> {
+    pub code: ErrorCode,
     msg: String,
     pub span: Span<'src>,
 }
 
 impl<'src> std::fmt::Display for TypeCheckError<'src> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}\nlocation: {}:{}: {}",
-            self.msg,
-            self.span.location_line(),
-            self.span.get_utf8_column(),
-            self.span.fragment()
-        )
+        write!(f, "[{}] {}", self.code, self.msg)
     }
 }
 
 impl<'src> Error for TypeCheckError<'src> {}
 
 impl<'src> TypeCheckError<'src> {
-    fn new(msg: String, span: Span<'src>) -> Self {
-        Self { msg, span }
+    fn new(code: ErrorCode, msg: String, span: Span<'src>) -> Self {
+        Self { code, msg, span }
     }
 }
 
 fn tc_binary_op<'src>(
     lhs: &Expression<'src>,
     rhs: &Expression<'src>,
-    ctx: &mut TypeCheckContext<'src, '_>,
+    ctx: &mut TypeCheckContext<'_>,
     op: &str,
 ) -> Result<TypeDecl, TypeCheckError<'src>> {
     let lhst = tc_expr(lhs, ctx)?;
     let rhst = tc_expr(rhs, ctx)?;
-    binary_op_type(&lhst, &rhst).map_err(|_| {
+    match (&lhst, &rhst) {
+        // a string multiplied by a number is repeated
+        (TypeDecl::Str, TypeDecl::F64 | TypeDecl::I64)
+        | (TypeDecl::F64 | TypeDecl::I64, TypeDecl::Str)
+            if op == "Mul" =>
+        {
+            Ok(TypeDecl::Str)
+        }
+        _ => binary_op_type(&lhst, &rhst).and_then(|ty| match ty {
+            // otherwise strings can only be concatenated
+            TypeDecl::Str if op != "Add" => Err(()),
+            _ => Ok(ty),
+        }),
+    }
+    .map_err(|_| {
         TypeCheckError::new(
+            ErrorCode::E002,
             format!(
                 "Operation {op} between incompatible type: {:?} and {:?}",
                 lhst, rhst
@@ -310,16 +1397,40 @@ fn binary_op_type(lhs: &TypeDecl, rhs: &TypeDecl) -> Result<TypeDecl, ()> {
     Ok(match (lhs, rhs) {
         (Any, _) => Any,
         (_, Any) => Any,
+        (I64, I64) => I64,
         (F64 | I64, I64 | F64) => F64,
         (Str, Str) => Str,
         _ => return Err(()),
     })
 }
 
+/// Check that a value of the type can be tested for truthiness, which all but a unit can.
+fn tc_truthy<'src>(ty: &TypeDecl, span: Span<'src>) -> Result<(), TypeCheckError<'src>> {
+    if *ty == TypeDecl::Unit {
+        return Err(TypeCheckError::new(
+            ErrorCode::E002,
+            "Unit has no truthiness".to_string(),
+            span,
+        ));
+    }
+    Ok(())
+}
+
+/// `&&` and `||` give `i64` 1 or 0 from the truthiness of the operands.
+fn tc_logic_op<'src>(
+    lhs: &Expression<'src>,
+    rhs: &Expression<'src>,
+    ctx: &mut TypeCheckContext<'_>,
+) -> Result<TypeDecl, TypeCheckError<'src>> {
+    tc_truthy(&tc_expr(lhs, ctx)?, lhs.span)?;
+    tc_truthy(&tc_expr(rhs, ctx)?, rhs.span)?;
+    Ok(TypeDecl::I64)
+}
+
 fn tc_binary_cmp<'src>(
     lhs: &Expression<'src>,
     rhs: &Expression<'src>,
-    ctx: &mut TypeCheckContext<'src, '_>,
+    ctx: &mut TypeCheckContext<'_>,
     op: &str,
 ) -> Result<TypeDecl, TypeCheckError<'src>> {
     use TypeDecl::*;
@@ -333,6 +1444,7 @@ fn tc_binary_cmp<'src>(
         (Str, Str) => I64,
         _ => {
             return Err(TypeCheckError::new(
+                ErrorCode::E002,
                 format!(
                     "Operation {op} bwetween incompatible type: {:?} and {:?}",
                     lhst, rhst
@@ -345,35 +1457,30 @@ fn tc_binary_cmp<'src>(
 
 fn tc_expr<'src>(
     e: &Expression<'src>,
-    ctx: &mut TypeCheckContext<'src, '_>,
+    ctx: &mut TypeCheckContext<'_>,
 ) -> Result<TypeDecl, TypeCheckError<'src>> {
     use ExprEnum::*;
     Ok(match &e.expr {
         NumLiteral(_val) => TypeDecl::F64,
         StrLiteral(_val) => TypeDecl::Str,
+        BoolLiteral(_val) => TypeDecl::I64,
         // TODO: fix temporary array type
         ArrayLiteral(val) => {
             let mut ty: Option<TypeDecl> = None;
             for v in val.iter() {
                 let v_ty = tc_expr(v, ctx)?;
-                if let Some(ty) = ty.as_ref() {
-                    match ty {
-                        TypeDecl::Array(_, _) => {
-                            // TODO: fix check array type
-                        }
-                        _ => {
-                            if v_ty != *ty {
-                                println!("{:?} {:?}", v_ty, ty);
-                                return Err(TypeCheckError::new(
-                                    "Array elements should have the same type".to_string(),
-                                    v.span,
-                                ));
-                            };
-                        }
-                    }
-                } else {
-                    ty = Some(v_ty);
-                }
+                ty = Some(match ty {
+                    Some(ty) => tc_element_type(&ty, &v_ty).ok_or_else(|| {
+                        TypeCheckError::new(
+                            ErrorCode::E002,
+                            format!(
+                                "Array element of type {v_ty:?} does not match the preceding elements of type {ty:?}"
+                            ),
+                            v.span,
+                        )
+                    })?,
+                    None => v_ty,
+                });
             }
             if let Some(ty) = ty {
                 TypeDecl::Array(Box::new(ty), val.len())
@@ -383,37 +1490,76 @@ fn tc_expr<'src>(
         }
         ArrayIndexAccess(name, indices, ..) => {
             let var = ctx.get_var(name).ok_or_else(|| {
-                TypeCheckError::new(format!("Variable \"{}\" not found", name), e.span)
+                TypeCheckError::new(
+                    ErrorCode::E001,
+                    format!("Variable \"{}\" not found", name),
+                    e.span,
+                )
             })?;
             // TODO: index should be i64
             for index in indices.iter() {
                 if tc_expr(index, ctx)? != TypeDecl::F64 && tc_expr(index, ctx)? != TypeDecl::I64 {
                     return Err(TypeCheckError::new(
+                        ErrorCode::E002,
                         "index should be f64 or i64".to_string(),
                         e.span,
                     ));
                 };
             }
             let mut var_ty = var;
-            while let TypeDecl::Array(ty, _) = var_ty {
-                var_ty = *ty;
+            for _ in indices.iter() {
+                var_ty = match var_ty {
+                    TypeDecl::Array(ty, _) | TypeDecl::DynArray(ty) => *ty,
+                    TypeDecl::Any => TypeDecl::Any,
+                    _ => {
+                        return Err(TypeCheckError::new(
+                            ErrorCode::E002,
+                            format!("Variable \"{}\" cannot be indexed: {:?}", name, var_ty),
+                            e.span,
+                        ))
+                    }
+                };
             }
             var_ty
         }
-        Ident(name) => ctx.get_var(name).ok_or_else(|| {
-            TypeCheckError::new(format!("Variable \"{}\" not found", name), e.span)
-        })?,
+        Ident(name) => match ctx.get_var(name) {
+            Some(ty) => ty,
+            // A function name without a call is a function value
+            None if ctx.get_fn(name).is_some() => TypeDecl::Func,
+            None => {
+                return Err(TypeCheckError::new(
+                    ErrorCode::E001,
+                    format!("Variable \"{}\" not found", name),
+                    e.span,
+                ))
+            }
+        },
         FnInvoke(name, args) => {
             let args_ty = args
                 .iter()
-                .map(|v| Ok((tc_expr(v, ctx)?, v.span)))
+                .map(|v| Ok((tc_expr(v, ctx)?, v)))
                 .collect::<Result<Vec<_>, _>>()?;
+            if ctx.get_var(name) == Some(TypeDecl::Func) {
+                // An indirect call through a function value
+                return Ok(TypeDecl::Any);
+            }
             let func = ctx.get_fn(name).ok_or_else(|| {
-                TypeCheckError::new(format!("function {} is not defined", name), *name)
+                TypeCheckError::new(
+                    ErrorCode::E003,
+                    format!("function {} is not defined", name),
+                    *name,
+                )
             })?;
             let args_decl = func.args();
-            for ((arg_ty, arg_span), decl) in args_ty.iter().zip(args_decl.iter()) {
-                tc_coerce_type(arg_ty, &decl.1, *arg_span)?;
+            for ((arg_ty, arg), decl) in args_ty.iter().zip(args_decl.iter()) {
+                let arg_ty = match (&arg.expr, &decl.1) {
+                    // an array literal can be passed as a dynamic array
+                    (ArrayLiteral(_), TypeDecl::DynArray(_)) => {
+                        tc_dyn_array_literal(arg_ty.clone())
+                    }
+                    _ => arg_ty.clone(),
+                };
+                tc_coerce_type(&arg_ty, &decl.1, arg.span)?;
             }
             func.ret_type()
         }
@@ -421,70 +1567,161 @@ fn tc_expr<'src>(
         Sub(lhs, rhs) => tc_binary_op(lhs, rhs, ctx, "Sub")?,
         Mul(lhs, rhs) => tc_binary_op(lhs, rhs, ctx, "Mul")?,
         Div(lhs, rhs) => tc_binary_op(lhs, rhs, ctx, "Div")?,
-        And(lhs, rhs) => tc_binary_cmp(lhs, rhs, ctx, "And")?,
-        Or(lhs, rhs) => tc_binary_cmp(lhs, rhs, ctx, "Or")?,
+        And(lhs, rhs) | Or(lhs, rhs) => tc_logic_op(lhs, rhs, ctx)?,
         Gt(lhs, rhs) => tc_binary_cmp(lhs, rhs, ctx, "GT")?,
         Lt(lhs, rhs) => tc_binary_cmp(lhs, rhs, ctx, "LT")?,
         Eq(lhs, rhs) => tc_binary_cmp(lhs, rhs, ctx, "Eq")?,
         Neq(lhs, rhs) => tc_binary_cmp(lhs, rhs, ctx, "Neq")?,
-        Not(ex) => {
+        Cast(ex, td) => {
             let ty = tc_expr(ex, ctx)?;
-            if ty == TypeDecl::I64 {
-                TypeDecl::I64
-            } else if ty == TypeDecl::F64 {
-                TypeDecl::F64
-            } else {
-                return Err(TypeCheckError::new(
-                    format!("Operation Not between incompatible type: {:?}", ty),
-                    e.span,
-                ));
+            match (&ty, td) {
+                // any number can be formatted
+                (TypeDecl::Any | TypeDecl::F64 | TypeDecl::I64 | TypeDecl::Str, TypeDecl::Str) => {
+                    TypeDecl::Str
+                }
+                _ => tc_coerce_type(&ty, td, ex.span).map_err(|_| {
+                    TypeCheckError::new(
+                        ErrorCode::E002,
+                        format!("{ty:?} cannot be cast to {td:?}"),
+                        e.span,
+                    )
+                })?,
             }
         }
+        Not(ex) => {
+            tc_truthy(&tc_expr(ex, ctx)?, ex.span)?;
+            TypeDecl::I64
+        }
         If(cond, true_branch, false_branch) => {
-            tc_coerce_type(&tc_expr(cond, ctx)?, &TypeDecl::I64, cond.span)?;
+            tc_truthy(&tc_expr(cond, ctx)?, cond.span)?;
             let true_type = type_check(true_branch, ctx)?;
             if let Some(false_branch) = false_branch {
                 let false_type = type_check(false_branch, ctx)?;
-                binary_op_type(&true_type, &false_type).map_err(|_| {
+                let branch_type = match (&true_type, &false_type) {
+                    (TypeDecl::Unit, TypeDecl::Unit) => Ok(TypeDecl::Unit),
+                    _ => binary_op_type(&true_type, &false_type),
+                };
+                branch_type.map_err(|_| {
                     let true_span = true_branch.span();
                     let false_span = false_branch.span();
                     TypeCheckError::new(
+                        ErrorCode::E002,
                         format!("Conditional expression doesn't have the compatible types in true and flse banch: {:?} and {:?}", true_type, false_type),
                         calc_offset(true_span, false_span),
                     )
                 })?
             } else {
-                true_type
+                // A false condition leaves no value
+                TypeDecl::Unit
             }
         }
-        Await(ex) => {
+        Await(ex) | Yield(ex) => {
             let _res = tc_expr(ex, ctx)?;
             TypeDecl::Any
         }
+        Lambda(args, ret_type, stmts) => {
+            let mut subctx = TypeCheckContext::push_fn_scope(ctx, "<lambda>", args, ret_type);
+            let last_stmt = type_check(stmts, &mut subctx)?;
+            if !ends_with_return(stmts) {
+                tc_return_type(&last_stmt, ret_type, tail_span(stmts, e.span))?;
+            }
+            TypeDecl::Func
+        }
     })
 }
 
+/// Check a returned value against the declared return type of the function
+fn tc_return_type<'src>(
+    res: &TypeDecl,
+    ret_type: &TypeDecl,
+    span: Span<'src>,
+) -> Result<(), TypeCheckError<'src>> {
+    tc_coerce_type(res, ret_type, span).map_err(|_| {
+        TypeCheckError::new(
+            ErrorCode::E002,
+            format!(
+                "Returned value of type {res:?} does not match the declared return type {ret_type:?}"
+            ),
+            span,
+        )
+    })?;
+    Ok(())
+}
+
+/// Span of the statement giving the value of a function body, the last one other than comments,
+/// or `fallback` for an empty body
+fn tail_span<'src>(stmts: &Statements<'src>, fallback: Span<'src>) -> Span<'src> {
+    stmts
+        .iter()
+        .rev()
+        .find_map(|stmt| stmt.span())
+        .unwrap_or(fallback)
+}
+
+/// Whether the last statement other than comments is a `return`, whose value the `Return` arm
+/// has already checked against the declared return type
+fn ends_with_return(stmts: &Statements) -> bool {
+    matches!(
+        stmts
+            .iter()
+            .rfind(|stmt| !matches!(stmt, Statement::Comment)),
+        Some(Statement::Return(_))
+    )
+}
+
+/// Type of an array literal (possibly nested) which is compiled into a dynamic array
+fn tc_dyn_array_literal(ty: TypeDecl) -> TypeDecl {
+    match ty {
+        TypeDecl::Array(ty, _) => TypeDecl::DynArray(Box::new(tc_dyn_array_literal(*ty))),
+        _ => ty,
+    }
+}
+
 pub fn type_check<'src>(
     stmts: &Vec<Statement<'src>>,
-    ctx: &mut TypeCheckContext<'src, '_>,
+    ctx: &mut TypeCheckContext<'_>,
 ) -> Result<TypeDecl, TypeCheckError<'src>> {
     let mut res = TypeDecl::Any;
     for stmt in stmts {
         match stmt {
             Statement::VarDef { name, td, ex, .. } => {
-                let init_type = tc_expr(ex, ctx)?;
-                let init_type = tc_coerce_type(&init_type, td, ex.span)?;
-                ctx.vars.insert(**name, init_type);
+                let init_type = match (tc_expr(ex, ctx)?, &ex.expr) {
+                    // an array literal can initialize a dynamic array
+                    (init_type, ExprEnum::ArrayLiteral(_))
+                        if matches!(td, TypeDecl::DynArray(_)) =>
+                    {
+                        tc_dyn_array_literal(init_type)
+                    }
+                    (init_type, _) => init_type,
+                };
+                let init_type = match tc_coerce_type(&init_type, td, ex.span)? {
+                    // a value of unknown type, e.g. from `pop`, takes the declared type
+                    TypeDecl::Any => td.clone(),
+                    init_type => init_type,
+                };
+                ctx.define_var(name, init_type);
             }
             Statement::VarAssign { name, ex, .. } => {
                 let init_type = tc_expr(ex, ctx)?;
-                let target = ctx.vars.get(**name).expect("Variable not found");
-                tc_coerce_type(&init_type, target, ex.span)?;
+                let target = ctx.get_var(name).ok_or_else(|| {
+                    TypeCheckError::new(
+                        ErrorCode::E001,
+                        format!("Variable \"{}\" not found", name),
+                        *name,
+                    )
+                })?;
+                tc_coerce_type(&init_type, &target, ex.span)?;
             }
             Statement::ArrayIndexAssign { name, ex, .. } => {
                 let init_type = tc_expr(ex, ctx)?;
-                let arr = ctx.vars.get(**name).expect("Variable not found");
-                let mut var_ty = arr;
+                let arr = ctx.get_var(name).ok_or_else(|| {
+                    TypeCheckError::new(
+                        ErrorCode::E001,
+                        format!("Variable \"{}\" not found", name),
+                        *name,
+                    )
+                })?;
+                let mut var_ty = &arr;
                 while let TypeDecl::Array(ty, _) = var_ty {
                     var_ty = ty;
                 }
@@ -496,69 +1733,121 @@ pub fn type_check<'src>(
                 ret_type,
                 stmts,
                 cofn,
+                ..
             } => {
-                ctx.funcs.insert(
-                    name.to_string(),
+                ctx.define_fn(
+                    name,
                     FnDecl::User(UserFn {
-                        args: args.clone(),
+                        args: args
+                            .iter()
+                            .map(|(arg, ty)| (arg.to_string(), ty.clone()))
+                            .collect(),
                         ret_type: ret_type.clone(),
                         cofn: *cofn,
                     }),
                 );
-                let mut subctx = TypeCheckContext::push_stack(ctx);
-                for (arg, ty) in args.iter() {
-                    subctx.vars.insert(arg, ty.clone());
-                }
+                let mut subctx = TypeCheckContext::push_fn_scope(ctx, name, args, ret_type);
                 let last_stmt = type_check(stmts, &mut subctx)?;
-                tc_coerce_type(&last_stmt, ret_type, stmts.span())?;
+                // A coroutine which runs to the end finishes without a value to yield
+                if !(ends_with_return(stmts) || *cofn && last_stmt == TypeDecl::Unit) {
+                    tc_return_type(&last_stmt, ret_type, tail_span(stmts, *name))?;
+                }
             }
             Statement::Expression(e) => {
                 res = tc_expr(e, ctx)?;
             }
+            // A comment has no value, so the type of the statement before it is kept
+            Statement::Comment => (),
             Statement::For {
                 loop_var,
                 start,
                 end,
+                step,
                 stmts,
                 ..
             } => {
-                tc_coerce_type(&tc_expr(start, ctx)?, &TypeDecl::I64, start.span)?;
-                tc_coerce_type(&tc_expr(end, ctx)?, &TypeDecl::I64, end.span)?;
-                ctx.vars.insert(loop_var, TypeDecl::I64);
-                res = type_check(stmts, ctx)?;
+                let bounds = [
+                    (Some(start), "start"),
+                    (Some(end), "end"),
+                    (step.as_ref(), "step"),
+                ];
+                for (bound, name) in bounds {
+                    let Some(bound) = bound else {
+                        continue;
+                    };
+                    let ty = tc_expr(bound, ctx)?;
+                    if !matches!(ty, TypeDecl::Any | TypeDecl::F64 | TypeDecl::I64) {
+                        return Err(TypeCheckError::new(
+                            ErrorCode::E002,
+                            format!("The {name} of a for loop should be a number: {ty:?}"),
+                            bound.span,
+                        ));
+                    }
+                }
+                // The loop variable is visible only in the body
+                let mut subctx = TypeCheckContext::push_stack(ctx);
+                subctx.define_var(loop_var, TypeDecl::I64);
+                type_check(stmts, &mut subctx)?;
+                // A loop has no value, whatever its body does
+                res = TypeDecl::Unit;
             }
             Statement::While { cond, stmts, .. } => {
-                tc_coerce_type(&tc_expr(cond, ctx)?, &TypeDecl::I64, cond.span)?;
-                res = type_check(stmts, ctx)?;
+                tc_truthy(&tc_expr(cond, ctx)?, cond.span)?;
+                type_check(stmts, ctx)?;
+                res = TypeDecl::Unit;
+            }
+            Statement::Match {
+                scrutinee,
+                arms,
+                default,
+                ..
+            } => {
+                let scrutinee_ty = tc_expr(scrutinee, ctx)?;
+                for (pattern, stmts) in arms {
+                    let pattern_ty = tc_expr(pattern, ctx)?;
+                    binary_op_type(&scrutinee_ty, &pattern_ty).map_err(|_| {
+                        TypeCheckError::new(
+                            ErrorCode::E002,
+                            format!(
+                                "Pattern of type {pattern_ty:?} can't match a value of type {scrutinee_ty:?}"
+                            ),
+                            pattern.span,
+                        )
+                    })?;
+                    type_check(stmts, ctx)?;
+                }
+                if let Some(stmts) = default {
+                    type_check(stmts, ctx)?;
+                }
+                res = TypeDecl::Unit;
             }
             Statement::Return(e) => {
-                return tc_expr(e, ctx);
+                res = tc_expr(e, ctx)?;
+                if let Some(ret_type) = &ctx.ret_type {
+                    tc_return_type(&res, ret_type, e.span)?;
+                }
             }
             Statement::Break => {
                 // TODO
             }
             Statement::Continue => (),
-            Statement::Yield(e) => {
-                tc_expr(e, ctx)?;
-                // TODO
-            }
         }
     }
     Ok(res)
 }
 
 pub enum FnDecl<'src> {
-    User(UserFn<'src>),
+    User(UserFn),
     Native(NativeFn<'src>),
 }
 
 impl<'src> FnDecl<'src> {
-    fn args(&self) -> Vec<(&'src str, TypeDecl)> {
+    fn args(&self) -> Vec<(&str, TypeDecl)> {
         match self {
             Self::User(user) => user
                 .args
                 .iter()
-                .map(|(name, ty)| (*name.fragment(), ty.clone()))
+                .map(|(name, ty)| (name.as_str(), ty.clone()))
                 .collect(),
             Self::Native(native) => native.args.clone(),
         }
@@ -578,16 +1867,16 @@ impl<'src> FnDecl<'src> {
     }
 }
 
-pub struct UserFn<'src> {
-    args: Vec<(Span<'src>, TypeDecl)>,
+pub struct UserFn {
+    args: Vec<(String, TypeDecl)>,
     ret_type: TypeDecl,
     cofn: bool,
 }
 
-type NativeFnCode = dyn Fn(&[Value]) -> Value;
+type NativeFnCode = dyn Fn(&[Value]) -> Result<Value, RuntimeError>;
 pub struct NativeFn<'src> {
-    args: Vec<(&'src str, TypeDecl)>,
-    ret_type: TypeDecl,
+    pub(crate) args: Vec<(&'src str, TypeDecl)>,
+    pub(crate) ret_type: TypeDecl,
     pub code: Box<NativeFnCode>,
 }
 
@@ -596,6 +1885,8 @@ pub enum ExprEnum<'src> {
     Ident(Span<'src>),
     NumLiteral(f64),
     StrLiteral(String),
+    /// `true` or `false`. There is no boolean type, so they are `i64` 1 and 0 like `from_json` reads.
+    BoolLiteral(bool),
     ArrayLiteral(Vec<Expression<'src>>),
     ArrayIndexAccess(Span<'src>, Vec<Expression<'src>>),
     FnInvoke(Span<'src>, Vec<Expression<'src>>),
@@ -610,12 +1901,22 @@ pub enum ExprEnum<'src> {
     Eq(Box<Expression<'src>>, Box<Expression<'src>>),
     Neq(Box<Expression<'src>>, Box<Expression<'src>>),
     Not(Box<Expression<'src>>),
+    /// `ex as type`, converting a number to the other numeric type or to a string
+    Cast(Box<Expression<'src>>, TypeDecl),
     If(
         Box<Expression<'src>>,
         Box<Statements<'src>>,
         Option<Box<Statements<'src>>>,
     ),
     Await(Box<Expression<'src>>),
+    /// Yield a value from a coroutine, evaluating to the value sent in by `resume`
+    Yield(Box<Expression<'src>>),
+    /// Anonymous function with its arguments, return type and body
+    Lambda(
+        Vec<(Span<'src>, TypeDecl)>,
+        Box<TypeDecl>,
+        Box<Statements<'src>>,
+    ),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -625,9 +1926,82 @@ pub struct Expression<'a> {
 }
 
 impl<'a> Expression<'a> {
-    fn new(expr: ExprEnum<'a>, span: Span<'a>) -> Self {
+    pub(crate) fn new(expr: ExprEnum<'a>, span: Span<'a>) -> Self {
         Self { expr, span }
     }
+
+    /// Number of expressions nested in this one, including itself and those in `if` blocks.
+    ///
+    /// It recurses, so it is only called on expressions the parser has limited in depth.
+    pub fn depth(&self) -> usize {
+        use ExprEnum::*;
+        let children = match &self.expr {
+            Ident(_) | NumLiteral(_) | StrLiteral(_) | BoolLiteral(_) => 0,
+            ArrayLiteral(values) | ArrayIndexAccess(_, values) | FnInvoke(_, values) => {
+                values.iter().map(Self::depth).max().unwrap_or(0)
+            }
+            Add(lhs, rhs)
+            | Sub(lhs, rhs)
+            | Mul(lhs, rhs)
+            | Div(lhs, rhs)
+            | And(lhs, rhs)
+            | Or(lhs, rhs)
+            | Gt(lhs, rhs)
+            | Lt(lhs, rhs)
+            | Eq(lhs, rhs)
+            | Neq(lhs, rhs) => lhs.depth().max(rhs.depth()),
+            Not(ex) | Cast(ex, _) | Await(ex) | Yield(ex) => ex.depth(),
+            // A function body is compiled on its own, so it doesn't add to the depth
+            Lambda(..) => 0,
+            If(cond, true_branch, false_branch) => cond
+                .depth()
+                .max(stmts_depth(true_branch))
+                .max(false_branch.as_deref().map_or(0, stmts_depth)),
+        };
+        children + 1
+    }
+}
+
+/// Depth of the deepest expression in the statements
+fn stmts_depth(stmts: &Statements) -> usize {
+    let exprs_depth = |exprs: &[&Expression]| exprs.iter().map(|ex| ex.depth()).max().unwrap_or(0);
+    stmts
+        .iter()
+        .map(|stmt| match stmt {
+            Statement::Expression(ex) | Statement::Return(ex) => ex.depth(),
+            Statement::VarDef { ex, .. } | Statement::VarAssign { ex, .. } => ex.depth(),
+            Statement::ArrayIndexAssign { indices, ex, .. } => indices
+                .iter()
+                .map(Expression::depth)
+                .max()
+                .unwrap_or(0)
+                .max(ex.depth()),
+            Statement::For {
+                start,
+                end,
+                step,
+                stmts,
+                ..
+            } => exprs_depth(&[start, end])
+                .max(step.as_ref().map_or(0, Expression::depth))
+                .max(stmts_depth(stmts)),
+            Statement::While { cond, stmts, .. } => cond.depth().max(stmts_depth(stmts)),
+            Statement::Match {
+                scrutinee,
+                arms,
+                default,
+                ..
+            } => arms
+                .iter()
+                .map(|(pattern, stmts)| pattern.depth().max(stmts_depth(stmts)))
+                .fold(scrutinee.depth(), usize::max)
+                .max(default.as_ref().map_or(0, stmts_depth)),
+            // A function body is compiled on its own, so it doesn't add to the depth
+            Statement::FnDef { .. } => 0,
+            Statement::Break | Statement::Continue | Statement::Comment => 0,
+        })
+        .max()
+        .unwrap_or(0)
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -655,6 +2029,8 @@ pub enum Statement<'src> {
         loop_var: Span<'src>,
         start: Expression<'src>,
         end: Expression<'src>,
+        /// Increment of the loop variable, which is 1 if omitted
+        step: Option<Expression<'src>>,
         stmts: Statements<'src>,
     },
     While {
@@ -662,21 +2038,31 @@ pub enum Statement<'src> {
         cond: Expression<'src>,
         stmts: Statements<'src>,
     },
+    /// Only literal patterns are supported, and the arms can't bind variables yet.
+    Match {
+        span: Span<'src>,
+        scrutinee: Expression<'src>,
+        arms: Vec<(Expression<'src>, Statements<'src>)>,
+        default: Option<Statements<'src>>,
+    },
     Break,
     Continue,
+    /// Line comment. It evaluates to 0, so a block ending with a comment still has a value.
+    Comment,
     FnDef {
         name: Span<'src>,
         args: Vec<(Span<'src>, TypeDecl)>,
         ret_type: TypeDecl,
         stmts: Statements<'src>,
         cofn: bool,
+        /// Whether the function is a part of the public API of the compiled bytecode
+        export: bool,
     },
     Return(Expression<'src>),
-    Yield(Expression<'src>),
 }
 
 impl<'src> Statement<'src> {
-    fn span(&self) -> Option<Span<'src>> {
+    pub fn span(&self) -> Option<Span<'src>> {
         use Statement::*;
         Some(match self {
             Expression(ex) => ex.span,
@@ -685,10 +2071,10 @@ impl<'src> Statement<'src> {
             ArrayIndexAssign { span, .. } => *span,
             For { span, .. } => *span,
             While { span, .. } => *span,
-            FnDef { name, stmts, .. } => calc_offset(*name, stmts.span()),
+            Match { span, .. } => *span,
+            FnDef { name, .. } => *name,
             Return(ex) => ex.span,
-            Break | Continue => return None,
-            Yield(ex) => ex.span,
+            Break | Continue | Comment => return None,
         })
     }
 }
@@ -720,8 +2106,10 @@ fn calc_offset<'a>(i: Span<'a>, r: Span<'a>) -> Span<'a> {
 
 fn factor(i: Span) -> IResult<Span, Expression> {
     alt((
+        lambda,
         str_literal,
         num_literal,
+        bool_literal,
         func_call,
         array_index_access,
         ident,
@@ -732,11 +2120,25 @@ fn factor(i: Span) -> IResult<Span, Expression> {
 
 fn func_call(i: Span) -> IResult<Span, Expression> {
     let (r, ident) = space_delimited(identifier)(i)?;
-    let (r, args) = space_delimited(delimited(
+    let (r, (args_src, mut args)) = space_delimited(delimited(
         tag("("),
-        many0(delimited(multispace0, expr, space_delimited(opt(tag(","))))),
+        consumed(many0(delimited(
+            multispace0,
+            expr,
+            space_delimited(opt(tag(","))),
+        ))),
         tag(")"),
     ))(r)?;
+    // `dbg(ex)` is given the location and the source of `ex` to print along with the value
+    if *ident == "dbg" && args.len() == 1 {
+        let label = format!(
+            "[{}:{}] {}",
+            ident.location_line(),
+            ident.get_utf8_column(),
+            args_src.trim().trim_end_matches(',').trim_end()
+        );
+        args.insert(0, Expression::new(ExprEnum::StrLiteral(label), ident));
+    }
     Ok((
         r,
         Expression {
@@ -746,22 +2148,69 @@ fn func_call(i: Span) -> IResult<Span, Expression> {
     ))
 }
 
+/// A factor followed by any number of `as type`, which bind tighter than `*` and `/`
+fn cast_factor(input: Span) -> IResult<Span, Expression> {
+    let (mut r, mut ex) = factor(input)?;
+    loop {
+        let (next, td) = match preceded(terminated(tag("as"), multispace1), cut(type_decl))(r) {
+            Ok(res) => res,
+            Err(nom::Err::Error(_)) => return Ok((r, ex)),
+            Err(e) => return Err(e),
+        };
+        if max_expr_depth() < ex.depth() + 1 {
+            return too_deep(r);
+        }
+        ex = Expression::new(ExprEnum::Cast(Box::new(ex), td), calc_offset(input, next));
+        r = next;
+    }
+}
+
 fn term(input: Span) -> IResult<Span, Expression> {
-    let (r, init) = factor(input)?;
-
-    let res = fold_many0(
-        pair(space_delimited(alt((char('*'), char('/')))), factor),
-        move || init.clone(),
-        |acc, (op, val): (char, Expression)| {
-            let span = calc_offset(input, acc.span);
-            match op {
-                '*' => Expression::new(ExprEnum::Mul(Box::new(acc), Box::new(val)), span),
-                '/' => Expression::new(ExprEnum::Div(Box::new(acc), Box::new(val)), span),
-                _ => panic!("Multiplicative expression should have '*' or '/' operator"),
-            }
+    let (r, init) = cast_factor(input)?;
+
+    fold_binary_ops(
+        input,
+        r,
+        init,
+        ('*', '/'),
+        cast_factor,
+        |op, lhs, rhs| match op {
+            '*' => ExprEnum::Mul(lhs, rhs),
+            '/' => ExprEnum::Div(lhs, rhs),
+            _ => panic!("Multiplicative expression should have '*' or '/' operator"),
         },
-    )(r);
-    res
+    )
+}
+
+/// Fold a chain of left associative binary operators onto `init`.
+///
+/// The depth of the expression is checked on every operator, so a long chain fails before the
+/// tree gets too deep even to be dropped.
+fn fold_binary_ops<'src>(
+    input: Span<'src>,
+    mut r: Span<'src>,
+    init: Expression<'src>,
+    ops: (char, char),
+    operand: fn(Span<'src>) -> IResult<Span<'src>, Expression<'src>>,
+    make: fn(char, Box<Expression<'src>>, Box<Expression<'src>>) -> ExprEnum<'src>,
+) -> IResult<Span<'src>, Expression<'src>> {
+    let mut acc = init;
+    let mut depth = acc.depth();
+    loop {
+        let (next, (op, val)) =
+            match pair(space_delimited(alt((char(ops.0), char(ops.1)))), operand)(r) {
+                Ok(res) => res,
+                Err(nom::Err::Error(_)) => return Ok((r, acc)),
+                Err(e) => return Err(e),
+            };
+        depth = depth.max(val.depth()) + 1;
+        if max_expr_depth() < depth {
+            return too_deep(r);
+        }
+        let span = calc_offset(input, acc.span);
+        acc = Expression::new(make(op, Box::new(acc), Box::new(val)), span);
+        r = next;
+    }
 }
 
 fn ident(input: Span) -> IResult<Span, Expression> {
@@ -800,6 +2249,22 @@ fn str_literal(i: Span) -> IResult<Span, Expression> {
     ))
 }
 
+/// `true` and `false` are tried before identifiers, so that they are never read as variables.
+fn bool_literal(input: Span) -> IResult<Span, Expression> {
+    let (r, word) = space_delimited(identifier)(input)?;
+    let value = match *word {
+        "true" => true,
+        "false" => false,
+        _ => {
+            return Err(nom::Err::Error(nom::error::Error {
+                input,
+                code: nom::error::ErrorKind::Tag,
+            }))
+        }
+    };
+    Ok((r, Expression::new(ExprEnum::BoolLiteral(value), word)))
+}
+
 fn num_literal(input: Span) -> IResult<Span, Expression> {
     let (r, v) = space_delimited(recognize_float)(input)?;
     Ok((
@@ -865,19 +2330,11 @@ fn not_factor(i: Span) -> IResult<Span, Expression> {
 fn num_expr(i: Span) -> IResult<Span, Expression> {
     let (r, init) = term(i)?;
 
-    let res = fold_many0(
-        pair(space_delimited(alt((char('+'), char('-')))), term),
-        move || init.clone(),
-        |acc, (op, val): (char, Expression)| {
-            let span = calc_offset(i, acc.span);
-            match op {
-                '+' => Expression::new(ExprEnum::Add(Box::new(acc), Box::new(val)), span),
-                '-' => Expression::new(ExprEnum::Sub(Box::new(acc), Box::new(val)), span),
-                _ => panic!("Additive expression should have '+' or '-' operator"),
-            }
-        },
-    )(r);
-    res
+    fold_binary_ops(i, r, init, ('+', '-'), term, |op, lhs, rhs| match op {
+        '+' => ExprEnum::Add(lhs, rhs),
+        '-' => ExprEnum::Sub(lhs, rhs),
+        _ => panic!("Additive expression should have '+' or '-' operator"),
+    })
 }
 
 fn cond_expr(i0: Span) -> IResult<Span, Expression> {
@@ -952,15 +2409,42 @@ fn await_expr(i: Span) -> IResult<Span, Expression> {
     ))
 }
 
+fn yield_expr(i: Span) -> IResult<Span, Expression> {
+    let i0 = i;
+    let (i, _) = space_delimited(tag("yield"))(i)?;
+    let (i, ex) = cut(space_delimited(expr))(i)?;
+    Ok((
+        i,
+        Expression::new(ExprEnum::Yield(Box::new(ex)), calc_offset(i0, i)),
+    ))
+}
+
 pub fn expr(i: Span) -> IResult<Span, Expression> {
-    alt((await_expr, if_expr, cond_expr, num_expr, array_literal))(i)
+    let (r, ex) = nested(
+        i,
+        alt((
+            await_expr,
+            yield_expr,
+            if_expr,
+            cond_expr,
+            num_expr,
+            array_literal,
+        )),
+    )?;
+    if max_expr_depth() < ex.depth() {
+        return too_deep(i);
+    }
+    Ok((r, ex))
 }
 
 fn var_def(i: Span) -> IResult<Span, Statement> {
     let span = i;
     let (i, _) = delimited(multispace0, tag("var"), multispace1)(i)?;
     let (i, (name, td, ex)) = cut(|i| {
-        let (i, name) = space_delimited(identifier)(i)?;
+        // A variable named like a literal couldn't be read
+        let (i, name) = space_delimited(verify(identifier, |name: &Span| {
+            !matches!(**name, "true" | "false")
+        }))(i)?;
         let (i, _) = space_delimited(char(':'))(i)?;
         let (i, td) = type_decl(i)?;
         let (i, _) = space_delimited(char('='))(i)?;
@@ -982,9 +2466,24 @@ fn var_def(i: Span) -> IResult<Span, Statement> {
 fn var_assign(i: Span) -> IResult<Span, Statement> {
     let span = i;
     let (i, name) = space_delimited(identifier)(i)?;
-    let (i, _) = space_delimited(char('='))(i)?;
+    let (i, op) = space_delimited(alt((tag("="), tag("+="), tag("-="), tag("*="), tag("/="))))(i)?;
     let (i, ex) = space_delimited(expr)(i)?;
     let (i, _) = space_delimited(char(';'))(i)?;
+    // Compound assignment `x += e` is desugared into `x = x + e`
+    let ex = if *op == "=" {
+        ex
+    } else {
+        let lhs = Box::new(Expression::new(ExprEnum::Ident(name), name));
+        let span = ex.span;
+        let rhs = Box::new(ex);
+        let expr = match *op {
+            "+=" => ExprEnum::Add(lhs, rhs),
+            "-=" => ExprEnum::Sub(lhs, rhs),
+            "*=" => ExprEnum::Mul(lhs, rhs),
+            _ => ExprEnum::Div(lhs, rhs),
+        };
+        Expression::new(expr, span)
+    };
     Ok((
         i,
         Statement::VarAssign {
@@ -1027,14 +2526,18 @@ fn expr_statement(i: Span) -> IResult<Span, Statement> {
 fn for_statement(i: Span) -> IResult<Span, Statement> {
     let i0 = i;
     let (i, _) = space_delimited(tag("for"))(i)?;
-    let (i, (loop_var, start, end, stmts)) = cut(|i| {
+    let (i, (loop_var, start, end, step, stmts)) = cut(|i| {
         let (i, loop_var) = space_delimited(identifier)(i)?;
         let (i, _) = space_delimited(tag("in"))(i)?;
         let (i, start) = space_delimited(expr)(i)?;
         let (i, _) = space_delimited(tag("to"))(i)?;
         let (i, end) = space_delimited(expr)(i)?;
+        let (i, step) = opt(preceded(
+            space_delimited(tag("step")),
+            space_delimited(expr),
+        ))(i)?;
         let (i, stmts) = delimited(open_brace, statements, close_brace)(i)?;
-        Ok((i, (loop_var, start, end, stmts)))
+        Ok((i, (loop_var, start, end, step, stmts)))
     })(i)?;
     Ok((
         i,
@@ -1043,6 +2546,7 @@ fn for_statement(i: Span) -> IResult<Span, Statement> {
             loop_var,
             start,
             end,
+            step,
             stmts,
         },
     ))
@@ -1066,6 +2570,42 @@ fn while_statement(i: Span) -> IResult<Span, Statement> {
     ))
 }
 
+fn match_arm(i: Span) -> IResult<Span, (Expression, Statements)> {
+    let (i, pattern) = alt((str_literal, num_literal))(i)?;
+    let (i, _) = space_delimited(tag("=>"))(i)?;
+    let (i, stmts) = delimited(open_brace, statements, close_brace)(i)?;
+    let (i, _) = opt(space_delimited(char(',')))(i)?;
+    Ok((i, (pattern, stmts)))
+}
+
+fn match_statement(i: Span) -> IResult<Span, Statement> {
+    let i0 = i;
+    let (i, _) = space_delimited(terminated(tag("match"), multispace1))(i)?;
+    let (i, (scrutinee, arms, default)) = cut(|i| {
+        let (i, scrutinee) = space_delimited(expr)(i)?;
+        let (i, _) = open_brace(i)?;
+        let (i, arms) = many0(match_arm)(i)?;
+        let (i, default) = opt(|i| {
+            let (i, _) = space_delimited(tag("_"))(i)?;
+            let (i, _) = space_delimited(tag("=>"))(i)?;
+            let (i, stmts) = delimited(open_brace, statements, close_brace)(i)?;
+            let (i, _) = opt(space_delimited(char(',')))(i)?;
+            Ok((i, stmts))
+        })(i)?;
+        let (i, _) = close_brace(i)?;
+        Ok((i, (scrutinee, arms, default)))
+    })(i)?;
+    Ok((
+        i,
+        Statement::Match {
+            span: calc_offset(i0, i),
+            scrutinee,
+            arms,
+            default,
+        },
+    ))
+}
+
 fn type_decl(i: Span) -> IResult<Span, TypeDecl> {
     let (i, td) = space_delimited(identifier)(i)?;
     match *td.fragment() {
@@ -1078,15 +2618,19 @@ fn type_decl(i: Span) -> IResult<Span, TypeDecl> {
                 type_decl,
                 space_delimited(tag(">")),
             )(i)?;
-            let (i, len) = delimited(
+            let (i, len) = opt(delimited(
                 space_delimited(tag("[")),
                 space_delimited(recognize_float), // TODO: index should be usize
                 space_delimited(tag("]")),
-            )(i)?;
+            ))(i)?;
 
-            Ok((i, TypeDecl::Array(Box::new(td), len.parse().unwrap())))
+            match len {
+                Some(len) => Ok((i, TypeDecl::Array(Box::new(td), len.parse().unwrap()))),
+                None => Ok((i, TypeDecl::DynArray(Box::new(td)))),
+            }
         }
         "cofn" => Ok((i, TypeDecl::Coro)),
+        "fn" => Ok((i, TypeDecl::Func)),
         _ => Err(nom::Err::Failure(nom::error::Error::new(
             td,
             nom::error::ErrorKind::Verify,
@@ -1101,19 +2645,39 @@ fn argument(i: Span) -> IResult<Span, (Span, TypeDecl)> {
     Ok((i, (ident, td)))
 }
 
+/// Arguments, return type and body of a function
+type FnSignatureAndBody<'src> = (Vec<(Span<'src>, TypeDecl)>, TypeDecl, Statements<'src>);
+
+/// Arguments, return type and body of a function after its name
+fn fn_signature_and_body(i: Span) -> IResult<Span, FnSignatureAndBody> {
+    let (i, _) = space_delimited(tag("("))(i)?;
+    let (i, args) = separated_list0(char(','), space_delimited(argument))(i)?;
+    let (i, _) = space_delimited(tag(")"))(i)?;
+    let (i, _) = space_delimited(tag("->"))(i)?;
+    let (i, ret_type) = type_decl(i)?;
+    let (i, stmts) = delimited(open_brace, statements, close_brace)(i)?;
+    Ok((i, (args, ret_type, stmts)))
+}
+
+fn lambda(i0: Span) -> IResult<Span, Expression> {
+    // Without a name, `fn` is followed by the arguments
+    let (i, _) = space_delimited(terminated(tag("fn"), peek(space_delimited(char('(')))))(i0)?;
+    let (i, (args, ret_type, stmts)) = cut(fn_signature_and_body)(i)?;
+    Ok((
+        i,
+        Expression::new(
+            ExprEnum::Lambda(args, Box::new(ret_type), Box::new(stmts)),
+            calc_offset(i0, i),
+        ),
+    ))
+}
+
 fn fn_def_statement(i: Span) -> IResult<Span, Statement> {
+    let (i, export) = opt(space_delimited(tag("export")))(i)?;
     let (i, fn_kw) = space_delimited(alt((tag("cofn"), tag("fn"))))(i)?;
-    let (i, (name, args, ret_type, stmts)) = cut(|i| {
-        let (i, name) = space_delimited(identifier)(i)?;
-        let (i, _) = space_delimited(tag("("))(i)?;
-        let (i, args) = separated_list0(char(','), space_delimited(argument))(i)?;
-        let (i, _) = space_delimited(tag(")"))(i)?;
-        let (i, _) = space_delimited(tag("->"))(i)?;
-        let (i, ret_type) = type_decl(i)?;
-        let (i, stmts) = delimited(open_brace, statements, close_brace)(i)?;
-
-        Ok((i, (name, args, ret_type, stmts)))
-    })(i)?;
+    // A lambda has no name, so it is an expression statement
+    let (i, name) = space_delimited(identifier)(i)?;
+    let (i, (args, ret_type, stmts)) = cut(fn_signature_and_body)(i)?;
     Ok((
         i,
         Statement::FnDef {
@@ -1122,6 +2686,7 @@ fn fn_def_statement(i: Span) -> IResult<Span, Statement> {
             ret_type,
             stmts,
             cofn: *fn_kw == "cofn",
+            export: export.is_some(),
         },
     ))
 }
@@ -1142,19 +2707,11 @@ fn continue_statement(i: Span) -> IResult<Span, Statement> {
     Ok((i, Statement::Continue))
 }
 
-fn yield_statement(i: Span) -> IResult<Span, Statement> {
-    let (i, _) = space_delimited(tag("yield"))(i)?;
-    let (i, ex) = cut(space_delimited(expr))(i)?;
-    Ok((i, Statement::Yield(ex)))
-}
-
 fn comment_statement(i: Span) -> IResult<Span, Statement> {
-    let (i, _) = space_delimited(tag("//"))(i)?;
+    // Spaces after `//` are a part of the comment, or an empty comment would take the next line
+    let (i, _) = preceded(multispace0, tag("//"))(i)?;
     let (i, _) = take_until("\n")(i)?;
-    Ok((
-        i,
-        Statement::Expression(Expression::new(ExprEnum::NumLiteral(0.0), i)),
-    ))
+    Ok((i, Statement::Comment))
 }
 
 fn general_statement<'a>(last: bool) -> impl Fn(Span<'a>) -> IResult<Span<'a>, Statement> {
@@ -1175,10 +2732,10 @@ fn general_statement<'a>(last: bool) -> impl Fn(Span<'a>) -> IResult<Span<'a>, S
             fn_def_statement,
             for_statement,
             while_statement,
+            match_statement,
             terminated(return_statement, terminator),
             terminated(break_statement, terminator),
             terminated(continue_statement, terminator),
-            terminated(yield_statement, terminator),
             terminated(expr_statement, terminator),
         ))(input)
     }
@@ -1193,6 +2750,10 @@ pub fn statement(i: Span) -> IResult<Span, Statement> {
 }
 
 fn statements(i: Span) -> IResult<Span, Statements> {
+    nested(i, statements_inner)
+}
+
+fn statements_inner(i: Span) -> IResult<Span, Statements> {
     let (i, mut stmts) = many0(statement)(i)?;
     let (i, last) = opt(last_statement)(i)?;
     let (i, _) = opt(multispace0)(i)?;
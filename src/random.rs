@@ -0,0 +1,55 @@
+use std::cell::Cell;
+
+/// Seed of the generator until a script or the host calls `seed`, so that runs are reproducible
+pub const DEFAULT_SEED: u64 = 0x5eed;
+
+thread_local! {
+    /// State of the xorshift64* generator behind the `random` natives.
+    ///
+    /// Natives don't have access to the `Vm`, so the state is kept per thread.
+    static STATE: Cell<u64> = const { Cell::new(scramble(DEFAULT_SEED)) };
+}
+
+/// Spread the bits of the seed with splitmix64, since xorshift needs a nonzero state
+/// and close seeds would otherwise start with similar sequences.
+const fn scramble(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    if z == 0 {
+        1
+    } else {
+        z
+    }
+}
+
+/// Restart the sequence of random numbers from the seed.
+pub fn seed(seed: u64) {
+    STATE.set(scramble(seed));
+}
+
+pub fn next_u64() -> u64 {
+    let mut x = STATE.get();
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    STATE.set(x);
+    x.wrapping_mul(0x2545f4914f6cdd1d)
+}
+
+/// A uniform number in `[0, 1)`
+pub fn next_f64() -> f64 {
+    // The upper 53 bits fill the mantissa exactly
+    (next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A uniform integer in `[lo, hi)`, or `None` if the range is empty
+pub fn next_range(lo: i64, hi: i64) -> Option<i64> {
+    if hi <= lo {
+        return None;
+    }
+    let span = hi.wrapping_sub(lo) as u64;
+    let offset = ((next_u64() as u128 * span as u128) >> 64) as u64;
+    Some(lo.wrapping_add(offset as i64))
+}
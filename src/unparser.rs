@@ -0,0 +1,328 @@
+use std::fmt::Write;
+
+use crate::parser::{ExprEnum, Expression, Statement, Statements, TypeDecl};
+
+const INDENT: &str = "    ";
+
+/// How tightly an expression binds, following the levels of the parser.
+///
+/// An operand is wrapped in parentheses if it binds less tightly than its position requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Prec {
+    /// `if`, `await`, `yield`, comparisons, logical operators and array literals
+    Expr,
+    /// `+` and `-`
+    Sum,
+    /// `*` and `/`
+    Term,
+    /// `as`
+    Cast,
+    /// Literals, identifiers, calls, `!` and parenthesized expressions
+    Factor,
+}
+
+/// Render statements back to source with 4 spaces of indentation per block.
+///
+/// The source parses back to the same tree, except for the spans and the text of comments,
+/// which are not kept in the tree, and the locations in the labels of `dbg`. Compound
+/// assignments come back as plain assignments, since the parser desugars them.
+pub fn unparse(stmts: &Statements) -> String {
+    let mut buf = String::new();
+    write_stmts(&mut buf, stmts, 0);
+    buf
+}
+
+fn write_stmts(buf: &mut String, stmts: &Statements, indent: usize) {
+    for stmt in stmts {
+        buf.push_str(&INDENT.repeat(indent));
+        write_stmt(buf, stmt, indent);
+        buf.push('\n');
+    }
+}
+
+/// Write a block in braces, opening on the current line and closing on its own line.
+fn write_block(buf: &mut String, stmts: &Statements, indent: usize) {
+    if stmts.is_empty() {
+        buf.push_str("{}");
+        return;
+    }
+    buf.push_str("{\n");
+    write_stmts(buf, stmts, indent + 1);
+    buf.push_str(&INDENT.repeat(indent));
+    buf.push('}');
+}
+
+fn write_stmt(buf: &mut String, stmt: &Statement, indent: usize) {
+    match stmt {
+        Statement::Expression(ex) => {
+            write_expr(buf, ex, Prec::Expr, indent);
+            buf.push(';');
+        }
+        Statement::VarDef { name, td, ex, .. } => {
+            write!(buf, "var {name}: {} = ", type_name(td)).unwrap();
+            write_expr(buf, ex, Prec::Expr, indent);
+            buf.push(';');
+        }
+        Statement::VarAssign { name, ex, .. } => {
+            write!(buf, "{name} = ").unwrap();
+            write_expr(buf, ex, Prec::Expr, indent);
+            buf.push(';');
+        }
+        Statement::ArrayIndexAssign {
+            name, indices, ex, ..
+        } => {
+            buf.push_str(name);
+            write_indices(buf, indices, indent);
+            buf.push_str(" = ");
+            write_expr(buf, ex, Prec::Expr, indent);
+            buf.push(';');
+        }
+        Statement::For {
+            loop_var,
+            start,
+            end,
+            step,
+            stmts,
+            ..
+        } => {
+            write!(buf, "for {loop_var} in ").unwrap();
+            write_expr(buf, start, Prec::Expr, indent);
+            buf.push_str(" to ");
+            write_expr(buf, end, Prec::Expr, indent);
+            if let Some(step) = step {
+                buf.push_str(" step ");
+                write_expr(buf, step, Prec::Expr, indent);
+            }
+            buf.push(' ');
+            write_block(buf, stmts, indent);
+        }
+        Statement::While { cond, stmts, .. } => {
+            buf.push_str("while ");
+            write_expr(buf, cond, Prec::Expr, indent);
+            buf.push(' ');
+            write_block(buf, stmts, indent);
+        }
+        Statement::Match {
+            scrutinee,
+            arms,
+            default,
+            ..
+        } => {
+            buf.push_str("match ");
+            write_expr(buf, scrutinee, Prec::Expr, indent);
+            buf.push_str(" {\n");
+            let patterns = arms.iter().map(|(pattern, stmts)| (Some(pattern), stmts));
+            for (pattern, stmts) in patterns.chain(default.iter().map(|stmts| (None, stmts))) {
+                buf.push_str(&INDENT.repeat(indent + 1));
+                match pattern {
+                    Some(pattern) => write_expr(buf, pattern, Prec::Factor, indent + 1),
+                    None => buf.push('_'),
+                }
+                buf.push_str(" => ");
+                write_block(buf, stmts, indent + 1);
+                buf.push_str(",\n");
+            }
+            buf.push_str(&INDENT.repeat(indent));
+            buf.push('}');
+        }
+        Statement::Break => buf.push_str("break;"),
+        Statement::Continue => buf.push_str("continue;"),
+        Statement::Comment => buf.push_str("//"),
+        Statement::FnDef {
+            name,
+            args,
+            ret_type,
+            stmts,
+            cofn,
+            export,
+        } => {
+            if *export {
+                buf.push_str("export ");
+            }
+            buf.push_str(if *cofn { "cofn " } else { "fn " });
+            buf.push_str(name);
+            write_signature_and_body(buf, args, ret_type, stmts, indent);
+        }
+        Statement::Return(ex) => {
+            buf.push_str("return ");
+            write_expr(buf, ex, Prec::Expr, indent);
+            buf.push(';');
+        }
+    }
+}
+
+fn write_signature_and_body<T: std::fmt::Display>(
+    buf: &mut String,
+    args: &[(T, TypeDecl)],
+    ret_type: &TypeDecl,
+    stmts: &Statements,
+    indent: usize,
+) {
+    let args = args
+        .iter()
+        .map(|(name, td)| format!("{name}: {}", type_name(td)))
+        .collect::<Vec<_>>();
+    write!(buf, "({}) -> {} ", args.join(", "), type_name(ret_type)).unwrap();
+    write_block(buf, stmts, indent);
+}
+
+fn write_indices(buf: &mut String, indices: &[Expression], indent: usize) {
+    for index in indices {
+        buf.push('[');
+        write_expr(buf, index, Prec::Expr, indent);
+        buf.push(']');
+    }
+}
+
+fn write_list(buf: &mut String, values: &[Expression], indent: usize) {
+    for (i, value) in values.iter().enumerate() {
+        if i != 0 {
+            buf.push_str(", ");
+        }
+        write_expr(buf, value, Prec::Expr, indent);
+    }
+}
+
+/// Write an expression, in parentheses if it binds less tightly than `prec`.
+fn write_expr(buf: &mut String, ex: &Expression, prec: Prec, indent: usize) {
+    use ExprEnum::*;
+    let (own, op, lhs, rhs) = match &ex.expr {
+        Add(lhs, rhs) => (Prec::Sum, "+", lhs, rhs),
+        Sub(lhs, rhs) => (Prec::Sum, "-", lhs, rhs),
+        Mul(lhs, rhs) => (Prec::Term, "*", lhs, rhs),
+        Div(lhs, rhs) => (Prec::Term, "/", lhs, rhs),
+        And(lhs, rhs) => (Prec::Expr, "&&", lhs, rhs),
+        Or(lhs, rhs) => (Prec::Expr, "||", lhs, rhs),
+        Gt(lhs, rhs) => (Prec::Expr, ">", lhs, rhs),
+        Lt(lhs, rhs) => (Prec::Expr, "<", lhs, rhs),
+        Eq(lhs, rhs) => (Prec::Expr, "==", lhs, rhs),
+        Neq(lhs, rhs) => (Prec::Expr, "!=", lhs, rhs),
+        _ => {
+            let own = match &ex.expr {
+                If(..) | Await(_) | Yield(_) | ArrayLiteral(_) => Prec::Expr,
+                Cast(..) => Prec::Cast,
+                _ => Prec::Factor,
+            };
+            if own < prec {
+                buf.push('(');
+                write_operand(buf, ex, indent);
+                buf.push(')');
+            } else {
+                write_operand(buf, ex, indent);
+            }
+            return;
+        }
+    };
+
+    // Sums and terms are left associative, while the others take a single operator
+    let (lhs_prec, rhs_prec) = match own {
+        Prec::Sum => (Prec::Sum, Prec::Term),
+        Prec::Term => (Prec::Term, Prec::Cast),
+        _ => (Prec::Sum, Prec::Sum),
+    };
+    if own < prec {
+        buf.push('(');
+    }
+    write_expr(buf, lhs, lhs_prec, indent);
+    write!(buf, " {op} ").unwrap();
+    write_expr(buf, rhs, rhs_prec, indent);
+    if own < prec {
+        buf.push(')');
+    }
+}
+
+/// Write an expression that is not a binary operator.
+fn write_operand(buf: &mut String, ex: &Expression, indent: usize) {
+    use ExprEnum::*;
+    match &ex.expr {
+        Ident(name) => buf.push_str(name),
+        NumLiteral(value) => write!(buf, "{value}").unwrap(),
+        BoolLiteral(value) => write!(buf, "{value}").unwrap(),
+        StrLiteral(value) => write!(
+            buf,
+            "\"{}\"",
+            value.replace('\\', "\\\\").replace('\n', "\\n")
+        )
+        .unwrap(),
+        ArrayLiteral(values) => {
+            buf.push('[');
+            write_list(buf, values, indent);
+            buf.push(']');
+        }
+        ArrayIndexAccess(name, indices) => {
+            buf.push_str(name);
+            write_indices(buf, indices, indent);
+        }
+        FnInvoke(name, args) => {
+            write!(buf, "{name}(").unwrap();
+            match &args[..] {
+                // The parser adds the label to `dbg(ex)`, which gets another location when parsed
+                // again
+                [Expression {
+                    expr: StrLiteral(label),
+                    ..
+                }, ex]
+                    if **name == "dbg" && label.starts_with('[') =>
+                {
+                    write_expr(buf, ex, Prec::Expr, indent)
+                }
+                _ => write_list(buf, args, indent),
+            }
+            buf.push(')');
+        }
+        Not(ex) => {
+            buf.push('!');
+            write_expr(buf, ex, Prec::Factor, indent);
+        }
+        Cast(ex, td) => {
+            write_expr(buf, ex, Prec::Cast, indent);
+            write!(buf, " as {}", type_name(td)).unwrap();
+        }
+        If(cond, true_branch, false_branch) => {
+            buf.push_str("if ");
+            write_expr(buf, cond, Prec::Expr, indent);
+            buf.push(' ');
+            write_block(buf, true_branch, indent);
+            match false_branch.as_deref().map(Vec::as_slice) {
+                // `else if` is parsed into a block of the single `if` expression
+                Some([Statement::Expression(ex @ Expression { expr: If(..), .. })]) => {
+                    buf.push_str(" else ");
+                    write_operand(buf, ex, indent);
+                }
+                Some(_) => {
+                    buf.push_str(" else ");
+                    write_block(buf, false_branch.as_ref().unwrap(), indent);
+                }
+                None => {}
+            }
+        }
+        Await(ex) => {
+            buf.push_str("await ");
+            write_expr(buf, ex, Prec::Expr, indent);
+        }
+        Yield(ex) => {
+            buf.push_str("yield ");
+            write_expr(buf, ex, Prec::Expr, indent);
+        }
+        Lambda(args, ret_type, stmts) => {
+            buf.push_str("fn");
+            write_signature_and_body(buf, args, ret_type, stmts, indent);
+        }
+        _ => unreachable!("binary operators are written by write_expr"),
+    }
+}
+
+/// The name of a type as it is declared in the source
+fn type_name(td: &TypeDecl) -> String {
+    match td {
+        TypeDecl::Any => "any".to_string(),
+        TypeDecl::F64 => "f64".to_string(),
+        TypeDecl::I64 => "i64".to_string(),
+        TypeDecl::Str => "str".to_string(),
+        TypeDecl::Array(td, len) => format!("Array<{}>[{len}]", type_name(td)),
+        TypeDecl::DynArray(td) => format!("Array<{}>", type_name(td)),
+        TypeDecl::Coro => "cofn".to_string(),
+        TypeDecl::Func => "fn".to_string(),
+        TypeDecl::Unit => "unit".to_string(),
+    }
+}
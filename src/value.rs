@@ -1,7 +1,8 @@
-use super::Vm;
+use crate::compiler::{RuntimeError, Vm};
 use std::fmt::Display;
 use std::{
     cell::RefCell,
+    cmp::Ordering,
     io::{Read, Write},
     rc::Rc,
 };
@@ -29,20 +30,64 @@ pub fn deserialize_str(reader: &mut impl Read) -> std::io::Result<String> {
     Ok(s)
 }
 
+/// Write an integer as a zigzag LEB128 varint: 7 bits per byte, with the sign in the lowest bit,
+/// so that integers of small magnitude take 1 or 2 bytes, and the extremes 10.
+pub fn serialize_varint(value: i64, writer: &mut impl Write) -> std::io::Result<()> {
+    let mut bits = ((value << 1) ^ (value >> 63)) as u64;
+    while 0x80 <= bits {
+        writer.write_all(&[bits as u8 | 0x80])?;
+        bits >>= 7;
+    }
+    writer.write_all(&[bits as u8])
+}
+
+pub fn deserialize_varint(reader: &mut impl Read) -> std::io::Result<i64> {
+    let mut bits = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut buf = [0u8; 1];
+        reader.read_exact(&mut buf)?;
+        bits |= ((buf[0] & 0x7f) as u64) << shift;
+        if buf[0] & 0x80 == 0 {
+            return Ok((bits >> 1) as i64 ^ -((bits & 1) as i64));
+        }
+    }
+    Err(std::io::Error::other("Varint is longer than 64 bits"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ValueKind {
     F64,
     I64,
     Str,
     Coro,
+    Array,
+    Func,
+    Unit,
 }
 
 #[derive(Debug, Clone)]
 pub enum Value {
     F64(f64),
     I64(i64),
-    Str(String),
+    Str(Rc<String>),
     Coro(Rc<RefCell<Vm>>),
+    /// Dynamic-length array. It is a reference type, so copies share the same elements.
+    Array(Rc<RefCell<Vec<Value>>>),
+    /// Function referred by name, which can be called indirectly
+    Func(String),
+    /// Nested function with the values of the variables it captured at its definition
+    Closure(Rc<Closure>),
+    /// Result of the statements which don't produce a value, like a loop or an `if` without
+    /// `else` whose condition is false
+    Unit,
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub name: String,
+    /// Captured values, passed before the arguments in a call
+    pub captures: Vec<Value>,
 }
 
 impl PartialEq for Value {
@@ -52,11 +97,120 @@ impl PartialEq for Value {
             (F64(lhs), F64(rhs)) => lhs == rhs,
             (I64(lhs), I64(rhs)) => lhs == rhs,
             (Str(lhs), Str(rhs)) => lhs == rhs,
+            (Array(lhs), Array(rhs)) => *lhs.borrow() == *rhs.borrow(),
+            (Func(lhs), Func(rhs)) => lhs == rhs,
+            (Closure(lhs), Closure(rhs)) => Rc::ptr_eq(lhs, rhs),
+            (Unit, Unit) => true,
             _ => false,
         }
     }
 }
 
+impl Value {
+    /// Equality of the `==` and `!=` operators.
+    ///
+    /// Unlike `PartialEq`, numbers are compared after coercing to `f64` if either is `f64`,
+    /// like arithmetic does, so `I64(1)` equals `F64(1.0)`. Arrays are equal if their elements
+    /// are, and values of different kinds otherwise, like a string and a number, are not equal.
+    pub fn value_eq(&self, other: &Self) -> bool {
+        use Value::*;
+        match (self, other) {
+            (I64(lhs), I64(rhs)) => lhs == rhs,
+            (F64(_) | I64(_), F64(_) | I64(_)) => self.coerce_f64() == other.coerce_f64(),
+            (Array(lhs), Array(rhs)) => {
+                let (lhs, rhs) = (lhs.borrow(), rhs.borrow());
+                lhs.len() == rhs.len() && lhs.iter().zip(rhs.iter()).all(|(l, r)| l.value_eq(r))
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Ordering of `sort`, consistent with `value_eq` for numbers.
+    ///
+    /// Numbers are ordered by value, with NaN after every other number, and strings
+    /// lexicographically. Values of other or different kinds can't be ordered.
+    pub fn try_cmp(&self, other: &Self) -> Result<Ordering, RuntimeError> {
+        use Value::*;
+        match (self, other) {
+            (I64(lhs), I64(rhs)) => Ok(lhs.cmp(rhs)),
+            (F64(_) | I64(_), F64(_) | I64(_)) => {
+                let (lhs, rhs) = (self.coerce_f64(), other.coerce_f64());
+                Ok(lhs
+                    .partial_cmp(&rhs)
+                    .unwrap_or_else(|| lhs.is_nan().cmp(&rhs.is_nan())))
+            }
+            (Str(lhs), Str(rhs)) => Ok(lhs.cmp(rhs)),
+            _ => {
+                let (lhs, rhs) = (self.type_name(), other.type_name());
+                Err(RuntimeError::Incomparable {
+                    lhs: lhs.min(rhs),
+                    rhs: lhs.max(rhs),
+                })
+            }
+        }
+    }
+}
+
+/// Stable merge sort by a comparison which can fail.
+///
+/// Unlike `slice::sort_by`, an inconsistent comparison, like a user function, only gives an
+/// unspecified order instead of a panic.
+pub(crate) fn merge_sort_by<E>(
+    mut values: Vec<Value>,
+    cmp: &mut impl FnMut(&Value, &Value) -> Result<Ordering, E>,
+) -> Result<Vec<Value>, E> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let mut left = merge_sort_by(values, cmp)?.into_iter().peekable();
+    let mut right = merge_sort_by(right, cmp)?.into_iter().peekable();
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Take from the left unless the right one is strictly less, to keep equal values in order
+        let next = if cmp(r, l)? == Ordering::Less {
+            right.next()
+        } else {
+            left.next()
+        };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// Hashable key of a literal `Value`, used to dedup literals in the compiler.
+///
+/// `f64` is neither `Hash` nor `Eq`, so floats are keyed by their bit pattern.
+/// Every NaN maps to the same key, since they all behave the same. `0.0` and `-0.0` keep
+/// different keys although they are equal, because `1 / -0.0` is `-inf`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LiteralKey {
+    F64(u64),
+    I64(i64),
+    Str(Rc<String>),
+    Func(String),
+    Unit,
+}
+
+impl Value {
+    /// Returns `None` for values which can't be a literal (coroutines, arrays and closures).
+    pub fn literal_key(&self) -> Option<LiteralKey> {
+        Some(match self {
+            Self::F64(value) => {
+                let value = if value.is_nan() { f64::NAN } else { *value };
+                LiteralKey::F64(value.to_bits())
+            }
+            Self::I64(value) => LiteralKey::I64(*value),
+            Self::Str(value) => LiteralKey::Str(value.clone()),
+            Self::Func(name) => LiteralKey::Func(name.clone()),
+            Self::Unit => LiteralKey::Unit,
+            Self::Coro(_) | Self::Array(_) | Self::Closure(_) => return None,
+        })
+    }
+}
+
 impl Default for Value {
     fn default() -> Self {
         Self::F64(0.)
@@ -70,6 +224,19 @@ impl Display for Value {
             Self::I64(value) => write!(f, "{value}"),
             Self::Str(value) => write!(f, "{value}"),
             Self::Coro(_) => write!(f, "<Coroutine>"),
+            Self::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.borrow().iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Func(name) => write!(f, "<fn {name}>"),
+            Self::Closure(closure) => write!(f, "<fn {}>", closure.name),
+            Self::Unit => write!(f, "()"),
         }
     }
 }
@@ -81,6 +248,9 @@ impl Value {
             Self::I64(_) => ValueKind::I64,
             Self::Str(_) => ValueKind::Str,
             Self::Coro(_) => ValueKind::Coro,
+            Self::Array(_) => ValueKind::Array,
+            Self::Func(_) | Self::Closure(_) => ValueKind::Func,
+            Self::Unit => ValueKind::Unit,
         }
     }
 
@@ -91,16 +261,20 @@ impl Value {
             Self::F64(value) => {
                 writer.write_all(&value.to_le_bytes())?;
             }
-            Self::I64(value) => {
-                writer.write_all(&value.to_le_bytes())?;
-            }
+            Self::I64(value) => serialize_varint(*value, writer)?,
             Self::Str(value) => serialize_str(value, writer)?,
-            Self::Coro(_) => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Coroutine can't be serialized",
-                ))
+            Self::Func(value) => serialize_str(value, writer)?,
+            Self::Coro(_) => return Err(std::io::Error::other("Coroutine can't be serialized")),
+            Self::Closure(_) => return Err(std::io::Error::other("Closure can't be serialized")),
+            Self::Array(values) => {
+                let values = values.borrow();
+                serialize_size(values.len(), writer)?;
+                for value in values.iter() {
+                    value.serialize(writer)?;
+                }
             }
+            // The kind is all there is to a unit
+            Self::Unit => {}
         };
         Ok(())
     }
@@ -110,6 +284,9 @@ impl Value {
         const F64: u8 = ValueKind::F64 as u8;
         const I64: u8 = ValueKind::I64 as u8;
         const Str: u8 = ValueKind::Str as u8;
+        const Array: u8 = ValueKind::Array as u8;
+        const Func: u8 = ValueKind::Func as u8;
+        const Unit: u8 = ValueKind::Unit as u8;
 
         let mut kind_buf = [0u8; 1];
         reader.read_exact(&mut kind_buf)?;
@@ -119,44 +296,366 @@ impl Value {
                 reader.read_exact(&mut buf)?;
                 Ok(Value::F64(f64::from_le_bytes(buf)))
             }
-            I64 => {
-                let mut buf = [0u8; std::mem::size_of::<i64>()];
-                reader.read_exact(&mut buf)?;
-                Ok(Value::I64(i64::from_le_bytes(buf)))
+            I64 => Ok(Value::I64(deserialize_varint(reader)?)),
+            Str => Ok(Value::Str(deserialize_str(reader)?.into())),
+            Func => Ok(Value::Func(deserialize_str(reader)?)),
+            Unit => Ok(Value::Unit),
+            Array => {
+                let len = deserialize_size(reader)?;
+                let mut values = Vec::with_capacity(len);
+                for _ in 0..len {
+                    values.push(Value::deserialize(reader)?);
+                }
+                Ok(Value::array(values))
             }
-            Str => Ok(Value::Str(deserialize_str(reader)?)),
-            _ => Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!(
-                    "ValueKind {} does not match to any known kinds",
-                    kind_buf[0]
-                ),
-            )),
+            _ => Err(std::io::Error::other(format!(
+                "ValueKind {} does not match to any known kinds",
+                kind_buf[0]
+            ))),
         }
     }
 
-    pub fn coerce_f64(&self) -> f64 {
+    /// Render the value as JSON.
+    ///
+    /// Numbers and strings map to JSON numbers and strings, and arrays to JSON arrays.
+    /// `f64` values keep a fraction or an exponent, like `1.0`, so `from_json` reads them back
+    /// as `f64`. A unit is `null`, and so are the values JSON can't represent, which are
+    /// non-finite numbers, functions and coroutines.
+    pub fn to_json(&self) -> String {
+        let mut buf = String::new();
+        self.write_json(&mut buf);
+        buf
+    }
+
+    fn write_json(&self, buf: &mut String) {
+        use std::fmt::Write;
+        match self {
+            Self::F64(value) if value.is_finite() => write!(buf, "{value:?}").unwrap(),
+            Self::I64(value) => write!(buf, "{value}").unwrap(),
+            Self::Str(value) => {
+                buf.push('"');
+                for c in value.chars() {
+                    match c {
+                        '"' => buf.push_str("\\\""),
+                        '\\' => buf.push_str("\\\\"),
+                        '\n' => buf.push_str("\\n"),
+                        '\r' => buf.push_str("\\r"),
+                        '\t' => buf.push_str("\\t"),
+                        c if c < ' ' => write!(buf, "\\u{:04x}", c as u32).unwrap(),
+                        c => buf.push(c),
+                    }
+                }
+                buf.push('"');
+            }
+            Self::Array(values) => {
+                buf.push('[');
+                for (i, value) in values.borrow().iter().enumerate() {
+                    if i != 0 {
+                        buf.push(',');
+                    }
+                    value.write_json(buf);
+                }
+                buf.push(']');
+            }
+            Self::F64(_) | Self::Coro(_) | Self::Func(_) | Self::Closure(_) | Self::Unit => {
+                buf.push_str("null")
+            }
+        }
+    }
+
+    /// Build a value from JSON.
+    ///
+    /// Numbers with a fraction or an exponent are `f64`, and the others are `i64` unless they
+    /// are out of its range. `true` and `false` are `I64(1)` and `I64(0)`, like the results of
+    /// comparisons. `null` and objects have no value to map to, so they are errors.
+    pub fn from_json(src: &str) -> Result<Self, String> {
+        let mut reader = JsonReader { src, pos: 0 };
+        let value = reader.value(0)?;
+        reader.skip_spaces();
+        if reader.pos < src.len() {
+            return Err(reader.error("end of input"));
+        }
+        Ok(value)
+    }
+
+    /// Name of the runtime type, spelled as in type declarations
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::F64(_) => "f64",
+            Self::I64(_) => "i64",
+            Self::Str(_) => "str",
+            Self::Coro(_) => "cofn",
+            Self::Array(_) => "Array",
+            Self::Func(_) | Self::Closure(_) => "fn",
+            Self::Unit => "unit",
+        }
+    }
+
+    pub fn str(value: &str) -> Self {
+        Self::Str(Rc::new(value.to_string()))
+    }
+
+    pub fn array(values: Vec<Value>) -> Self {
+        Self::Array(Rc::new(RefCell::new(values)))
+    }
+
+    /// Convert a number to the numeric kind as a declared type would, or format a number as a
+    /// string like `str` does. `None` for the other values and kinds.
+    pub fn cast(&self, kind: ValueKind) -> Option<Value> {
+        Some(match (self, kind) {
+            (Self::F64(_) | Self::I64(_), ValueKind::F64) => Self::F64(self.coerce_f64()),
+            (Self::F64(_) | Self::I64(_), ValueKind::I64) => Self::I64(self.coerce_i64()),
+            (Self::F64(_) | Self::I64(_) | Self::Str(_), ValueKind::Str) => {
+                Self::Str(self.try_coerce_str().ok()?)
+            }
+            _ => return None,
+        })
+    }
+
+    /// Whether the value can be serialized, which is false for coroutines and closures.
+    pub fn is_persistable(&self) -> bool {
         match self {
-            Self::F64(value) => *value,
-            Self::I64(value) => *value as f64,
-            _ => panic!("Coercion failed: {:?} cannot be coerced to f64", self),
+            Self::Coro(_) | Self::Closure(_) => false,
+            Self::Array(values) => values.borrow().iter().all(Self::is_persistable),
+            _ => true,
         }
     }
 
+    /// Whether the value is the array or has it among its elements, at any depth.
+    ///
+    /// `push` checks this so that an array never contains itself, which the recursive walks
+    /// like `Display` and `PartialEq` rely on.
+    pub(crate) fn contains_array(&self, array: &Rc<RefCell<Vec<Value>>>) -> bool {
+        match self {
+            Self::Array(values) => {
+                Rc::ptr_eq(values, array)
+                    || values
+                        .borrow()
+                        .iter()
+                        .any(|value| value.contains_array(array))
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the value counts as true in conditions and logical operators.
+    ///
+    /// Numbers are true unless they are zero, strings and arrays are true unless they are empty,
+    /// coroutines and functions are always true, and a unit is always false.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Self::F64(value) => *value != 0.,
+            Self::I64(value) => *value != 0,
+            Self::Str(value) => !value.is_empty(),
+            Self::Coro(_) | Self::Func(_) | Self::Closure(_) => true,
+            Self::Array(values) => !values.borrow().is_empty(),
+            Self::Unit => false,
+        }
+    }
+
+    pub fn coerce_f64(&self) -> f64 {
+        self.try_coerce_f64()
+            .unwrap_or_else(|_| panic!("Coercion failed: {:?} cannot be coerced to f64", self))
+    }
+
     pub fn coerce_i64(&self) -> i64 {
+        self.try_coerce_i64()
+            .unwrap_or_else(|_| panic!("Coercion failed: {:?} cannot be coerced to i64", self))
+    }
+
+    pub fn coerce_str(&self) -> Rc<String> {
+        self.try_coerce_str()
+            .unwrap_or_else(|_| panic!("Coercion failed: {:?} cannot be coerced to str", self))
+    }
+
+    /// Like `coerce_f64`, but returns a `RuntimeError` for natives to raise instead of panicking.
+    pub fn try_coerce_f64(&self) -> Result<f64, RuntimeError> {
+        match self {
+            Self::F64(value) => Ok(*value),
+            Self::I64(value) => Ok(*value as f64),
+            _ => Err(self.coercion_error("f64")),
+        }
+    }
+
+    pub fn try_coerce_i64(&self) -> Result<i64, RuntimeError> {
         match self {
-            Self::F64(value) => *value as i64,
-            Self::I64(value) => *value,
-            _ => panic!("Coercion failed: {:?} cannot be coerced to i64", self),
+            Self::F64(value) => Ok(*value as i64),
+            Self::I64(value) => Ok(*value),
+            _ => Err(self.coercion_error("i64")),
         }
     }
 
-    pub fn coerce_str(&self) -> String {
+    /// A string value is shared instead of copied, and a number is formatted.
+    pub fn try_coerce_str(&self) -> Result<Rc<String>, RuntimeError> {
         match self {
-            Self::F64(value) => format!("{value}"),
-            Self::I64(value) => format!("{value}"),
-            Self::Str(value) => value.clone(),
-            _ => panic!("Coercion failed: {:?} cannot be coerced to str", self),
+            Self::F64(value) => Ok(format!("{value}").into()),
+            Self::I64(value) => Ok(format!("{value}").into()),
+            Self::Str(value) => Ok(value.clone()),
+            _ => Err(self.coercion_error("str")),
+        }
+    }
+
+    pub(crate) fn coercion_error(&self, expected: &'static str) -> RuntimeError {
+        RuntimeError::TypeError {
+            expected,
+            got: self.type_name(),
+        }
+    }
+}
+
+/// Maximum nesting of arrays `Value::from_json` reads, to keep the recursion off a stack overflow
+const MAX_JSON_DEPTH: usize = 512;
+
+struct JsonReader<'a> {
+    src: &'a str,
+    /// Byte offset of the next character
+    pos: usize,
+}
+
+impl JsonReader<'_> {
+    fn error(&self, expected: &str) -> String {
+        match self.src[self.pos..].chars().next() {
+            Some(c) => format!("Expected {expected} but found {c:?} at byte {}", self.pos),
+            None => format!("Expected {expected} but found the end of input"),
         }
     }
+
+    fn skip_spaces(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.src[self.pos..].starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        self.skip_spaces();
+        if self.eat("true") {
+            return Ok(Value::I64(1));
+        }
+        if self.eat("false") {
+            return Ok(Value::I64(0));
+        }
+        match self.src[self.pos..].chars().next() {
+            Some('"') => Ok(Value::Str(self.string()?.into())),
+            Some('[') => self.array(depth),
+            Some('-' | '0'..='9') => self.number(),
+            _ => Err(self.error("a number, a string, an array, true or false")),
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, String> {
+        if MAX_JSON_DEPTH <= depth {
+            return Err(format!(
+                "Arrays are nested deeper than {MAX_JSON_DEPTH} at byte {}",
+                self.pos
+            ));
+        }
+        self.pos += 1;
+        let mut values = vec![];
+        self.skip_spaces();
+        if self.eat("]") {
+            return Ok(Value::array(values));
+        }
+        loop {
+            values.push(self.value(depth + 1)?);
+            self.skip_spaces();
+            if self.eat("]") {
+                return Ok(Value::array(values));
+            }
+            if !self.eat(",") {
+                return Err(self.error("',' or ']'"));
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        let rest = &self.src[start..];
+        let len = rest
+            .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+            .unwrap_or(rest.len());
+        let text = &rest[..len];
+        self.pos += len;
+        let invalid = || format!("Invalid number {text:?} at byte {start}");
+        if text.contains(['.', 'e', 'E']) {
+            return text.parse().map(Value::F64).map_err(|_| invalid());
+        }
+        match text.parse() {
+            Ok(value) => Ok(Value::I64(value)),
+            Err(_) => text.parse().map(Value::F64).map_err(|_| invalid()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut buf = String::new();
+        loop {
+            let Some(c) = self.src[self.pos..].chars().next() else {
+                return Err(self.error("'\"'"));
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(buf),
+                '\\' => {
+                    let Some(escaped) = self.src[self.pos..].chars().next() else {
+                        return Err(self.error("an escape sequence"));
+                    };
+                    self.pos += escaped.len_utf8();
+                    buf.push(match escaped {
+                        '"' | '\\' | '/' => escaped,
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => self.unicode_escape()?,
+                        _ => {
+                            self.pos -= escaped.len_utf8();
+                            return Err(self.error("an escape sequence"));
+                        }
+                    });
+                }
+                c => buf.push(c),
+            }
+        }
+    }
+
+    /// Read the hex digits of a `\u` escape, and the low surrogate following a high one.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.eat("\\u") {
+                return Err(self.error("a low surrogate"));
+            }
+            let low = self.hex4()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(format!(
+                    "Invalid low surrogate {low:04x} at byte {}",
+                    self.pos
+                ));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code)
+            .ok_or_else(|| format!("Invalid code point {code:04x} at byte {}", self.pos))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.src.get(self.pos..self.pos + 4).unwrap_or_default();
+        let code = u32::from_str_radix(digits, 16)
+            .ok()
+            .filter(|_| digits.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("4 hex digits"))?;
+        self.pos += 4;
+        Ok(code)
+    }
 }